@@ -12,8 +12,22 @@ use crate::gl;
 use crate::modules::{tas_optimizer, tas_studio};
 use crate::utils::*;
 
+pub static SDL_GameControllerClose: Pointer<unsafe extern "C" fn(*mut c_void)> =
+    Pointer::empty(b"SDL_GameControllerClose\0");
+pub static SDL_GameControllerGetAxis: Pointer<unsafe extern "C" fn(*mut c_void, c_int) -> i16> =
+    Pointer::empty(b"SDL_GameControllerGetAxis\0");
+pub static SDL_GameControllerOpen: Pointer<unsafe extern "C" fn(c_int) -> *mut c_void> =
+    Pointer::empty(b"SDL_GameControllerOpen\0");
+pub static SDL_GetKeyboardFocus: Pointer<unsafe extern "C" fn() -> *mut c_void> =
+    Pointer::empty(b"SDL_GetKeyboardFocus\0");
 pub static SDL_GetMouseState: Pointer<unsafe extern "C" fn(*mut c_int, *mut c_int) -> c_uint> =
     Pointer::empty(b"SDL_GetMouseState\0");
+pub static SDL_IsGameController: Pointer<unsafe extern "C" fn(c_int) -> c_int> =
+    Pointer::empty(b"SDL_IsGameController\0");
+pub static SDL_NumJoysticks: Pointer<unsafe extern "C" fn() -> c_int> =
+    Pointer::empty(b"SDL_NumJoysticks\0");
+pub static SDL_ShowCursor: Pointer<unsafe extern "C" fn(c_int) -> c_int> =
+    Pointer::empty(b"SDL_ShowCursor\0");
 pub static SDL_GL_ExtensionSupported: Pointer<unsafe extern "C" fn(*const c_char) -> c_int> =
     Pointer::empty(b"SDL_GL_ExtensionSupported\0");
 pub static SDL_GL_GetProcAddress: Pointer<unsafe extern "C" fn(*const c_char) -> *const c_void> =
@@ -34,7 +48,14 @@ pub static SDL_WaitEventTimeout: Pointer<unsafe extern "C" fn(*mut c_void, c_int
     );
 
 static POINTERS: &[&dyn PointerTrait] = &[
+    &SDL_GameControllerClose,
+    &SDL_GameControllerGetAxis,
+    &SDL_GameControllerOpen,
+    &SDL_GetKeyboardFocus,
     &SDL_GetMouseState,
+    &SDL_IsGameController,
+    &SDL_NumJoysticks,
+    &SDL_ShowCursor,
     &SDL_GL_ExtensionSupported,
     &SDL_GL_GetProcAddress,
     &SDL_SetRelativeMouseMode,
@@ -233,6 +254,56 @@ pub fn set_relative_mouse_mode(marker: MainThreadMarker, value: bool) {
     unsafe { SDL_SetRelativeMouseMode.get(marker)(value.into()) };
 }
 
+/// Returns whether the game window has keyboard focus.
+///
+/// Returns [`None`] if `SDL_GetKeyboardFocus` was not found.
+pub fn has_keyboard_focus(marker: MainThreadMarker) -> Option<bool> {
+    let get_keyboard_focus = SDL_GetKeyboardFocus.get_opt(marker)?;
+    // SAFETY: we set SDL_GetKeyboardFocus when it is safe to call and unset before it's unsafe.
+    Some(unsafe { !get_keyboard_focus().is_null() })
+}
+
+/// Returns whether the mouse cursor is shown.
+///
+/// Returns [`None`] if `SDL_ShowCursor` was not found.
+pub fn is_cursor_shown(marker: MainThreadMarker) -> Option<bool> {
+    /// `SDL_QUERY`, which makes `SDL_ShowCursor` return the current state without changing it.
+    const QUERY: c_int = -1;
+
+    let show_cursor = SDL_ShowCursor.get_opt(marker)?;
+    // SAFETY: we set SDL_ShowCursor when it is safe to call and unset before it's unsafe.
+    Some(unsafe { show_cursor(QUERY) } == 1)
+}
+
+/// Returns the stick positions of the first connected game controller.
+///
+/// The axes are the left stick X and Y followed by the right stick X and Y, all in the `[-1; 1]`
+/// range with positive values pointing right and down. Returns [`None`] if there's no game
+/// controller or the SDL game controller functions were not found.
+pub fn game_controller_sticks(marker: MainThreadMarker) -> Option<[f32; 4]> {
+    let num_joysticks = SDL_NumJoysticks.get_opt(marker)?;
+    let is_game_controller = SDL_IsGameController.get_opt(marker)?;
+    let open = SDL_GameControllerOpen.get_opt(marker)?;
+    let get_axis = SDL_GameControllerGetAxis.get_opt(marker)?;
+    let close = SDL_GameControllerClose.get_opt(marker)?;
+
+    // SAFETY: we set the SDL functions when they are safe to call and unset them before they are
+    // unsafe. Opening an already opened controller only increments its reference count, which
+    // closing it decrements again.
+    unsafe {
+        let index = (0..num_joysticks()).find(|&index| is_game_controller(index) != 0)?;
+        let controller = open(index);
+        if controller.is_null() {
+            return None;
+        }
+
+        // SDL_CONTROLLER_AXIS_LEFTX, LEFTY, RIGHTX and RIGHTY.
+        let sticks = [0, 1, 2, 3].map(|axis| get_axis(controller, axis) as f32 / i16::MAX as f32);
+        close(controller);
+        Some(sticks)
+    }
+}
+
 use exported::*;
 
 /// Functions exported for `LD_PRELOAD` hooking.
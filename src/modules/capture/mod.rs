@@ -1,13 +1,17 @@
 //! Video capture.
 
 use std::mem;
+use std::path::{Path, PathBuf};
 
-use color_eyre::eyre::Context;
+use color_eyre::eyre::{self, eyre, Context};
+use hltas::HLTAS;
 
 use super::cvars::CVar;
 use super::{capture_video_per_demo, Module};
 use crate::hooks::engine::{self, con_print};
+use crate::hooks::sdl;
 use crate::modules::commands::Command;
+use crate::modules::tas_studio::editor::utils::frame_hud_lines;
 use crate::utils::*;
 use crate::{gl, handler};
 
@@ -22,7 +26,13 @@ impl Module for Capture {
     }
 
     fn commands(&self) -> &'static [&'static Command] {
-        static COMMANDS: &[&Command] = &[&BXT_CAP_START, &BXT_CAP_STOP];
+        static COMMANDS: &[&Command] = &[
+            &BXT_CAP_START,
+            &BXT_CAP_STOP,
+            &BXT_CAP_PAUSE,
+            &BXT_CAP_RESUME,
+            &BXT_CAP_SELF_TEST,
+        ];
         COMMANDS
     }
 
@@ -36,6 +46,49 @@ impl Module for Capture {
             &BXT_CAP_FORCE_FALLBACK,
             &BXT_CAP_OVERRIDE_FFMPEG_ARGS,
             &BXT_CAP_SAMPLING_MIN_FPS,
+            &BXT_CAP_VIDEO_CODEC,
+            &BXT_CAP_ENCODER,
+            &BXT_CAP_NVENC_PRESET,
+            &BXT_CAP_NVENC_BITRATE,
+            &BXT_CAP_AUDIO_ONLY,
+            &BXT_CAP_SOUND,
+            &BXT_CAP_SCENE_THRESHOLD,
+            &BXT_CAP_LOSSLESS,
+            &BXT_CAP_VULKAN_DEVICE,
+            &BXT_CAP_MAX_SAMPLES_PER_CALL,
+            &BXT_CAP_FFMPEG_PATH,
+            &BXT_CAP_QUEUE_SIZE,
+            &BXT_CAP_DROP_FRAMES_WHEN_BEHIND,
+            &BXT_CAP_EXTRA_FFMPEG_ARGS,
+            &BXT_CAP_SEGMENT_SECONDS,
+            &BXT_CAP_ADAPTIVE_RESOLUTION,
+            &BXT_CAP_TEE_TARGETS,
+            &BXT_CAP_OVERSAMPLE,
+            &BXT_CAP_SHUTTER_ANGLE,
+            &BXT_CAP_DITHER,
+            &BXT_CAP_FILM_24FPS,
+            &BXT_CAP_LOW_LATENCY,
+            &BXT_CAP_OFFLINE,
+            &BXT_CAP_SEPARATE_AUDIO_THREAD,
+            &BXT_CAP_PAUSE_ON_FOCUS_LOSS,
+            &BXT_CAP_CURSOR,
+            &BXT_CAP_GAMEPAD,
+            &BXT_CAP_HUD_FILE,
+            &BXT_CAP_BURN_TIMECODE,
+            &BXT_CAP_CAPTIONS,
+            &BXT_CAP_LOUDNORM,
+            &BXT_CAP_FRAGMENTED,
+            &BXT_CAP_RAW_PLANES,
+            &BXT_CAP_LOSSLESS_PREVIEW,
+            &BXT_CAP_PROXY_FPS,
+            &BXT_CAP_PROXY_SCALE,
+            &BXT_CAP_TARGET_SIZE_MB,
+            &BXT_CAP_FADE_IN,
+            &BXT_CAP_FADE_OUT,
+            &BXT_CAP_CHECKSUMS,
+            &BXT_CAP_EDL,
+            &BXT_CAP_GIF_PREVIEW,
+            &BXT_CAP_COMPARE_TO,
         ];
         CVARS
     }
@@ -59,9 +112,17 @@ impl Module for Capture {
 
 mod muxer;
 mod opengl;
+mod options;
+mod passes;
 mod recorder;
+mod sinks;
+use muxer::{Encoder, TeeTarget, VideoCodec};
+use options::{
+    GifConfig, LoudnessTarget, MotionBlur, OutputTarget, ProxyConfig, QueuePolicy, RecorderOptions,
+};
 use recorder::{CaptureType, Recorder};
 mod vulkan;
+use vulkan::DeviceSelector;
 
 #[cfg(unix)]
 pub type ExternalObject = std::os::unix::io::RawFd;
@@ -71,7 +132,8 @@ pub type ExternalObject = *mut std::os::raw::c_void;
 static BXT_CAP_FPS: CVar = CVar::new(
     b"bxt_cap_fps\0",
     b"60\0",
-    "Frames-per-second of the recorded video.",
+    "Frames-per-second of the recorded video. Can be a fraction such as `30000/1001` for NTSC \
+    frame rates.",
 );
 static BXT_CAP_SOUND_EXTRA: CVar = CVar::new(
     b"bxt_cap_sound_extra\0",
@@ -128,6 +190,238 @@ static BXT_CAP_FORCE_FALLBACK: CVar = CVar::new(
     "Set to `1` to force the use of simple OpenGL capturing instead of the fast \
     GPU-accelerated Vulkan capturing. Try this if you get artifacts on the recorded video.",
 );
+static BXT_CAP_AUDIO_ONLY: CVar = CVar::new(
+    b"bxt_cap_audio_only\0",
+    b"0\0",
+    "Set to `1` to record only the game audio, without capturing any video. The format is picked \
+    from the file extension of the output, for example `.wav`, `.flac` or `.opus`.",
+);
+static BXT_CAP_SOUND: CVar = CVar::new(
+    b"bxt_cap_sound\0",
+    b"1\0",
+    "Set to `0` to record the video without an audio track, for example if you add your own audio \
+    later.",
+);
+static BXT_CAP_SCENE_THRESHOLD: CVar = CVar::new(
+    b"bxt_cap_scene_threshold\0",
+    b"0\0",
+    "Set to a value between `0` and `1` to start a new chapter whenever consecutive video frames \
+    differ by more than this fraction. The chapters are written next to the video into \
+    `<filename>.chapters.txt`. Set to `0` to disable.",
+);
+static BXT_CAP_LOSSLESS: CVar = CVar::new(
+    b"bxt_cap_lossless\0",
+    b"0\0",
+    "Set to `1` to record losslessly with FFV1 for archival or color-critical work. The output \
+    file must end with `.mkv`. Lossless videos are very large.",
+);
+static BXT_CAP_VULKAN_DEVICE: CVar = CVar::new(
+    b"bxt_cap_vulkan_device\0",
+    b"\0",
+    "Index or part of the name of the GPU to use for the fast Vulkan capturing, for example `1` or \
+    `nvidia`. It must be the GPU the game runs on. Leave empty to pick it automatically.",
+);
+static BXT_CAP_MAX_SAMPLES_PER_CALL: CVar = CVar::new(
+    b"bxt_cap_max_samples_per_call\0",
+    b"0\0",
+    "Maximum number of audio samples to record at once. When a lot of time passes at once, for \
+    example during a lag spike, the rest of the audio is spread across the following frames. Set \
+    to `0` for no limit.",
+);
+static BXT_CAP_FFMPEG_PATH: CVar = CVar::new(
+    b"bxt_cap_ffmpeg_path\0",
+    b"\0",
+    "Path to the FFmpeg executable to record with. Leave empty to use `ffmpeg.exe` in the \
+    Half-Life folder on Windows and `ffmpeg` from `PATH` on Linux.",
+);
+static BXT_CAP_QUEUE_SIZE: CVar = CVar::new(
+    b"bxt_cap_queue_size\0",
+    b"0\0",
+    "Number of video frames and audio chunks that can wait for the encoder before the game waits \
+    for it. Set to `0` for the default of `64`.",
+);
+static BXT_CAP_DROP_FRAMES_WHEN_BEHIND: CVar = CVar::new(
+    b"bxt_cap_drop_frames_when_behind\0",
+    b"0\0",
+    "Set to `1` to drop video frames instead of making the game wait when the encoder falls \
+    behind, for real-time recording. Only works without `bxt_cap_sampling_exposure` and when \
+    capturing with `glReadPixels`.",
+);
+static BXT_CAP_SEGMENT_SECONDS: CVar = CVar::new(
+    b"bxt_cap_segment_seconds\0",
+    b"0\0",
+    "Set to split the recording into files this many seconds long, named like `name_0001.mp4`, \
+    `name_0002.mp4` and so on. Set to `0` to record into a single file.",
+);
+static BXT_CAP_ADAPTIVE_RESOLUTION: CVar = CVar::new(
+    b"bxt_cap_adaptive_resolution\0",
+    b"0\0",
+    "Set to `1` to record the next segment at a lower resolution, down to half, when the encoding \
+    can't keep up, and to go back up once it catches up. Needs `bxt_cap_segment_seconds`.",
+);
+static BXT_CAP_TEE_TARGETS: CVar = CVar::new(
+    b"bxt_cap_tee_targets\0",
+    b"\0",
+    "Additional outputs to write the recording into at the same time, such as a live stream, \
+    separated by spaces. Every output is given as the FFmpeg format and the path or URL separated \
+    by a colon, for example `flv:rtmp://localhost/live/stream`. The video is only encoded once.",
+);
+static BXT_CAP_OVERSAMPLE: CVar = CVar::new(
+    b"bxt_cap_oversample\0",
+    b"0\0",
+    "Number of game frames to blend into every video frame for motion blur. The game runs this \
+    many times faster than `bxt_cap_fps`. Set to `0` or `1` to disable. This overrides \
+    `bxt_cap_sampling_exposure` and `_bxt_cap_sampling_min_fps`.",
+);
+static BXT_CAP_SHUTTER_ANGLE: CVar = CVar::new(
+    b"bxt_cap_shutter_angle\0",
+    b"0.5\0",
+    "Fraction of every video frame, from `0` to `1`, whose game frames are blended together when \
+    `bxt_cap_oversample` is set. `1` blends all of them, `0.5` only the second half.",
+);
+static BXT_CAP_DITHER: CVar = CVar::new(
+    b"bxt_cap_dither\0",
+    b"0\0",
+    "Set to `1` to dither the frames blended with `bxt_cap_sampling_exposure` or \
+    `bxt_cap_oversample`, which reduces banding in smooth gradients such as skyboxes. Only works \
+    when capturing with `glReadPixels`.",
+);
+static BXT_CAP_FILM_24FPS: CVar = CVar::new(
+    b"bxt_cap_film_24fps\0",
+    b"0\0",
+    "Set to `1` to record at the film frame rate of 23.976 FPS instead of `bxt_cap_fps`.",
+);
+static BXT_CAP_LOW_LATENCY: CVar = CVar::new(
+    b"bxt_cap_low_latency\0",
+    b"0\0",
+    "Set to `1` to record at most one video frame per game frame and carry the rest over to the \
+    next game frames, which keeps the video closer to real time at the cost of smoothness. Doesn't \
+    work with sampling.",
+);
+static BXT_CAP_OFFLINE: CVar = CVar::new(
+    b"bxt_cap_offline\0",
+    b"0\0",
+    "Set to `1` to never drop or delay video frames, making the game wait for the encoder instead. \
+    This overrides `bxt_cap_low_latency` and `bxt_cap_drop_frames_when_behind`.",
+);
+static BXT_CAP_SEPARATE_AUDIO_THREAD: CVar = CVar::new(
+    b"bxt_cap_separate_audio_thread\0",
+    b"0\0",
+    "Set to `1` to write the audio from its own thread, so it doesn't wait for the video \
+    conversion.",
+);
+static BXT_CAP_PAUSE_ON_FOCUS_LOSS: CVar = CVar::new(
+    b"bxt_cap_pause_on_focus_loss\0",
+    b"0\0",
+    "Set to `1` to pause the recording while the game window is out of focus.",
+);
+static BXT_CAP_CURSOR: CVar = CVar::new(
+    b"bxt_cap_cursor\0",
+    b"0\0",
+    "Set to `1` to draw the mouse cursor into the video while it's shown, for example in menus.",
+);
+static BXT_CAP_GAMEPAD: CVar = CVar::new(
+    b"bxt_cap_gamepad\0",
+    b"0\0",
+    "Set to `1` to draw the sticks of the first connected gamepad in the bottom-left corner of the \
+    video.",
+);
+static BXT_CAP_HUD_FILE: CVar = CVar::new(
+    b"bxt_cap_hud_file\0",
+    b"\0",
+    "Text file with the HUD text to draw on top of the video, one line per video frame. An \
+    `.hltas` script shows the frame index, time and strafe type of every frame instead. Leave \
+    empty to disable.",
+);
+static BXT_CAP_BURN_TIMECODE: CVar = CVar::new(
+    b"bxt_cap_burn_timecode\0",
+    b"0\0",
+    "Set to `1` to draw a running timecode in the top-left corner of the video.",
+);
+static BXT_CAP_CAPTIONS: CVar = CVar::new(
+    b"bxt_cap_captions\0",
+    b"\0",
+    "SRT or WebVTT file to add to the video as a caption track. Leave empty to disable.",
+);
+static BXT_CAP_LOUDNORM: CVar = CVar::new(
+    b"bxt_cap_loudnorm\0",
+    b"0\0",
+    "Integrated loudness in LUFS to normalize the audio to, for example `-14`. Set to `0` to \
+    disable.",
+);
+static BXT_CAP_FRAGMENTED: CVar = CVar::new(
+    b"bxt_cap_fragmented\0",
+    b"0\0",
+    "Set to `1` to write a fragmented MP4 which can be uploaded while it's being recorded. Only \
+    works with `.mp4` and `.mov` files.",
+);
+static BXT_CAP_RAW_PLANES: CVar = CVar::new(
+    b"bxt_cap_raw_planes\0",
+    b"0\0",
+    "Set to `1` to write the unprocessed R, G and B planes of every frame into `<filename>.r`, \
+    `<filename>.g` and `<filename>.b` instead of encoding a video. The audio is not recorded.",
+);
+static BXT_CAP_LOSSLESS_PREVIEW: CVar = CVar::new(
+    b"bxt_cap_lossless_preview\0",
+    b"0\0",
+    "Set to `1` to record a lossless master into the output file along with a compressed preview \
+    in `<filename>.preview.mp4`.",
+);
+static BXT_CAP_PROXY_FPS: CVar = CVar::new(
+    b"bxt_cap_proxy_fps\0",
+    b"0\0",
+    "Set to record a low-resolution proxy at this frame rate into `<filename>.proxy.mp4` for \
+    scrubbing in editors. Set to `0` to disable.",
+);
+static BXT_CAP_PROXY_SCALE: CVar = CVar::new(
+    b"bxt_cap_proxy_scale\0",
+    b"0.25\0",
+    "Resolution of the proxy from `bxt_cap_proxy_fps` relative to the recording, from `0` to `1`.",
+);
+static BXT_CAP_TARGET_SIZE_MB: CVar = CVar::new(
+    b"bxt_cap_target_size_mb\0",
+    b"0\0",
+    "Set to re-encode the recording in two passes to fit into this many megabytes once it's \
+    finished. The game is recorded losslessly into an intermediate file first. Set to `0` to \
+    disable.",
+);
+static BXT_CAP_FADE_IN: CVar = CVar::new(
+    b"bxt_cap_fade_in\0",
+    b"0\0",
+    "Duration in seconds of the fade from black at the start of the recording. Like with \
+    `bxt_cap_target_size_mb`, the recording is re-encoded once it's finished.",
+);
+static BXT_CAP_FADE_OUT: CVar = CVar::new(
+    b"bxt_cap_fade_out\0",
+    b"0\0",
+    "Duration in seconds of the fade to black at the end of the recording. Like with \
+    `bxt_cap_target_size_mb`, the recording is re-encoded once it's finished.",
+);
+static BXT_CAP_CHECKSUMS: CVar = CVar::new(
+    b"bxt_cap_checksums\0",
+    b"0\0",
+    "Set to `1` to write the SHA-256 checksum of the output file into `<filename>.sha256`. With \
+    `bxt_cap_segment_seconds`, every segment gets its own checksum.",
+);
+static BXT_CAP_EDL: CVar = CVar::new(
+    b"bxt_cap_edl\0",
+    b"0\0",
+    "Set to `1` to write an edit decision list for video editors into `<filename>.edl`.",
+);
+static BXT_CAP_GIF_PREVIEW: CVar = CVar::new(
+    b"bxt_cap_gif_preview\0",
+    b"\0",
+    "Set to make a GIF preview of the recording into `<filename>.gif` once it's finished. The \
+    value is the start and the duration in seconds, the width and the frame rate separated by \
+    spaces, for example `10 5 320 15`. Leave empty to disable.",
+);
+static BXT_CAP_COMPARE_TO: CVar = CVar::new(
+    b"bxt_cap_compare_to\0",
+    b"\0",
+    "Reference video to compare the recording against once it's finished. The SSIM and the PSNR of \
+    every frame are written into `<filename>.ssim.log` and `<filename>.psnr.log`. Leave empty to \
+    disable.",
+);
 static BXT_CAP_OVERRIDE_FFMPEG_ARGS: CVar = CVar::new(
     b"_bxt_cap_override_ffmpeg_args\0",
     b"\0",
@@ -139,6 +433,51 @@ adds automatically when this variable is unset: `-c:v libx264 -crf 15 -preset ul
 -color_primaries bt709 -color_trc bt709 -colorspace bt709 -color_range tv \
 -chroma_sample_location center`.",
 );
+static BXT_CAP_EXTRA_FFMPEG_ARGS: CVar = CVar::new(
+    b"bxt_cap_extra_ffmpeg_args\0",
+    b"\0",
+    "Extra arguments to pass to FFmpeg right before the output file, for example \
+    `-vf eq=contrast=1.1`. Unlike `_bxt_cap_override_ffmpeg_args`, these are added on top of the \
+    default arguments. They can't contain `-f` when recording with `bxt_cap_tee_targets` or with \
+    an encoder that needs its own container, such as ProRes.",
+);
+static BXT_CAP_VIDEO_CODEC: CVar = CVar::new(
+    b"bxt_cap_video_codec\0",
+    b"h264\0",
+    "\
+Video codec to encode the recording with: `h264`, `h265`, `vp9` or `ffv1`.
+
+`ffv1` is lossless and needs a container that supports it, such as `.mkv`. This variable has no \
+effect when `_bxt_cap_override_ffmpeg_args` is set.",
+);
+static BXT_CAP_ENCODER: CVar = CVar::new(
+    b"bxt_cap_encoder\0",
+    b"x264\0",
+    "\
+Encoder to use for the `h264` and `h265` video codecs: `x264` for software encoding, or `nvenc`, \
+`vaapi` or `qsv` for hardware encoding.
+
+`vaapi` is only available on Linux and uses `/dev/dri/renderD128` by default. Use a different \
+device by adding its path after a colon, for example `vaapi:/dev/dri/renderD129`.
+
+`prores` and `hap` encode with the ProRes and HAP intermediate codecs for editing software \
+instead, and need a `.mov` output file. The ProRes profile can be added after a colon, from \
+`prores:0` (Proxy) to `prores:5` (4444 XQ), and is `3` (HQ) by default.
+
+If the hardware encoder isn't available, the recording falls back to software encoding. This \
+variable has no effect when `_bxt_cap_override_ffmpeg_args` is set.",
+);
+static BXT_CAP_NVENC_PRESET: CVar = CVar::new(
+    b"bxt_cap_nvenc_preset\0",
+    b"1\0",
+    "NVENC preset when `bxt_cap_encoder` is `nvenc`, from `1` (fastest) to `7` (best quality).",
+);
+static BXT_CAP_NVENC_BITRATE: CVar = CVar::new(
+    b"bxt_cap_nvenc_bitrate\0",
+    b"0\0",
+    "Target bitrate in kbit/s when `bxt_cap_encoder` is `nvenc`. Set to `0` to encode with \
+    constant quality instead.",
+);
 
 static HAVE_REQUIRED_GL_EXTENSIONS: MainThreadCell<bool> = MainThreadCell::new(false);
 
@@ -215,7 +554,13 @@ static BXT_CAP_START: Command = Command::new(
 
 Starts capturing video. The default filename is `output.mp4`.
 
-If the filename ends with `.wav`, captures only the sound.",
+If the filename ends with `.wav`, captures only the sound. With `bxt_cap_audio_only` set to `1`, \
+the filename can also end with `.flac` or `.opus`, but not with `.png` or `.tga`.
+
+If `bxt_cap_lossless` is `1`, the filename must end with `.mkv`.
+
+If the filename ends with `.png` or `.tga`, writes every frame into a numbered image instead, in \
+the folder named like the file without the extension. The sound goes into `audio.wav` there.",
         cap_start as fn(_),
         cap_start_with_filename as fn(_, _)
     ),
@@ -230,11 +575,24 @@ pub fn cap_start_with_filename(marker: MainThreadMarker, filename: String) {
         return;
     }
 
-    if !filename.ends_with(".mp4") && !filename.ends_with(".wav") {
-        con_print(
-            marker,
-            "Error: the filename must end with \".mp4\" or \".wav\".\n",
-        );
+    let (extensions, error): (&[&str], _) = if BXT_CAP_AUDIO_ONLY.as_bool(marker) {
+        (
+            &[".mp4", ".mkv", ".wav", ".flac", ".opus"],
+            "Error: with bxt_cap_audio_only, the filename must end with \".mp4\", \".mkv\", \
+            \".wav\", \".flac\" or \".opus\".\n",
+        )
+    } else {
+        (
+            &[".mp4", ".mkv", ".wav", ".png", ".tga"],
+            "Error: the filename must end with \".mp4\", \".mkv\", \".wav\", \".png\" or \
+            \".tga\".\n",
+        )
+    };
+    if !extensions
+        .iter()
+        .any(|extension| filename.ends_with(extension))
+    {
+        con_print(marker, error);
         return;
     }
 
@@ -276,13 +634,74 @@ pub fn cap_stop(marker: MainThreadMarker) {
     let old_state = mem::replace(&mut *STATE.borrow_mut(marker), State::Idle);
     let stopped = !matches!(old_state, State::Idle);
     if let State::Recording(recorder) = old_state {
-        if let Some(ffmpeg_output) = recorder.finish() {
+        let paused_time = recorder.paused_time();
+        let full_size = (
+            recorder.width() + recorder.padded().0,
+            recorder.height() + recorder.padded().1,
+        );
+        let output_size = (recorder.output_width(), recorder.output_height());
+        let report = recorder.finish_with_report();
+        if let Some(ffmpeg_output) = &report.ffmpeg_output {
             let output = ffmpeg_output.trim();
             if !output.is_empty() {
                 warn!("FFmpeg output:\n{}", output);
                 con_print(marker, &format!("FFmpeg output:\n{output}\n"));
             }
         }
+
+        if let Some(err) = &report.error {
+            error!("error during recording: {:?}", err);
+            con_print(marker, &format!("Recording failed: {err:#}\n"));
+        }
+
+        let stats = report.stats;
+        con_print(
+            marker,
+            &format!(
+                "Recorded {} video frames ({} dropped) and {} audio samples in {:.1} s.\n",
+                stats.video_frames,
+                stats.dropped_frames,
+                stats.audio_samples,
+                stats.elapsed.as_secs_f64(),
+            ),
+        );
+
+        if paused_time > 0. {
+            con_print(
+                marker,
+                &format!("Skipped {paused_time:.1} s of game time while paused.\n"),
+            );
+        }
+
+        if output_size != full_size {
+            con_print(
+                marker,
+                &format!(
+                    "The last segment was recorded at {}x{} to keep up.\n",
+                    output_size.0, output_size.1,
+                ),
+            );
+        }
+
+        if report.chapters.len() > 1 {
+            con_print(
+                marker,
+                &format!("Found {} scenes.\n", report.chapters.len()),
+            );
+        }
+
+        let drift = report.drift();
+        if drift != 0 && report.error.is_none() {
+            warn!("video frame drift: {}", drift);
+            con_print(
+                marker,
+                &format!(
+                    "Warning: the video is {drift} frame(s) off from the {:.1} s of recorded game \
+                    time.\n",
+                    report.game_time,
+                ),
+            );
+        }
     }
 
     if stopped {
@@ -292,6 +711,161 @@ pub fn cap_stop(marker: MainThreadMarker) {
     capture_video_per_demo::stop(marker);
 }
 
+static BXT_CAP_PAUSE: Command = Command::new(
+    b"bxt_cap_pause\0",
+    handler!(
+        "bxt_cap_pause
+
+Pauses capturing video without finishing the recording. The game time that passes while paused \
+is skipped in the video.",
+        cap_pause as fn(_)
+    ),
+);
+
+fn cap_pause(marker: MainThreadMarker) {
+    let mut state = STATE.borrow_mut(marker);
+    let State::Recording(ref mut recorder) = *state else {
+        con_print(marker, "Error: not recording.\n");
+        return;
+    };
+
+    recorder.pause();
+    con_print(
+        marker,
+        &format!(
+            "Paused the recording after {:.1} s of video.\n",
+            recorder.recorded_seconds()
+        ),
+    );
+}
+
+static BXT_CAP_RESUME: Command = Command::new(
+    b"bxt_cap_resume\0",
+    handler!(
+        "bxt_cap_resume
+
+Resumes capturing video paused with `bxt_cap_pause`.",
+        cap_resume as fn(_)
+    ),
+);
+
+fn cap_resume(marker: MainThreadMarker) {
+    let mut state = STATE.borrow_mut(marker);
+    let State::Recording(ref mut recorder) = *state else {
+        con_print(marker, "Error: not recording.\n");
+        return;
+    };
+
+    recorder.resume();
+}
+
+static BXT_CAP_SELF_TEST: Command = Command::new(
+    b"bxt_cap_self_test\0",
+    handler!(
+        "bxt_cap_self_test [filename.mp4]
+
+Records two seconds of a moving test pattern at the game resolution, to check that FFmpeg and the \
+capture settings work without playing. The default filename is `self_test.mp4`.",
+        cap_self_test as fn(_),
+        cap_self_test_with_filename as fn(_, _)
+    ),
+);
+
+fn cap_self_test(marker: MainThreadMarker) {
+    cap_self_test_with_filename(marker, "self_test.mp4".to_string());
+}
+
+fn cap_self_test_with_filename(marker: MainThreadMarker, filename: String) {
+    if !Capture.is_enabled(marker) {
+        return;
+    }
+
+    if !matches!(*STATE.borrow(marker), State::Idle) {
+        con_print(marker, "Error: can't run the self-test while recording.\n");
+        return;
+    }
+
+    let fps = BXT_CAP_FPS.as_u64(marker).max(1);
+    let result = unsafe {
+        let (width, height) = engine::get_resolution(marker);
+        let capture_type = video_capture_type(marker);
+        Recorder::self_test(
+            width,
+            height,
+            fps,
+            &filename,
+            fps as usize * 2,
+            capture_type,
+        )
+    };
+
+    match result {
+        Ok(()) => con_print(
+            marker,
+            &format!("Recorded the self-test into {filename}.\n"),
+        ),
+        Err(err) => {
+            error!("error running the self-test: {:?}", err);
+            con_print(marker, &format!("Self-test failed: {err:#}\n"));
+        }
+    }
+}
+
+/// Parses the GIF preview settings: the start, the duration, the width and the frame rate.
+fn parse_gif_config(value: &str) -> Option<GifConfig> {
+    let mut parts = value.split_ascii_whitespace();
+    let config = GifConfig {
+        start_seconds: parts.next()?.parse().ok()?,
+        duration_seconds: parts.next()?.parse().ok()?,
+        width: parts.next()?.parse().ok()?,
+        fps: parts.next()?.parse().ok()?,
+    };
+
+    parts.next().is_none().then_some(config)
+}
+
+/// Reads the HUD text for every video frame.
+///
+/// `.hltas` scripts are turned into the frame index, time and strafe type of every frame, other
+/// files are used line by line.
+fn read_hud_lines(path: &Path) -> eyre::Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)?;
+
+    let is_script = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("hltas"));
+    if is_script {
+        let hltas = HLTAS::from_str(&text).map_err(|err| eyre!("can't parse the script: {err}"))?;
+        return Ok(frame_hud_lines(&hltas));
+    }
+
+    Ok(text.lines().map(str::to_owned).collect())
+}
+
+/// Returns the fastest way to capture the video that works.
+unsafe fn video_capture_type(marker: MainThreadMarker) -> CaptureType {
+    if HAVE_REQUIRED_GL_EXTENSIONS.get(marker)
+        && !BXT_CAP_FORCE_FALLBACK.as_bool(marker)
+        // Check Vulkan last.
+        //
+        // On some Windows AMD GPU setups initializing Vulkan and then doing anything with
+        // OpenGL causes a crash in the driver (atioglxx.dll). To remedy this, we initialize
+        // Vulkan lazily. This way if you don't use video recording, or if you have
+        // _bxt_cap_force_fallback 1, Vulkan is never initialized, so you can avoid the crash.
+        && crate::vulkan::VULKAN.is_some()
+    {
+        match opengl::get_uuids(marker).wrap_err("error getting OpenGL UUIDs") {
+            Ok(uuids) => CaptureType::Vulkan(uuids),
+            Err(err) => {
+                warn!("{:?}", err);
+                CaptureType::ReadPixels
+            }
+        }
+    } else {
+        CaptureType::ReadPixels
+    }
+}
+
 pub unsafe fn capture_frame(marker: MainThreadMarker) {
     if !Capture.is_enabled(marker) {
         return;
@@ -308,28 +882,23 @@ pub unsafe fn capture_frame(marker: MainThreadMarker) {
 
     // Initialize the recording if needed.
     if let State::Starting(ref filename) = *state {
-        let fps = BXT_CAP_FPS.as_u64(marker).max(1);
+        let fps_fraction = BXT_CAP_FPS
+            .to_string(marker)
+            .trim()
+            .split_once('/')
+            .and_then(|(num, den)| Some((num.trim().parse().ok()?, den.trim().parse().ok()?)))
+            .filter(|&(num, den): &(u64, u64)| num > 0 && den > 0);
+        // The recorder takes the exact rate from the fraction, this is only the nearest whole one.
+        let fps = match fps_fraction {
+            Some((num, den)) => ((num + den / 2) / den).max(1),
+            None => BXT_CAP_FPS.as_u64(marker).max(1),
+        };
         let slowdown = BXT_CAP_SLOWDOWN.as_f32(marker).max(0.1) as f64;
 
-        let capture_type = if HAVE_REQUIRED_GL_EXTENSIONS.get(marker)
-            && !BXT_CAP_FORCE_FALLBACK.as_bool(marker)
-            // Check Vulkan last.
-            //
-            // On some Windows AMD GPU setups initializing Vulkan and then doing anything with
-            // OpenGL causes a crash in the driver (atioglxx.dll). To remedy this, we initialize
-            // Vulkan lazily. This way if you don't use video recording, or if you have
-            // _bxt_cap_force_fallback 1, Vulkan is never initialized, so you can avoid the crash.
-            && crate::vulkan::VULKAN.is_some()
-        {
-            match opengl::get_uuids(marker).wrap_err("error getting OpenGL UUIDs") {
-                Ok(uuids) => CaptureType::Vulkan(uuids),
-                Err(err) => {
-                    warn!("{:?}", err);
-                    CaptureType::ReadPixels
-                }
-            }
+        let capture_type = if BXT_CAP_AUDIO_ONLY.as_bool(marker) {
+            CaptureType::AudioOnly
         } else {
-            CaptureType::ReadPixels
+            video_capture_type(marker)
         };
 
         let custom_ffmpeg_args = BXT_CAP_OVERRIDE_FFMPEG_ARGS.to_string(marker);
@@ -343,6 +912,99 @@ pub unsafe fn capture_frame(marker: MainThreadMarker) {
         };
         let custom_ffmpeg_args = custom_ffmpeg_args.as_deref();
 
+        let codec_name = BXT_CAP_VIDEO_CODEC.to_string(marker);
+        let Some(codec) = VideoCodec::from_name(codec_name.trim()) else {
+            con_print(
+                marker,
+                &format!("Error initializing recording: unknown video codec {codec_name}.\n"),
+            );
+            *state = State::Idle;
+            return;
+        };
+
+        let encoder_name = BXT_CAP_ENCODER.to_string(marker);
+        let Some(encoder) = Encoder::from_name(encoder_name.trim()) else {
+            con_print(
+                marker,
+                &format!("Error initializing recording: unknown encoder {encoder_name}.\n"),
+            );
+            *state = State::Idle;
+            return;
+        };
+        let encoder = match encoder {
+            Encoder::Nvenc { .. } => Encoder::Nvenc {
+                preset: BXT_CAP_NVENC_PRESET.as_u64(marker).clamp(1, 7) as u8,
+                bitrate_kbps: match BXT_CAP_NVENC_BITRATE.as_u64(marker) {
+                    0 => None,
+                    kbps => Some(kbps.min(u32::MAX.into()) as u32),
+                },
+            },
+            encoder => encoder,
+        };
+
+        let tee_targets = BXT_CAP_TEE_TARGETS.to_string(marker);
+        let tee_targets = match tee_targets
+            .split_ascii_whitespace()
+            .map(|target| TeeTarget::parse(target).ok_or(target))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(targets) => Some(targets).filter(|targets| !targets.is_empty()),
+            Err(target) => {
+                con_print(
+                    marker,
+                    &format!(
+                        "Error initializing recording: invalid tee target {target}, expected \
+                        the format and the path or URL separated by a colon.\n"
+                    ),
+                );
+                *state = State::Idle;
+                return;
+            }
+        };
+
+        let hud_file = BXT_CAP_HUD_FILE.to_string(marker);
+        let hud_lines = if hud_file.trim().is_empty() {
+            None
+        } else {
+            match read_hud_lines(Path::new(hud_file.trim())) {
+                Ok(lines) => Some(lines),
+                Err(err) => {
+                    con_print(
+                        marker,
+                        &format!("Error initializing recording: can't read {hud_file}: {err:#}.\n"),
+                    );
+                    *state = State::Idle;
+                    return;
+                }
+            }
+        };
+
+        let gif_preview = BXT_CAP_GIF_PREVIEW.to_string(marker);
+        let gif_preview = if gif_preview.trim().is_empty() {
+            None
+        } else {
+            match parse_gif_config(&gif_preview) {
+                Some(config) => Some(config),
+                None => {
+                    con_print(
+                        marker,
+                        &format!(
+                            "Error initializing recording: invalid GIF preview {gif_preview}, \
+                            expected the start, the duration, the width and the frame rate.\n"
+                        ),
+                    );
+                    *state = State::Idle;
+                    return;
+                }
+            }
+        };
+
+        let non_empty_path = |cvar: &CVar| {
+            Some(cvar.to_string(marker))
+                .filter(|path| !path.trim().is_empty())
+                .map(|path| PathBuf::from(path.trim()))
+        };
+
         let sampling_exposure = BXT_CAP_SAMPLING_EXPOSURE.as_f32(marker).into();
         let sampling_min_fps = BXT_CAP_SAMPLING_MIN_FPS
             .as_f32(marker)
@@ -359,11 +1021,102 @@ pub unsafe fn capture_frame(marker: MainThreadMarker) {
             custom_ffmpeg_args,
             sampling_exposure,
             sampling_min_fps,
+            RecorderOptions {
+                codec,
+                encoder,
+                no_sound: !BXT_CAP_SOUND.as_bool(marker),
+                fps_fraction,
+                output: OutputTarget::from_filename(filename),
+                lossless: BXT_CAP_LOSSLESS.as_bool(marker),
+                vulkan_device: Some(BXT_CAP_VULKAN_DEVICE.to_string(marker))
+                    .filter(|selector| !selector.trim().is_empty())
+                    .map(|selector| DeviceSelector::parse(&selector)),
+                scene_split_threshold: Some(BXT_CAP_SCENE_THRESHOLD.as_f32(marker))
+                    .filter(|&threshold| threshold > 0.),
+                max_samples_per_call: Some(BXT_CAP_MAX_SAMPLES_PER_CALL.as_u64(marker))
+                    .filter(|&max| max > 0)
+                    .map(|max| max.min(u32::MAX as u64) as u32),
+                extra_ffmpeg_args: BXT_CAP_EXTRA_FFMPEG_ARGS
+                    .to_string(marker)
+                    .split_ascii_whitespace()
+                    .map(str::to_owned)
+                    .collect(),
+                ffmpeg_path: Some(BXT_CAP_FFMPEG_PATH.to_string(marker))
+                    .filter(|path| !path.trim().is_empty())
+                    .map(PathBuf::from),
+                queue_size: Some(BXT_CAP_QUEUE_SIZE.as_u64(marker) as usize)
+                    .filter(|&size| size > 0),
+                queue_policy: if BXT_CAP_DROP_FRAMES_WHEN_BEHIND.as_bool(marker) {
+                    QueuePolicy::DropNewest
+                } else {
+                    QueuePolicy::Block
+                },
+                segment_seconds: Some(BXT_CAP_SEGMENT_SECONDS.as_f32(marker))
+                    .filter(|&seconds| seconds > 0.)
+                    .map(f64::from),
+                adaptive_resolution: BXT_CAP_ADAPTIVE_RESOLUTION.as_bool(marker),
+                tee_targets,
+                motion_blur: match BXT_CAP_OVERSAMPLE.as_u64(marker) {
+                    0 => None,
+                    subframes => Some(MotionBlur {
+                        subframes: subframes as usize,
+                        shutter: BXT_CAP_SHUTTER_ANGLE.as_f32(marker).clamp(0., 1.).into(),
+                    }),
+                },
+                dither: BXT_CAP_DITHER.as_bool(marker),
+                film_24fps: BXT_CAP_FILM_24FPS.as_bool(marker),
+                low_latency: BXT_CAP_LOW_LATENCY.as_bool(marker),
+                offline: BXT_CAP_OFFLINE.as_bool(marker),
+                separate_audio_thread: BXT_CAP_SEPARATE_AUDIO_THREAD.as_bool(marker),
+                hud_lines,
+                burn_timecode: BXT_CAP_BURN_TIMECODE.as_bool(marker),
+                captions: non_empty_path(&BXT_CAP_CAPTIONS),
+                loudnorm: Some(BXT_CAP_LOUDNORM.as_f32(marker))
+                    .filter(|&lufs| lufs != 0.)
+                    .map(|integrated_lufs| LoudnessTarget { integrated_lufs }),
+                fragmented: BXT_CAP_FRAGMENTED.as_bool(marker),
+                raw_planes: BXT_CAP_RAW_PLANES.as_bool(marker),
+                lossless_with_preview: BXT_CAP_LOSSLESS_PREVIEW.as_bool(marker),
+                proxy: match BXT_CAP_PROXY_FPS.as_u64(marker) {
+                    0 => None,
+                    fps => Some(ProxyConfig {
+                        fps,
+                        scale: BXT_CAP_PROXY_SCALE.as_f32(marker),
+                    }),
+                },
+                two_pass_target_size_mb: Some(BXT_CAP_TARGET_SIZE_MB.as_u64(marker))
+                    .filter(|&size| size > 0),
+                fade_in_seconds: BXT_CAP_FADE_IN.as_f32(marker).max(0.).into(),
+                fade_out_seconds: BXT_CAP_FADE_OUT.as_f32(marker).max(0.).into(),
+                write_checksums: BXT_CAP_CHECKSUMS.as_bool(marker),
+                write_edl: BXT_CAP_EDL.as_bool(marker),
+                gif_preview,
+                compare_to: non_empty_path(&BXT_CAP_COMPARE_TO),
+            },
+            None,
         ) {
-            Ok(recorder) => {
+            Ok(mut recorder) => {
                 if matches!(recorder.capture_type(), CaptureType::ReadPixels) {
                     con_print(marker, "Recording in slower fallback mode.\n");
                 }
+                if let Some(encoder) = recorder.encoder() {
+                    con_print(marker, &format!("Encoding with {}.\n", encoder.name()));
+                }
+                let command = recorder.encoder_command();
+                if !command.is_empty() {
+                    info!("FFmpeg command: {}", command.join(" "));
+                }
+                let (padded_width, padded_height) = recorder.padded();
+                if padded_width != 0 || padded_height != 0 {
+                    con_print(
+                        marker,
+                        &format!(
+                            "Padding the odd resolution with {padded_width} column(s) and \
+                            {padded_height} row(s) of black.\n"
+                        ),
+                    );
+                }
+                recorder.set_auto_pause_on_focus_loss(BXT_CAP_PAUSE_ON_FOCUS_LOSS.as_bool(marker));
                 *state = State::Recording(recorder)
             }
             Err(err) => {
@@ -406,6 +1159,20 @@ pub unsafe fn capture_frame(marker: MainThreadMarker) {
         return;
     }
 
+    // The overlay goes into the frames recorded after this one is captured.
+    if BXT_CAP_CURSOR.as_bool(marker) && sdl::SDL_GetMouseState.is_set(marker) {
+        if let Some(shown) = sdl::is_cursor_shown(marker) {
+            let mouse = sdl::mouse_state(marker);
+            recorder.set_cursor(mouse.pos.x, mouse.pos.y, shown);
+        }
+    }
+    if BXT_CAP_GAMEPAD.as_bool(marker) {
+        if let Some([lx, ly, rx, ry]) = sdl::game_controller_sticks(marker) {
+            // SDL's stick axes point down, the recorder's point up.
+            recorder.set_analog_input(lx, -ly, rx, -ry);
+        }
+    }
+
     // Capture this frame for recording later.
     if let Err(err) = recorder.capture_opengl(marker) {
         error!("{:?}", err);
@@ -535,6 +1302,10 @@ pub unsafe fn time_passed(marker: MainThreadMarker) {
         return;
     };
 
+    if let Some(focused) = sdl::has_keyboard_focus(marker) {
+        recorder.notify_focus(focused);
+    }
+
     // Accumulate time for the last frame.
     let time = *engine::host_frametime.get(marker);
     recorder.time_passed(time);
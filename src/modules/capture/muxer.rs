@@ -1,24 +1,141 @@
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
-use std::process::{Child, Command, Stdio};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread::{self, JoinHandle};
 
+use color_eyre::eyre::{self, ensure, Context};
 use thiserror::Error;
 
 pub struct Muxer {
     child: Child,
+    /// Thread reading FFmpeg's standard error, which returns everything it has read.
+    stderr: Option<JoinHandle<String>>,
+    /// FFmpeg output, if writing has failed and the output was already returned with the error.
+    failed_output: Option<String>,
+    command: Vec<String>,
+    encoder: Option<Encoder>,
+    /// Whether the stream has only the audio, with no video.
+    audio_only: bool,
+    /// Whether the stream has an audio track.
+    capture_sound: bool,
     video_pts: u64,
     audio_pts: u64,
 }
 
 #[derive(Error, Debug)]
 pub enum MuxerInitError {
-    #[error("could not spawn ffmpeg")]
-    FfmpegSpawn(io::Error),
+    #[error("could not spawn ffmpeg at {path}")]
+    FfmpegSpawn { path: String, source: io::Error },
+    #[error("fragmented output is only supported for MP4 and MOV files")]
+    FragmentedContainer,
+    #[error("the {} encoder is not available", .0.name())]
+    EncoderUnavailable(Encoder),
+    #[error("the {} encoder needs a .{} output file", .0.name(), .0.container().unwrap())]
+    ContainerMismatch(Encoder),
+    #[cfg(unix)]
+    #[error("could not open the VAAPI device {device}")]
+    VaapiDevice { device: String, source: io::Error },
+    #[error("the extra ffmpeg arguments contain the output file")]
+    OutputInExtraArgs,
+    #[error(
+        "the extra ffmpeg arguments can't contain {option} because the output format is already \
+        set to {format}"
+    )]
+    FormatInExtraArgs {
+        option: String,
+        format: &'static str,
+    },
+    #[error("ffmpeg exited on startup: {0}")]
+    FfmpegRejected(String),
     #[error(transparent)]
     Other(#[from] io::Error),
 }
 
+/// Additional output of the recording, written with the FFmpeg `tee` muxer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TeeTarget {
+    /// File path or URL to write to, such as `rtmp://localhost/live/stream`.
+    pub path_or_url: String,
+    /// FFmpeg output format, such as `flv` or `mpegts`.
+    pub format: String,
+}
+
+impl TeeTarget {
+    /// Parses a target given as the format and the path or URL separated by a colon, such as
+    /// `flv:rtmp://localhost/live/stream`.
+    pub fn parse(target: &str) -> Option<Self> {
+        let (format, path_or_url) = target.split_once(':')?;
+        if format.is_empty() || path_or_url.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            path_or_url: path_or_url.to_owned(),
+            format: format.to_owned(),
+        })
+    }
+}
+
+/// Settings for encoding the stream from the game.
+///
+/// The defaults encode with x264 into the format picked by FFmpeg from the output file extension.
+#[derive(Debug, Clone, Default)]
+pub struct MuxerOptions<'a> {
+    /// Video codec, used unless `custom_ffmpeg_args` are given.
+    pub codec: VideoCodec,
+    /// Encoder for `codec`. Codecs that the encoder doesn't support use their software encoders.
+    pub encoder: Encoder,
+    /// FFmpeg arguments replacing the built-in video codec and encoder arguments.
+    pub custom_ffmpeg_args: Option<&'a [&'a str]>,
+    /// FFmpeg arguments which go after all other arguments, right before the output file, so they
+    /// override the built-in and the custom arguments.
+    pub extra_ffmpeg_args: &'a [String],
+    /// SRT file with subtitles to draw on top of the video.
+    pub hud_subtitles: Option<&'a Path>,
+    /// SRT or WebVTT file to mux as a subtitle track.
+    pub captions: Option<&'a Path>,
+    /// Additional outputs which get the same encoded stream through the FFmpeg `tee` muxer.
+    pub tee_targets: &'a [TeeTarget],
+    /// Whether to draw a timecode counting the frames on top of the video.
+    pub burn_timecode: bool,
+    /// Whether to write a fragmented MP4 which is playable while it's still being written.
+    pub fragmented: bool,
+    /// Integrated loudness in LUFS to normalize the audio to with single-pass `loudnorm`, which is
+    /// approximate since it can't look ahead at the whole recording.
+    pub loudness_lufs: Option<f32>,
+    /// FFmpeg executable to run instead of the platform default.
+    pub ffmpeg_path: Option<&'a Path>,
+    /// Whether to leave out the audio track.
+    pub no_sound: bool,
+}
+
+impl MuxerOptions<'_> {
+    /// Returns the encoder actually used for the codec, or [`None`] if custom arguments are given.
+    pub fn effective_encoder(&self) -> Option<Encoder> {
+        if self.custom_ffmpeg_args.is_some() {
+            None
+        } else if self.encoder.codec_name(self.codec).is_some() {
+            Some(self.encoder.clone())
+        } else {
+            Some(Encoder::X264)
+        }
+    }
+}
+
+/// Destination for the video and audio frames produced by the recorder.
+pub trait FrameSink: Send {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error>;
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error>;
+
+    /// Finishes writing and returns the output of the sink, if any.
+    ///
+    /// Returns an error if the sink failed to finish writing.
+    fn close(self: Box<Self>) -> Result<String, io::Error>;
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PixelFormat {
     I420,
@@ -26,6 +143,369 @@ pub enum PixelFormat {
     Rgb24Flipped,
 }
 
+/// Video codec used by FFmpeg when no custom arguments are given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    H265,
+    Vp9,
+    /// Lossless, in the same pixel format as the captured frames.
+    Ffv1,
+}
+
+impl VideoCodec {
+    /// Parses a codec from its name, such as `h264`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "h264" => Some(Self::H264),
+            "h265" => Some(Self::H265),
+            "vp9" => Some(Self::Vp9),
+            "ffv1" => Some(Self::Ffv1),
+            _ => None,
+        }
+    }
+
+    /// Returns the FFmpeg arguments for encoding `pixel_format` frames with this codec.
+    ///
+    /// `encoder` is used if it supports the codec, otherwise the codec's software encoder is used.
+    fn ffmpeg_args(self, pixel_format: PixelFormat, encoder: &Encoder) -> Vec<String> {
+        let mut args = match encoder.codec_name(self) {
+            Some(name) => {
+                let mut args = vec!["-c:v".to_owned(), name.to_owned()];
+                args.extend(encoder.quality_args());
+                args
+            }
+            None => self
+                .software_args(pixel_format)
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+        };
+
+        // The color metadata doesn't apply to RGB output.
+        let is_rgb = (self == VideoCodec::Ffv1 && pixel_format == PixelFormat::Rgb24Flipped)
+            || *encoder == Encoder::Hap;
+        if !is_rgb {
+            #[rustfmt::skip]
+            let color_args = [
+                "-color_primaries", "bt709",
+                "-color_trc", "bt709",
+                "-colorspace", "bt709",
+                "-color_range", "tv",
+                "-chroma_sample_location", "center",
+            ];
+            args.extend(color_args.map(str::to_owned));
+        }
+
+        args
+    }
+
+    /// Returns the FFmpeg arguments for encoding `pixel_format` frames with the software encoder.
+    fn software_args(self, pixel_format: PixelFormat) -> Vec<&'static str> {
+        #[rustfmt::skip]
+        let args = match self {
+            VideoCodec::H264 => vec![
+                "-c:v", "libx264",
+                "-crf", "15",
+                "-preset", "ultrafast",
+            ],
+            VideoCodec::H265 => vec![
+                "-c:v", "libx265",
+                "-crf", "15",
+                "-preset", "ultrafast",
+                "-pix_fmt", "yuv420p",
+            ],
+            VideoCodec::Vp9 => vec![
+                "-c:v", "libvpx-vp9",
+                // Constant quality mode needs the bitrate set to zero.
+                "-crf", "15",
+                "-b:v", "0",
+                "-deadline", "realtime",
+                "-cpu-used", "8",
+                "-pix_fmt", "yuv420p",
+            ],
+            VideoCodec::Ffv1 => {
+                let pix_fmt = match pixel_format {
+                    PixelFormat::I420 => "yuv420p",
+                    PixelFormat::Rgb24Flipped => "bgr0",
+                };
+                vec!["-c:v", "ffv1", "-level", "3", "-pix_fmt", pix_fmt]
+            }
+        };
+        args
+    }
+}
+
+/// Encoder used by FFmpeg when no custom arguments are given.
+///
+/// Hardware encoders are used for H.264 and H.265, while the intermediate codec encoders for
+/// editing software replace the video codec.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Encoder {
+    /// Software encoding with x264, or the software encoder of the chosen codec.
+    #[default]
+    X264,
+    Nvenc {
+        /// NVENC preset from `1` (fastest) to `7` (best quality).
+        preset: u8,
+        /// Target bitrate in kbit/s, or [`None`] for constant quality.
+        bitrate_kbps: Option<u32>,
+    },
+    #[cfg(unix)]
+    Vaapi {
+        /// Path to the DRM render node of the GPU.
+        device: String,
+    },
+    QuickSync,
+    /// ProRes, replacing the video codec. Needs a MOV output file.
+    ProRes {
+        /// ProRes profile from `0` (Proxy) to `5` (4444 XQ).
+        profile: u8,
+    },
+    /// HAP, replacing the video codec. Needs a MOV output file.
+    Hap,
+}
+
+impl Encoder {
+    /// Parses an encoder from its name, such as `nvenc`.
+    ///
+    /// The VAAPI device and the ProRes profile can be given after a colon, such as
+    /// `vaapi:/dev/dri/renderD129` or `prores:2`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let (name, arg) = match name.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (name, None),
+        };
+
+        match (name.to_ascii_lowercase().as_str(), arg) {
+            ("x264", None) => Some(Self::X264),
+            ("nvenc", None) => Some(Self::Nvenc {
+                preset: 1,
+                bitrate_kbps: None,
+            }),
+            #[cfg(unix)]
+            ("vaapi", device) => Some(Self::Vaapi {
+                device: device.unwrap_or("/dev/dri/renderD128").to_owned(),
+            }),
+            ("qsv", None) => Some(Self::QuickSync),
+            ("prores", None) => Some(Self::ProRes { profile: 3 }),
+            ("prores", Some(profile)) => {
+                let profile = profile.parse().ok().filter(|&profile| profile <= 5)?;
+                Some(Self::ProRes { profile })
+            }
+            ("hap", None) => Some(Self::Hap),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoder::X264 => "x264",
+            Encoder::Nvenc { .. } => "NVENC",
+            #[cfg(unix)]
+            Encoder::Vaapi { .. } => "VAAPI",
+            Encoder::QuickSync => "Quick Sync",
+            Encoder::ProRes { .. } => "ProRes",
+            Encoder::Hap => "HAP",
+        }
+    }
+
+    /// Returns the FFmpeg name of this encoder for `codec`, if it supports it.
+    ///
+    /// The intermediate codec encoders are used regardless of `codec`.
+    fn codec_name(&self, codec: VideoCodec) -> Option<&'static str> {
+        match (self, codec) {
+            (Encoder::ProRes { .. }, _) => Some("prores_ks"),
+            (Encoder::Hap, _) => Some("hap"),
+            (Encoder::Nvenc { .. }, VideoCodec::H264) => Some("h264_nvenc"),
+            (Encoder::Nvenc { .. }, VideoCodec::H265) => Some("hevc_nvenc"),
+            #[cfg(unix)]
+            (Encoder::Vaapi { .. }, VideoCodec::H264) => Some("h264_vaapi"),
+            #[cfg(unix)]
+            (Encoder::Vaapi { .. }, VideoCodec::H265) => Some("hevc_vaapi"),
+            (Encoder::QuickSync, VideoCodec::H264) => Some("h264_qsv"),
+            (Encoder::QuickSync, VideoCodec::H265) => Some("hevc_qsv"),
+            _ => None,
+        }
+    }
+
+    /// Returns the names of the FFmpeg encoders behind this encoder.
+    fn ffmpeg_names(&self) -> Vec<&'static str> {
+        if *self == Encoder::X264 {
+            return vec!["libx264"];
+        }
+
+        let mut names = Vec::new();
+        for codec in [VideoCodec::H264, VideoCodec::H265] {
+            if let Some(name) = self.codec_name(codec) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// Returns the FFmpeg arguments for the encoder settings.
+    ///
+    /// Without a target bitrate, the quality is similar to the software encoders.
+    fn quality_args(&self) -> Vec<String> {
+        match *self {
+            Encoder::X264 => Vec::new(),
+            Encoder::Nvenc {
+                preset,
+                bitrate_kbps,
+            } => {
+                // NV12 is what NVENC encodes natively.
+                let mut args = vec![
+                    "-preset".to_owned(),
+                    format!("p{}", preset.clamp(1, 7)),
+                    "-pix_fmt".to_owned(),
+                    "nv12".to_owned(),
+                    "-rc".to_owned(),
+                    "vbr".to_owned(),
+                ];
+                match bitrate_kbps {
+                    Some(kbps) => args.extend(["-b:v".to_owned(), format!("{kbps}k")]),
+                    None => args.extend(["-cq", "15", "-b:v", "0"].map(str::to_owned)),
+                }
+                args
+            }
+            #[cfg(unix)]
+            Encoder::Vaapi { .. } => Vec::from(["-qp", "15"].map(str::to_owned)),
+            Encoder::QuickSync => {
+                Vec::from(["-preset", "veryfast", "-global_quality", "15"].map(str::to_owned))
+            }
+            Encoder::ProRes { profile } => {
+                // The 4444 profiles keep full chroma resolution.
+                let pix_fmt = if profile >= 4 {
+                    "yuv444p10le"
+                } else {
+                    "yuv422p10le"
+                };
+                vec![
+                    "-profile:v".to_owned(),
+                    profile.min(5).to_string(),
+                    "-pix_fmt".to_owned(),
+                    pix_fmt.to_owned(),
+                ]
+            }
+            Encoder::Hap => Vec::from(["-format", "hap", "-pix_fmt", "rgb0"].map(str::to_owned)),
+        }
+    }
+
+    /// Returns the FFmpeg arguments setting up the hardware device, which go before the input.
+    fn device_args(&self) -> Vec<&str> {
+        match self {
+            #[cfg(unix)]
+            Encoder::Vaapi { device } => vec!["-vaapi_device", device.as_str()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the filter uploading the frames to the hardware device, if one is needed.
+    fn upload_filter(&self) -> Option<&'static str> {
+        match self {
+            // The VAAPI encoders take NV12. The Vulkan capture gives I420, which differs only in
+            // the chroma plane layout, so this conversion is cheap.
+            #[cfg(unix)]
+            Encoder::Vaapi { .. } => Some("format=nv12,hwupload"),
+            _ => None,
+        }
+    }
+
+    /// Returns the container format that this encoder needs, if any.
+    fn container(&self) -> Option<&'static str> {
+        match self {
+            Encoder::ProRes { .. } | Encoder::Hap => Some("mov"),
+            _ => None,
+        }
+    }
+
+    /// Checks that the hardware device of this encoder can be opened.
+    fn check_device(&self) -> Result<(), MuxerInitError> {
+        match self {
+            #[cfg(unix)]
+            Encoder::Vaapi { device } => match std::fs::File::open(device) {
+                Ok(_) => Ok(()),
+                Err(source) => Err(MuxerInitError::VaapiDevice {
+                    device: device.clone(),
+                    source,
+                }),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the FFmpeg program and its arguments for encoding a short test video with the hardware
+/// `encoder` for `codec`.
+fn encoder_probe_command(encoder: &Encoder, codec: VideoCodec) -> Vec<String> {
+    let mut args = vec!["ffmpeg", "-loglevel", "error"];
+    args.extend(encoder.device_args());
+    #[rustfmt::skip]
+    args.extend_from_slice(&[
+        "-f", "lavfi",
+        "-i", "color=size=256x256:duration=0.1",
+    ]);
+    if let Some(filter) = encoder.upload_filter() {
+        args.extend_from_slice(&["-vf", filter]);
+    }
+    args.extend_from_slice(&["-c:v", encoder.codec_name(codec).unwrap_or("libx264")]);
+    args.extend_from_slice(&["-f", "null", "-"]);
+    args.into_iter().map(str::to_owned).collect()
+}
+
+/// Returns the encoders listed in the output of `ffmpeg -encoders`.
+///
+/// Every encoder is returned with its default settings.
+fn parse_encoders(output: &str) -> Vec<Encoder> {
+    // The list of encoders follows the legend of the flags, which ends with a separator line.
+    let names = output
+        .lines()
+        .skip_while(|line| line.trim() != "------")
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let flags = columns.next()?;
+            let name = columns.next()?;
+            flags.starts_with('V').then_some(name)
+        })
+        .collect::<Vec<_>>();
+
+    ["x264", "nvenc", "vaapi", "qsv", "prores", "hap"]
+        .into_iter()
+        .filter_map(Encoder::from_name)
+        .filter(|encoder| {
+            encoder
+                .ffmpeg_names()
+                .iter()
+                .any(|name| names.contains(name))
+        })
+        .collect()
+}
+
+/// Returns whether FFmpeg can encode with `encoder` by running it on a short test video.
+#[instrument]
+fn probe_encoder(encoder: &Encoder, codec: VideoCodec, ffmpeg_path: Option<&Path>) -> bool {
+    let mut command_line = encoder_probe_command(encoder, codec);
+    command_line[0] = ffmpeg_program(ffmpeg_path);
+
+    let mut command = Command::new(&command_line[0]);
+    command
+        .args(&command_line[1..])
+        .env_remove("LD_PRELOAD")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    #[cfg(windows)]
+    command.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
+
+    command.status().map_or(false, |status| status.success())
+}
+
 impl PixelFormat {
     fn fourcc(self) -> &'static [u8; 4] {
         match self {
@@ -95,133 +575,353 @@ fn packet<W: Write>(mut writer: W, startcode: u64, data: &[u8]) -> Result<(), io
     Ok(())
 }
 
+/// Returns subtitles in the SRT format showing every line of `lines` for one video frame.
+pub fn hud_subtitles(lines: &[String], fps_num: u64, fps_den: u64) -> String {
+    fn timestamp(frame: u64, fps_num: u64, fps_den: u64) -> String {
+        let ms = frame * fps_den * 1000 / fps_num;
+        format!(
+            "{:02}:{:02}:{:02},{:03}",
+            ms / 3_600_000,
+            ms / 60_000 % 60,
+            ms / 1000 % 60,
+            ms % 1000
+        )
+    }
+
+    let mut srt = String::new();
+    for (frame, line) in (0..).zip(lines) {
+        srt.push_str(&format!(
+            "{}\n{} --> {}\n{line}\n\n",
+            frame + 1,
+            timestamp(frame, fps_num, fps_den),
+            timestamp(frame + 1, fps_num, fps_den),
+        ));
+    }
+    srt
+}
+
+/// Returns whether `filename` has the file extension `extension`, ignoring case.
+fn has_extension(filename: &str, extension: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Returns the subtitle codec for captions in the container of `filename`.
+fn caption_codec(filename: &str) -> &'static str {
+    if ["mp4", "mov", "m4v"]
+        .into_iter()
+        .any(|container| has_extension(filename, container))
+    {
+        "mov_text"
+    } else if has_extension(filename, "webm") {
+        "webvtt"
+    } else {
+        "srt"
+    }
+}
+
+/// Returns whether FFmpeg can write a fragmented file with the container of `filename`.
+fn supports_fragmenting(filename: &str) -> bool {
+    has_extension(filename, "mp4") || has_extension(filename, "mov")
+}
+
+/// Returns `path` escaped for use in quotes in an FFmpeg filter graph.
+pub fn filter_path(path: &Path) -> String {
+    // Mainly for the drive letter colon on Windows.
+    path.to_string_lossy()
+        .replace('\\', "/")
+        .replace(':', "\\:")
+        .replace('\'', "\\'")
+}
+
+/// Returns the FFmpeg program and its arguments for encoding the muxed stream into `filename`.
+///
+/// The timecode, if any, counts frames at `fps_num / fps_den` frames per second.
+fn ffmpeg_command(
+    pixel_format: PixelFormat,
+    fps_num: u64,
+    fps_den: u64,
+    filename: &str,
+    options: &MuxerOptions,
+) -> Vec<String> {
+    let MuxerOptions {
+        codec,
+        custom_ffmpeg_args,
+        extra_ffmpeg_args,
+        hud_subtitles,
+        captions,
+        tee_targets,
+        burn_timecode,
+        fragmented,
+        ..
+    } = *options;
+    let timecode_rate = burn_timecode.then_some((fps_num, fps_den));
+    // There's nothing to normalize without the audio.
+    let loudness_lufs = options.loudness_lufs.filter(|_| !options.no_sound);
+
+    let encoder = options.effective_encoder();
+    let codec_args = codec.ffmpeg_args(pixel_format, encoder.as_ref().unwrap_or(&Encoder::X264));
+
+    let mut args = vec!["ffmpeg", "-loglevel", "error"];
+    if let Some(encoder) = &encoder {
+        args.extend(encoder.device_args());
+    }
+    #[rustfmt::skip]
+    args.extend_from_slice(&[
+        "-f", "nut",
+        "-i", "pipe:"
+    ]);
+
+    let captions = captions.map(|path| path.to_string_lossy().into_owned());
+    if let Some(captions) = &captions {
+        args.extend_from_slice(&["-i", captions.as_str()]);
+    }
+
+    let mut filters = Vec::new();
+    if pixel_format == PixelFormat::Rgb24Flipped {
+        filters.push("vflip".to_owned());
+    }
+    if let Some(path) = hud_subtitles {
+        filters.push(format!("subtitles='{}'", filter_path(path)));
+    }
+    if let Some((rate_num, rate_den)) = timecode_rate {
+        filters.push(format!(
+            "drawtext=timecode='00\\:00\\:00\\:00':rate={rate_num}/{rate_den}:\
+            x=8:y=8:fontsize=32:fontcolor=white:box=1:boxcolor=black@0.5"
+        ));
+    }
+    if let Some(filter) = encoder.as_ref().and_then(Encoder::upload_filter) {
+        filters.push(filter.to_owned());
+    }
+
+    let filters = filters.join(",");
+    if !filters.is_empty() {
+        args.extend_from_slice(&["-vf", filters.as_str()]);
+    }
+
+    let audio_filter = loudness_lufs.map(|lufs| format!("loudnorm=I={lufs}"));
+    if let Some(audio_filter) = &audio_filter {
+        args.extend_from_slice(&["-af", audio_filter.as_str()]);
+    }
+
+    if captions.is_some() {
+        #[rustfmt::skip]
+        args.extend_from_slice(&[
+            "-map", "0",
+            "-map", "1",
+            "-c:s", caption_codec(filename),
+        ]);
+    } else if !tee_targets.is_empty() {
+        // The tee muxer doesn't pick the streams by itself.
+        args.extend_from_slice(&["-map", "0"]);
+    }
+
+    if let Some(custom_ffmpeg_args) = custom_ffmpeg_args {
+        args.extend_from_slice(custom_ffmpeg_args);
+    } else {
+        args.extend(codec_args.iter().map(String::as_str));
+    }
+
+    // Fragments are written as the recording goes, so there's nothing to move to the start.
+    let movflags = if fragmented {
+        "frag_keyframe+empty_moov"
+    } else {
+        "+faststart"
+    };
+
+    let container = encoder.as_ref().and_then(Encoder::container);
+    let tee_output;
+    if tee_targets.is_empty() {
+        args.extend_from_slice(&["-movflags", movflags]);
+        if let Some(container) = container {
+            args.extend_from_slice(&["-f", container]);
+        }
+        args.extend(extra_ffmpeg_args.iter().map(String::as_str));
+        args.extend_from_slice(&["-y", filename]);
+    } else {
+        tee_output = tee_output_spec(filename, container, movflags, tee_targets);
+        args.extend(extra_ffmpeg_args.iter().map(String::as_str));
+        args.extend_from_slice(&["-f", "tee", "-y", tee_output.as_str()]);
+    }
+
+    args.into_iter().map(str::to_owned).collect()
+}
+
+/// Returns the output specification for the FFmpeg `tee` muxer which writes into `filename` and
+/// all `tee_targets`.
+///
+/// Muxer options which would otherwise go on the command line are set for `filename` only, since
+/// the targets have formats of their own.
+fn tee_output_spec(
+    filename: &str,
+    container: Option<&str>,
+    movflags: &str,
+    tee_targets: &[TeeTarget],
+) -> String {
+    let mut options = Vec::new();
+    if let Some(container) = container {
+        options.push(format!("f={container}"));
+    }
+    if supports_fragmenting(filename) {
+        options.push(format!("movflags={movflags}"));
+    }
+
+    let mut outputs = Vec::with_capacity(tee_targets.len() + 1);
+    if options.is_empty() {
+        outputs.push(escape_tee(filename));
+    } else {
+        outputs.push(format!("[{}]{}", options.join(":"), escape_tee(filename)));
+    }
+    for target in tee_targets {
+        outputs.push(format!(
+            "[f={}]{}",
+            escape_tee(&target.format),
+            escape_tee(&target.path_or_url)
+        ));
+    }
+    outputs.join("|")
+}
+
+/// Returns `value` escaped for use in an FFmpeg `tee` muxer output specification.
+fn escape_tee(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '\'' | '|' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Returns the FFmpeg program and its arguments for encoding the muxed audio-only stream into
+/// `filename`.
+///
+/// The arguments are the same as in [`ffmpeg_command`] but without any video settings.
+fn audio_only_ffmpeg_command(
+    filename: &str,
+    extra_ffmpeg_args: &[String],
+    loudness_lufs: Option<f32>,
+) -> Vec<String> {
+    #[rustfmt::skip]
+    let mut args = vec![
+        "ffmpeg",
+        "-loglevel", "error",
+        "-f", "nut",
+        "-i", "pipe:",
+    ];
+
+    let audio_filter = loudness_lufs.map(|lufs| format!("loudnorm=I={lufs}"));
+    if let Some(audio_filter) = &audio_filter {
+        args.extend_from_slice(&["-af", audio_filter.as_str()]);
+    }
+
+    args.extend(extra_ffmpeg_args.iter().map(String::as_str));
+    args.extend_from_slice(&["-y", filename]);
+
+    args.into_iter().map(str::to_owned).collect()
+}
+
 impl Muxer {
     #[instrument(name = "Muxer::new")]
     pub fn new(
         width: u64,
         height: u64,
-        fps: u64,
+        fps_num: u64,
+        fps_den: u64,
         pixel_format: PixelFormat,
         filename: &str,
-        custom_ffmpeg_args: Option<&[&str]>,
+        options: &MuxerOptions,
     ) -> Result<Self, MuxerInitError> {
-        #[rustfmt::skip]
-        let mut args = vec![
-            "-loglevel", "error",
-            "-f", "nut",
-            "-i", "pipe:"
-        ];
-
-        if pixel_format == PixelFormat::Rgb24Flipped {
-            args.extend_from_slice(&["-vf", "vflip"]);
+        if options.fragmented && !supports_fragmenting(filename) {
+            return Err(MuxerInitError::FragmentedContainer);
         }
 
-        if let Some(custom_ffmpeg_args) = custom_ffmpeg_args {
-            args.extend_from_slice(custom_ffmpeg_args);
-        } else {
-            #[rustfmt::skip]
-            args.extend_from_slice(&[
-                "-c:v", "libx264",
-                "-crf", "15",
-                "-preset", "ultrafast",
-                "-color_primaries", "bt709",
-                "-color_trc", "bt709",
-                "-colorspace", "bt709",
-                "-color_range", "tv",
-                "-chroma_sample_location", "center",
-            ]);
-        }
-
-        #[rustfmt::skip]
-        args.extend_from_slice(&[
-            "-movflags", "+faststart",
-            "-y",
-            filename,
-        ]);
+        // A missing hardware encoder only shows up once FFmpeg starts encoding, at which point the
+        // recording would just stop, so check for it upfront.
+        let effective_encoder = options.effective_encoder();
 
-        let mut command = Command::new("ffmpeg");
-        command
-            .args(&args)
-            .env_remove("LD_PRELOAD") // So there's no ld.so complaining in the output.
-            .stdin(Stdio::piped())
-            .stdout(Stdio::null())
-            .stderr(Stdio::piped());
-
-        #[cfg(windows)]
-        command.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
-
-        let mut child = info_span!("spawn")
-            .in_scope(|| command.spawn())
-            .map_err(MuxerInitError::FfmpegSpawn)?;
-        let mut writer = child.stdin.as_mut().unwrap();
+        let output_format = if options.tee_targets.is_empty() {
+            effective_encoder.as_ref().and_then(Encoder::container)
+        } else {
+            Some("tee")
+        };
+        check_extra_ffmpeg_args(filename, options.extra_ffmpeg_args, output_format)?;
 
-        const MAIN_STARTCODE: u64 = 0x4e4d7a561f5f04ad;
-        const STREAM_STARTCODE: u64 = 0x4e5311405bf2f9db;
+        if let Some(encoder) = &effective_encoder {
+            if let Some(container) = encoder.container() {
+                if !has_extension(filename, container) {
+                    return Err(MuxerInitError::ContainerMismatch(encoder.clone()));
+                }
+            }
+        }
+        if let Some(encoder) = effective_encoder
+            .as_ref()
+            .filter(|&encoder| *encoder != Encoder::X264)
+        {
+            encoder.check_device()?;
 
-        writer.write_all(b"nut/multimedia container\0")?;
+            if !probe_encoder(encoder, options.codec, options.ffmpeg_path) {
+                return Err(MuxerInitError::EncoderUnavailable(encoder.clone()));
+            }
+        }
 
-        // Main header.
-        let mut buf = Vec::new();
-        v(&mut buf, 3)?; // version
-        v(&mut buf, 2)?; // stream_count
-        v(&mut buf, 65536)?; // max_distance
-        v(&mut buf, 2)?; // time_base_count
-        v(&mut buf, 1)?; // time_base_num
-        v(&mut buf, fps)?; // time_base_denom
-        v(&mut buf, 1)?; // time_base_num
-        v(&mut buf, 22050)?; // time_base_denom
+        let mut command_line = ffmpeg_command(pixel_format, fps_num, fps_den, filename, options);
+        command_line[0] = ffmpeg_program(options.ffmpeg_path);
 
-        for _ in 0..255 {
-            // Not 256 because 'N' is skipped.
-            v(&mut buf, 1 << 12)?; // tmp_flag = FLAG_CODED
-            v(&mut buf, 0)?; // tmp_fields
-        }
+        let (child, stderr) = spawn_ffmpeg(&command_line, |writer| {
+            write_header(
+                writer,
+                width,
+                height,
+                fps_num,
+                fps_den,
+                pixel_format,
+                !options.no_sound,
+            )
+        })?;
 
-        v(&mut buf, 0)?; // header_count_minus1
-        v(&mut buf, 0)?; // main_flags
+        Ok(Self {
+            child,
+            stderr: Some(stderr),
+            failed_output: None,
+            command: command_line,
+            encoder: effective_encoder,
+            audio_only: false,
+            capture_sound: !options.no_sound,
+            video_pts: 0,
+            audio_pts: 0,
+        })
+    }
 
-        packet(&mut writer, MAIN_STARTCODE, &buf)?;
+    /// Starts muxing only the audio into `filename`.
+    ///
+    /// The output format and the audio codec are picked by FFmpeg from the file extension, such
+    /// as `.wav`, `.flac` or `.opus`. Writing video frames into this muxer fails.
+    #[instrument(name = "Muxer::new_audio_only")]
+    pub fn new_audio_only(
+        filename: &str,
+        extra_ffmpeg_args: &[String],
+        loudness_lufs: Option<f32>,
+        ffmpeg_path: Option<&Path>,
+    ) -> Result<Self, MuxerInitError> {
+        check_extra_ffmpeg_args(filename, extra_ffmpeg_args, None)?;
 
-        // Stream header (video).
-        buf.clear();
-        v(&mut buf, 0)?; // stream_id
-        v(&mut buf, 0)?; // stream_class = video
-        vb(&mut buf, pixel_format.fourcc())?; // fourcc
-        v(&mut buf, 0)?; // time_base_id
-        v(&mut buf, 0)?; // msb_pts_shift
-        v(&mut buf, 1)?; // max_pts_distance
-        v(&mut buf, 0)?; // decode_delay
-        v(&mut buf, 1)?; // stream_flags = FLAG_FIXED_FPS
-        vb(&mut buf, &[])?; // codec_specific_data
-
-        v(&mut buf, width)?; // width
-        v(&mut buf, height)?; // height
-        v(&mut buf, 1)?; // sample_width
-        v(&mut buf, 1)?; // sample_height
-        v(&mut buf, 2)?; // colorspace_type = narrow-range 709
-
-        packet(&mut writer, STREAM_STARTCODE, &buf)?;
-
-        // Stream header (audio).
-        buf.clear();
-        v(&mut buf, 1)?; // stream_id
-        v(&mut buf, 1)?; // stream_class = audio
-        vb(&mut buf, b"PSD\x10")?; // fourcc = little-endian signed interleaved 16-bit
-        v(&mut buf, 1)?; // time_base_id
-        v(&mut buf, 0)?; // msb_pts_shift
-        v(&mut buf, 1)?; // max_pts_distance
-        v(&mut buf, 0)?; // decode_delay
-        v(&mut buf, 1)?; // stream_flags = FLAG_FIXED_FPS
-        vb(&mut buf, &[])?; // codec_specific_data
-
-        v(&mut buf, 22050)?; // samplerate_num
-        v(&mut buf, 1)?; // samplerate_denom
-        v(&mut buf, 2)?; // channel_count
-
-        packet(&mut writer, STREAM_STARTCODE, &buf)?;
+        let mut command_line =
+            audio_only_ffmpeg_command(filename, extra_ffmpeg_args, loudness_lufs);
+        command_line[0] = ffmpeg_program(ffmpeg_path);
+        let (child, stderr) = spawn_ffmpeg(&command_line, write_audio_only_header)?;
 
         Ok(Self {
             child,
+            stderr: Some(stderr),
+            failed_output: None,
+            command: command_line,
+            encoder: None,
+            audio_only: true,
+            capture_sound: true,
             video_pts: 0,
             audio_pts: 0,
         })
@@ -229,9 +929,21 @@ impl Muxer {
 
     #[instrument(name = "Muxer::write_video_frame", skip_all)]
     pub fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        if self.audio_only {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the muxer has no video stream",
+            ));
+        }
+
+        self.write_video_packets(data)
+            .map_err(|err| self.with_ffmpeg_output(err))
+    }
+
+    fn write_video_packets(&mut self, data: &[u8]) -> Result<(), io::Error> {
         const SYNCPOINT_STARTCODE: u64 = 0x4e4be4adeeca4569;
 
-        let mut writer = self.child.stdin.as_mut().unwrap();
+        let mut writer = ffmpeg_stdin(&mut self.child)?;
 
         // Syncpoint.
         let mut buf = Vec::new();
@@ -271,13 +983,29 @@ impl Muxer {
 
     #[instrument(name = "Muxer::write_audio_frame", skip_all)]
     pub fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        if !self.capture_sound {
+            return Ok(());
+        }
+
+        self.write_audio_packets(data)
+            .map_err(|err| self.with_ffmpeg_output(err))
+    }
+
+    fn write_audio_packets(&mut self, data: &[u8]) -> Result<(), io::Error> {
         const SYNCPOINT_STARTCODE: u64 = 0x4e4be4adeeca4569;
 
-        let mut writer = self.child.stdin.as_mut().unwrap();
+        let mut writer = ffmpeg_stdin(&mut self.child)?;
+
+        // Without the video, the audio is stream 0 and has the only time base.
+        let (stream_id, global_key_pts) = if self.audio_only {
+            (0, self.audio_pts)
+        } else {
+            (1, self.audio_pts * 2 + 1)
+        };
 
         // Syncpoint.
         let mut buf = Vec::new();
-        v(&mut buf, self.audio_pts * 2 + 1)?; // global_key_pts
+        v(&mut buf, global_key_pts)?; // global_key_pts
         v(&mut buf, 0)?; // back_ptr_div16, ???
 
         packet(&mut writer, SYNCPOINT_STARTCODE, &buf)?;
@@ -294,7 +1022,7 @@ impl Muxer {
             | (1 << 6) // FLAG_CHECKSUM
             ;
         v(&mut buf, flags)?; // coded_flags
-        v(&mut buf, 1)?; // stream_id
+        v(&mut buf, stream_id)?; // stream_id
         v(&mut buf, self.audio_pts + (1 << 0))?; // coded_pts = pts + (1 << msb_pts_shift)
         v(&mut buf, data.len() as u64)?; // data_size_msb
 
@@ -308,10 +1036,1043 @@ impl Muxer {
         Ok(())
     }
 
+    /// Returns the encoders that the installed FFmpeg build supports, with their default settings.
+    ///
+    /// This only checks that FFmpeg was built with the encoders, not that the hardware for them is
+    /// present.
+    #[instrument(name = "Muxer::detect_encoders")]
+    pub fn detect_encoders(ffmpeg_path: Option<&Path>) -> eyre::Result<Vec<Encoder>> {
+        let mut command = Command::new(ffmpeg_program(ffmpeg_path));
+        command
+            .args(["-hide_banner", "-encoders"])
+            .env_remove("LD_PRELOAD")
+            .stdin(Stdio::null())
+            .stderr(Stdio::null());
+
+        #[cfg(windows)]
+        command.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
+
+        let output = command.output().wrap_err("could not run ffmpeg")?;
+        ensure!(
+            output.status.success(),
+            "ffmpeg -encoders exited with {}",
+            output.status
+        );
+
+        Ok(parse_encoders(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Returns the encoder that this muxer uses, or [`None`] if it was given custom arguments.
+    pub fn encoder(&self) -> Option<&Encoder> {
+        self.encoder.as_ref()
+    }
+
+    /// Returns the FFmpeg program and arguments that this muxer was started with.
+    pub fn command(&self) -> &[String] {
+        &self.command
+    }
+
+    /// Stops FFmpeg after a failed write and returns `err` with the end of FFmpeg's output.
+    ///
+    /// A write usually fails because FFmpeg has exited, and the reason is in its output.
+    fn with_ffmpeg_output(&mut self, err: io::Error) -> io::Error {
+        if self.failed_output.is_some() {
+            // Already reported.
+            return err;
+        }
+
+        // Nothing more can be written, and FFmpeg won't exit until its input is closed.
+        drop(self.child.stdin.take());
+        let status = self.child.wait();
+        let output = self.stderr_output();
+
+        let message = match status {
+            Ok(status) => format!(
+                "{err}; ffmpeg exited with {status}:\n{}",
+                last_lines(&output, FFMPEG_ERROR_LINES)
+            ),
+            Err(_) => format!("{err}:\n{}", last_lines(&output, FFMPEG_ERROR_LINES)),
+        };
+        self.failed_output = Some(output);
+        io::Error::new(err.kind(), message)
+    }
+
+    /// Waits for FFmpeg to close its standard error and returns everything it wrote there.
+    fn stderr_output(&mut self) -> String {
+        self.stderr
+            .take()
+            .map(|thread| thread.join().unwrap_or_default())
+            .unwrap_or_default()
+    }
+
     /// Waits for the child process to exit and returns its output.
+    ///
+    /// Returns an error with the end of the output if FFmpeg failed. If writing has already failed,
+    /// the error was returned then, so the output is returned as is.
     #[instrument(name = "Muxer::close", skip_all)]
-    pub fn close(self) -> String {
-        let output = self.child.wait_with_output().unwrap();
-        String::from_utf8_lossy(&output.stderr).into_owned()
+    pub fn close(mut self) -> Result<String, io::Error> {
+        if let Some(output) = self.failed_output.take() {
+            return Ok(output);
+        }
+
+        drop(self.child.stdin.take());
+        let status = self.child.wait()?;
+        let output = self.stderr_output();
+
+        if !status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "ffmpeg exited with {status}:\n{}",
+                    last_lines(&output, FFMPEG_ERROR_LINES)
+                ),
+            ));
+        }
+
+        Ok(output)
+    }
+}
+
+/// Returns an error if the extra FFmpeg arguments would break the recording.
+///
+/// The extra arguments go right before the output file, so they must not contain the output file
+/// itself. They also can't set the format if the command sets `output_format` for the output,
+/// since one of the two would be silently ignored.
+fn check_extra_ffmpeg_args(
+    filename: &str,
+    extra_ffmpeg_args: &[String],
+    output_format: Option<&'static str>,
+) -> Result<(), MuxerInitError> {
+    if extra_ffmpeg_args
+        .iter()
+        .any(|arg| Path::new(arg) == Path::new(filename))
+    {
+        return Err(MuxerInitError::OutputInExtraArgs);
+    }
+
+    if let Some(format) = output_format {
+        // Options can have a stream specifier, like `-f:v`.
+        if let Some(arg) = extra_ffmpeg_args
+            .iter()
+            .find(|arg| arg.split(':').next() == Some("-f"))
+        {
+            return Err(MuxerInitError::FormatInExtraArgs {
+                option: arg.clone(),
+                format,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// FFmpeg program which is run when no path to it is set.
+///
+/// On Windows this finds `ffmpeg.exe` in the Half-Life folder, elsewhere it's looked up in `PATH`.
+const DEFAULT_FFMPEG: &str = "ffmpeg";
+
+/// Returns the FFmpeg program to run: `path` if it's set, otherwise the platform default.
+pub fn ffmpeg_program(path: Option<&Path>) -> String {
+    path.map_or_else(
+        || DEFAULT_FFMPEG.to_owned(),
+        |path| path.to_string_lossy().into_owned(),
+    )
+}
+
+/// Returns FFmpeg's standard input, or an error if writing into it has already failed.
+fn ffmpeg_stdin(child: &mut Child) -> Result<&mut ChildStdin, io::Error> {
+    child
+        .stdin
+        .as_mut()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "ffmpeg has exited"))
+}
+
+/// Number of lines at the end of FFmpeg's output to include in errors.
+const FFMPEG_ERROR_LINES: usize = 20;
+
+/// Returns the last `count` non-empty lines of `output`.
+fn last_lines(output: &str, count: usize) -> String {
+    let lines = output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(count)..].join("\n")
+}
+
+/// Reads `stderr` to the end on a separate thread and returns everything read.
+///
+/// FFmpeg blocks when the pipe of its standard error is full, so reading it only after FFmpeg exits
+/// could deadlock with us blocking on writing its standard input.
+fn read_stderr(mut stderr: impl Read + Send + 'static) -> JoinHandle<String> {
+    thread::Builder::new()
+        .name("FFmpeg Output Thread".to_string())
+        .spawn(move || {
+            let mut output = Vec::new();
+            // On errors, return what was read so far.
+            let _ = stderr.read_to_end(&mut output);
+            String::from_utf8_lossy(&output).into_owned()
+        })
+        .unwrap()
+}
+
+/// Spawns FFmpeg with `command_line` and writes the stream header into its input.
+///
+/// Returns FFmpeg and the thread reading its standard error.
+fn spawn_ffmpeg(
+    command_line: &[String],
+    write_header: impl FnOnce(&mut ChildStdin) -> Result<(), io::Error>,
+) -> Result<(Child, JoinHandle<String>), MuxerInitError> {
+    let mut command = Command::new(&command_line[0]);
+    command
+        .args(&command_line[1..])
+        .env_remove("LD_PRELOAD") // So there's no ld.so complaining in the output.
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+
+    #[cfg(windows)]
+    command.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
+
+    let mut child = info_span!("spawn")
+        .in_scope(|| command.spawn())
+        .map_err(|source| MuxerInitError::FfmpegSpawn {
+            path: command_line[0].clone(),
+            source,
+        })?;
+    let writer = child.stdin.as_mut().unwrap();
+    if let Err(err) = write_header(writer) {
+        // If FFmpeg didn't like the arguments, it has exited and closed the pipe.
+        drop(child.stdin.take());
+        let output = child.wait_with_output()?;
+        if output.status.success() {
+            return Err(err.into());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(MuxerInitError::FfmpegRejected(stderr));
+    }
+
+    let stderr = read_stderr(child.stderr.take().unwrap());
+    Ok((child, stderr))
+}
+
+const STREAM_STARTCODE: u64 = 0x4e5311405bf2f9db;
+
+/// Writes the NUT file ID and the main header for streams with `time_bases`.
+///
+/// There is one stream per time base.
+fn write_main_header(
+    mut writer: &mut impl Write,
+    time_bases: &[(u64, u64)],
+) -> Result<(), io::Error> {
+    const MAIN_STARTCODE: u64 = 0x4e4d7a561f5f04ad;
+
+    writer.write_all(b"nut/multimedia container\0")?;
+
+    let mut buf = Vec::new();
+    v(&mut buf, 3)?; // version
+    v(&mut buf, time_bases.len() as u64)?; // stream_count
+    v(&mut buf, 65536)?; // max_distance
+    v(&mut buf, time_bases.len() as u64)?; // time_base_count
+    for &(num, denom) in time_bases {
+        v(&mut buf, num)?; // time_base_num
+        v(&mut buf, denom)?; // time_base_denom
+    }
+
+    for _ in 0..255 {
+        // Not 256 because 'N' is skipped.
+        v(&mut buf, 1 << 12)?; // tmp_flag = FLAG_CODED
+        v(&mut buf, 0)?; // tmp_fields
+    }
+
+    v(&mut buf, 0)?; // header_count_minus1
+    v(&mut buf, 0)?; // main_flags
+
+    packet(&mut writer, MAIN_STARTCODE, &buf)
+}
+
+/// Writes the NUT stream header of the audio with the given stream and time base indices.
+fn write_audio_stream_header(
+    mut writer: &mut impl Write,
+    stream_id: u64,
+    time_base_id: u64,
+) -> Result<(), io::Error> {
+    let mut buf = Vec::new();
+    v(&mut buf, stream_id)?; // stream_id
+    v(&mut buf, 1)?; // stream_class = audio
+    vb(&mut buf, b"PSD\x10")?; // fourcc = little-endian signed interleaved 16-bit
+    v(&mut buf, time_base_id)?; // time_base_id
+    v(&mut buf, 0)?; // msb_pts_shift
+    v(&mut buf, 1)?; // max_pts_distance
+    v(&mut buf, 0)?; // decode_delay
+    v(&mut buf, 1)?; // stream_flags = FLAG_FIXED_FPS
+    vb(&mut buf, &[])?; // codec_specific_data
+
+    v(&mut buf, 22050)?; // samplerate_num
+    v(&mut buf, 1)?; // samplerate_denom
+    v(&mut buf, 2)?; // channel_count
+
+    packet(&mut writer, STREAM_STARTCODE, &buf)
+}
+
+/// Writes the NUT main header and the video stream header, and the audio stream header if
+/// `capture_sound` is set.
+fn write_header(
+    mut writer: &mut impl Write,
+    width: u64,
+    height: u64,
+    fps_num: u64,
+    fps_den: u64,
+    pixel_format: PixelFormat,
+    capture_sound: bool,
+) -> Result<(), io::Error> {
+    if capture_sound {
+        write_main_header(writer, &[(fps_den, fps_num), (1, 22050)])?;
+    } else {
+        write_main_header(writer, &[(fps_den, fps_num)])?;
+    }
+
+    // Stream header (video).
+    let mut buf = Vec::new();
+    v(&mut buf, 0)?; // stream_id
+    v(&mut buf, 0)?; // stream_class = video
+    vb(&mut buf, pixel_format.fourcc())?; // fourcc
+    v(&mut buf, 0)?; // time_base_id
+    v(&mut buf, 0)?; // msb_pts_shift
+    v(&mut buf, 1)?; // max_pts_distance
+    v(&mut buf, 0)?; // decode_delay
+    v(&mut buf, 1)?; // stream_flags = FLAG_FIXED_FPS
+    vb(&mut buf, &[])?; // codec_specific_data
+
+    v(&mut buf, width)?; // width
+    v(&mut buf, height)?; // height
+    v(&mut buf, 1)?; // sample_width
+    v(&mut buf, 1)?; // sample_height
+    v(&mut buf, 2)?; // colorspace_type = narrow-range 709
+
+    packet(&mut writer, STREAM_STARTCODE, &buf)?;
+
+    if !capture_sound {
+        return Ok(());
+    }
+
+    // Stream header (audio).
+    write_audio_stream_header(writer, 1, 1)
+}
+
+/// Writes the NUT main header and the audio stream header, with no video stream.
+fn write_audio_only_header(writer: &mut impl Write) -> Result<(), io::Error> {
+    write_main_header(writer, &[(1, 22050)])?;
+    write_audio_stream_header(writer, 0, 0)
+}
+
+impl FrameSink for Muxer {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        Muxer::write_video_frame(self, data)
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        Muxer::write_audio_frame(self, data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        Muxer::close(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ffmpeg_command_contains_output() {
+        let command = ffmpeg_command(
+            PixelFormat::I420,
+            60,
+            1,
+            "output.mp4",
+            &MuxerOptions::default(),
+        );
+        assert_eq!(command[0], "ffmpeg");
+        assert_eq!(command.last().unwrap(), "output.mp4");
+        assert!(command.iter().any(|arg| arg == "libx264"));
+
+        let command = ffmpeg_command(
+            PixelFormat::Rgb24Flipped,
+            60,
+            1,
+            "output.mp4",
+            &MuxerOptions {
+                custom_ffmpeg_args: Some(&["-c:v", "libx265"]),
+                ..Default::default()
+            },
+        );
+        assert!(command.windows(2).any(|args| args == ["-vf", "vflip"]));
+        assert!(command.windows(2).any(|args| args == ["-c:v", "libx265"]));
+        assert!(!command.iter().any(|arg| arg == "libx264"));
+    }
+
+    #[test]
+    fn ffmpeg_command_draws_hud() {
+        let command = ffmpeg_command(
+            PixelFormat::Rgb24Flipped,
+            60,
+            1,
+            "output.mp4",
+            &MuxerOptions {
+                hud_subtitles: Some(Path::new("output.mp4.hud.srt")),
+                ..Default::default()
+            },
+        );
+        assert!(command
+            .windows(2)
+            .any(|args| args == ["-vf", "vflip,subtitles='output.mp4.hud.srt'"]));
+    }
+
+    #[test]
+    fn ffmpeg_command_burns_timecode() {
+        let command = ffmpeg_command(
+            PixelFormat::I420,
+            24000,
+            1001,
+            "output.mp4",
+            &MuxerOptions {
+                hud_subtitles: Some(Path::new("output.mp4.hud.srt")),
+                burn_timecode: true,
+                ..Default::default()
+            },
+        );
+        let filters = &command[command.iter().position(|arg| arg == "-vf").unwrap() + 1];
+        assert_eq!(
+            filters,
+            "subtitles='output.mp4.hud.srt',\
+            drawtext=timecode='00\\:00\\:00\\:00':rate=24000/1001:\
+            x=8:y=8:fontsize=32:fontcolor=white:box=1:boxcolor=black@0.5"
+        );
+    }
+
+    #[test]
+    fn hud_subtitles_per_frame() {
+        let lines = ["Frame: 1".to_owned(), "Frame: 2".to_owned()];
+        assert_eq!(
+            hud_subtitles(&lines, 50, 1),
+            "1\n00:00:00,000 --> 00:00:00,020\nFrame: 1\n\n\
+             2\n00:00:00,020 --> 00:00:00,040\nFrame: 2\n\n"
+        );
+    }
+
+    #[test]
+    fn fragmented_output() {
+        let command = ffmpeg_command(
+            PixelFormat::I420,
+            60,
+            1,
+            "output.mp4",
+            &MuxerOptions {
+                fragmented: true,
+                ..Default::default()
+            },
+        );
+        assert!(command
+            .windows(2)
+            .any(|args| args == ["-movflags", "frag_keyframe+empty_moov"]));
+        assert!(!command.iter().any(|arg| arg == "+faststart"));
+
+        assert!(supports_fragmenting("output.MOV"));
+        assert!(matches!(
+            Muxer::new(
+                64,
+                64,
+                60,
+                1,
+                PixelFormat::I420,
+                "output.mkv",
+                &MuxerOptions {
+                    fragmented: true,
+                    ..Default::default()
+                },
+            ),
+            Err(MuxerInitError::FragmentedContainer)
+        ));
+    }
+
+    #[test]
+    fn ffmpeg_command_normalizes_loudness() {
+        let command = ffmpeg_command(
+            PixelFormat::I420,
+            60,
+            1,
+            "output.mp4",
+            &MuxerOptions {
+                loudness_lufs: Some(-16.),
+                ..Default::default()
+            },
+        );
+        assert!(command
+            .windows(2)
+            .any(|args| args == ["-af", "loudnorm=I=-16"]));
+
+        let command = ffmpeg_command(
+            PixelFormat::I420,
+            60,
+            1,
+            "output.mp4",
+            &MuxerOptions::default(),
+        );
+        assert!(!command.iter().any(|arg| arg == "-af"));
+    }
+
+    #[test]
+    fn ffmpeg_command_per_codec() {
+        let codec_args = |codec, pixel_format| {
+            let command = ffmpeg_command(
+                pixel_format,
+                60,
+                1,
+                "output.mkv",
+                &MuxerOptions {
+                    codec,
+                    ..Default::default()
+                },
+            );
+            let output = command.len() - 4;
+            let input = command.iter().position(|arg| arg == "pipe:").unwrap() + 1;
+            let args = command[input..output]
+                .iter()
+                .skip_while(|arg| *arg != "-c:v")
+                .cloned()
+                .collect::<Vec<_>>();
+            assert_eq!(
+                command[output..],
+                ["-movflags", "+faststart", "-y", "output.mkv"]
+            );
+            args
+        };
+
+        let color_args = [
+            "-color_primaries",
+            "bt709",
+            "-color_trc",
+            "bt709",
+            "-colorspace",
+            "bt709",
+            "-color_range",
+            "tv",
+            "-chroma_sample_location",
+            "center",
+        ];
+        let with_color = |args: &[&'static str]| [args, &color_args].concat();
+
+        assert_eq!(
+            codec_args(VideoCodec::H264, PixelFormat::I420),
+            with_color(&["-c:v", "libx264", "-crf", "15", "-preset", "ultrafast"]),
+        );
+        assert_eq!(
+            codec_args(VideoCodec::H265, PixelFormat::I420),
+            with_color(&[
+                "-c:v",
+                "libx265",
+                "-crf",
+                "15",
+                "-preset",
+                "ultrafast",
+                "-pix_fmt",
+                "yuv420p",
+            ]),
+        );
+        assert_eq!(
+            codec_args(VideoCodec::Vp9, PixelFormat::I420),
+            with_color(&[
+                "-c:v",
+                "libvpx-vp9",
+                "-crf",
+                "15",
+                "-b:v",
+                "0",
+                "-deadline",
+                "realtime",
+                "-cpu-used",
+                "8",
+                "-pix_fmt",
+                "yuv420p",
+            ]),
+        );
+        assert_eq!(
+            codec_args(VideoCodec::Ffv1, PixelFormat::I420),
+            with_color(&["-c:v", "ffv1", "-level", "3", "-pix_fmt", "yuv420p"]),
+        );
+        assert_eq!(
+            codec_args(VideoCodec::Ffv1, PixelFormat::Rgb24Flipped),
+            ["-c:v", "ffv1", "-level", "3", "-pix_fmt", "bgr0"],
+        );
+
+        assert_eq!(VideoCodec::from_name("VP9"), Some(VideoCodec::Vp9));
+        assert_eq!(VideoCodec::from_name("av1"), None);
+    }
+
+    #[test]
+    fn ffmpeg_command_per_encoder() {
+        let command = |codec, encoder| {
+            ffmpeg_command(
+                PixelFormat::I420,
+                60,
+                1,
+                "output.mp4",
+                &MuxerOptions {
+                    codec,
+                    encoder,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let nvenc = Encoder::from_name("nvenc").unwrap();
+        let command_line = command(VideoCodec::H265, nvenc.clone());
+        assert!(command_line
+            .windows(2)
+            .any(|args| args == ["-c:v", "hevc_nvenc"]));
+        assert!(command_line.windows(2).any(|args| args == ["-cq", "15"]));
+        assert!(command_line
+            .windows(2)
+            .any(|args| args == ["-pix_fmt", "nv12"]));
+
+        let nvenc_bitrate = Encoder::Nvenc {
+            preset: 9,
+            bitrate_kbps: Some(20000),
+        };
+        let command_line = command(VideoCodec::H264, nvenc_bitrate);
+        assert!(command_line
+            .windows(2)
+            .any(|args| args == ["-preset", "p7"]));
+        assert!(command_line
+            .windows(2)
+            .any(|args| args == ["-b:v", "20000k"]));
+        assert!(!command_line.iter().any(|arg| arg == "-cq"));
+
+        #[cfg(unix)]
+        {
+            let vaapi = command(VideoCodec::H264, Encoder::from_name("vaapi").unwrap());
+            assert_eq!(
+                vaapi[1..5],
+                ["-loglevel", "error", "-vaapi_device", "/dev/dri/renderD128"]
+            );
+            assert!(vaapi
+                .windows(2)
+                .any(|args| args == ["-vf", "format=nv12,hwupload"]));
+            assert!(vaapi.windows(2).any(|args| args == ["-c:v", "h264_vaapi"]));
+
+            let vaapi = Encoder::from_name("VAAPI:/dev/dri/renderD129").unwrap();
+            assert_eq!(
+                vaapi,
+                Encoder::Vaapi {
+                    device: "/dev/dri/renderD129".to_owned()
+                }
+            );
+
+            let missing = Encoder::Vaapi {
+                device: "/nonexistent/renderD128".to_owned(),
+            };
+            assert!(matches!(
+                missing.check_device(),
+                Err(MuxerInitError::VaapiDevice { .. })
+            ));
+        }
+
+        let qsv = command(VideoCodec::H264, Encoder::QuickSync);
+        assert!(qsv.windows(2).any(|args| args == ["-c:v", "h264_qsv"]));
+
+        // Codecs without hardware support fall back to their software encoders.
+        let vp9 = command(VideoCodec::Vp9, nvenc.clone());
+        assert!(vp9.windows(2).any(|args| args == ["-c:v", "libvpx-vp9"]));
+        let options = MuxerOptions {
+            codec: VideoCodec::Vp9,
+            encoder: nvenc.clone(),
+            ..Default::default()
+        };
+        assert_eq!(options.effective_encoder(), Some(Encoder::X264));
+        let options = MuxerOptions {
+            codec: VideoCodec::H264,
+            encoder: nvenc.clone(),
+            custom_ffmpeg_args: Some(&[]),
+            ..Default::default()
+        };
+        assert_eq!(options.effective_encoder(), None);
+
+        let probe = encoder_probe_command(&nvenc, VideoCodec::H264);
+        assert!(probe.windows(2).any(|args| args == ["-c:v", "h264_nvenc"]));
+        assert_eq!(probe[probe.len() - 3..], ["-f", "null", "-"]);
+
+        assert_eq!(Encoder::from_name("QSV"), Some(Encoder::QuickSync));
+        assert_eq!(Encoder::from_name("amf"), None);
+    }
+
+    #[test]
+    fn ffmpeg_command_extra_args_before_output() {
+        let extra_args = ["-crf".to_owned(), "20".to_owned()];
+        let command = ffmpeg_command(
+            PixelFormat::I420,
+            60,
+            1,
+            "output.mp4",
+            &MuxerOptions {
+                extra_ffmpeg_args: &extra_args,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            command[command.len() - 4..],
+            ["-crf", "20", "-y", "output.mp4"]
+        );
+
+        // The built-in CRF comes first, so the extra one overrides it.
+        assert_eq!(command.iter().filter(|arg| *arg == "-crf").count(), 2);
+
+        assert!(matches!(
+            Muxer::new(
+                64,
+                64,
+                60,
+                1,
+                PixelFormat::I420,
+                "output.mp4",
+                &MuxerOptions {
+                    extra_ffmpeg_args: &[
+                        "-f".to_owned(),
+                        "mp4".to_owned(),
+                        "output.mp4".to_owned()
+                    ],
+                    ..Default::default()
+                },
+            ),
+            Err(MuxerInitError::OutputInExtraArgs)
+        ));
+    }
+
+    #[test]
+    fn intermediate_codecs_force_mov() {
+        let command = |encoder| {
+            ffmpeg_command(
+                PixelFormat::Rgb24Flipped,
+                60,
+                1,
+                "output.mov",
+                &MuxerOptions {
+                    encoder,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let prores = command(Encoder::from_name("prores:2").unwrap());
+        assert!(prores.windows(2).any(|args| args == ["-c:v", "prores_ks"]));
+        assert!(prores.windows(2).any(|args| args == ["-profile:v", "2"]));
+        assert_eq!(
+            prores[prores.len() - 4..],
+            ["-f", "mov", "-y", "output.mov"]
+        );
+
+        let hap = command(Encoder::Hap);
+        assert!(hap.windows(2).any(|args| args == ["-c:v", "hap"]));
+        assert!(!hap.iter().any(|arg| arg == "-color_range"));
+        assert_eq!(hap[hap.len() - 4..], ["-f", "mov", "-y", "output.mov"]);
+
+        assert_eq!(Encoder::from_name("prores:6"), None);
+        assert!(matches!(
+            Muxer::new(
+                64,
+                64,
+                60,
+                1,
+                PixelFormat::I420,
+                "output.mp4",
+                &MuxerOptions {
+                    encoder: Encoder::Hap,
+                    ..Default::default()
+                },
+            ),
+            Err(MuxerInitError::ContainerMismatch(Encoder::Hap))
+        ));
+    }
+
+    #[test]
+    fn parse_encoders_output() {
+        let output = "\
+Encoders:
+ V..... = Video
+ A..... = Audio
+ S..... = Subtitle
+ .F.... = Frame-level multithreading
+ ..S... = Slice-level multithreading
+ ...X.. = Codec is experimental
+ ....B. = Supports draw_horiz_band
+ .....D = Supports direct rendering method 1
+ ------
+ V....D a64multi             Multicolor charset for Commodore 64 (codec a64_multi)
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10 (codec h264)
+ V....D hevc_nvenc           NVIDIA NVENC hevc encoder (codec hevc)
+ V..... h264_vaapi           H.264/AVC (VAAPI) (codec h264)
+ VF.... hap                  Vidvox Hap
+ A....D aac                  AAC (Advanced Audio Coding)
+";
+
+        let mut expected = vec![Encoder::X264, Encoder::from_name("nvenc").unwrap()];
+        #[cfg(unix)]
+        expected.push(Encoder::from_name("vaapi").unwrap());
+        expected.push(Encoder::Hap);
+        assert_eq!(parse_encoders(output), expected);
+
+        assert_eq!(parse_encoders(""), []);
+    }
+
+    #[test]
+    fn audio_only_ffmpeg_command_has_no_video() {
+        let command = audio_only_ffmpeg_command("output.flac", &["-ac".into(), "1".into()], None);
+        assert_eq!(
+            command,
+            [
+                "ffmpeg",
+                "-loglevel",
+                "error",
+                "-f",
+                "nut",
+                "-i",
+                "pipe:",
+                "-ac",
+                "1",
+                "-y",
+                "output.flac"
+            ]
+        );
+
+        let command = audio_only_ffmpeg_command("output.wav", &[], Some(-16.));
+        assert!(command
+            .windows(2)
+            .any(|pair| pair == ["-af", "loudnorm=I=-16"]));
+    }
+
+    #[test]
+    fn header_has_exact_time_base() {
+        let mut header = Vec::new();
+        write_header(&mut header, 2, 2, 30000, 1001, PixelFormat::I420, true).unwrap();
+
+        // version, stream_count, max_distance, time_base_count, then 1001/30000 and 1/22050.
+        let main_header = &header[25 + 8 + 2..];
+        assert_eq!(
+            main_header[..15],
+            [3, 2, 0x84, 0x80, 0x00, 2, 0x87, 0x69, 0x81, 0xea, 0x30, 1, 0x81, 0xac, 0x22]
+        );
+    }
+
+    #[test]
+    fn header_streams() {
+        let mut full = Vec::new();
+        write_header(&mut full, 2, 2, 60, 1, PixelFormat::I420, true).unwrap();
+        let mut video_only = Vec::new();
+        write_header(&mut video_only, 2, 2, 60, 1, PixelFormat::I420, false).unwrap();
+        let mut audio_only = Vec::new();
+        write_audio_only_header(&mut audio_only).unwrap();
+
+        // The main header starts right after the file ID, its startcode and its size.
+        let main_header = |header: &[u8]| header[25 + 8 + 2..][..4].to_vec();
+        assert_eq!(main_header(&full)[..2], [3, 2]);
+        assert_eq!(main_header(&audio_only)[..2], [3, 1]);
+        assert_eq!(main_header(&video_only)[..2], [3, 1]);
+
+        let stream_startcode = STREAM_STARTCODE.to_be_bytes();
+        let stream_count = |header: &[u8]| {
+            header
+                .windows(8)
+                .filter(|window| *window == stream_startcode)
+                .count()
+        };
+        assert_eq!(stream_count(&full), 2);
+        assert_eq!(stream_count(&audio_only), 1);
+        assert_eq!(stream_count(&video_only), 1);
+        // The only stream of the video-only header is the video.
+        assert!(!video_only.windows(4).any(|window| window == b"PSD\x10"));
+    }
+
+    #[test]
+    fn ffmpeg_command_lossless() {
+        let command = ffmpeg_command(
+            PixelFormat::Rgb24Flipped,
+            60,
+            1,
+            "output.mkv",
+            &MuxerOptions {
+                codec: VideoCodec::Ffv1,
+                ..Default::default()
+            },
+        );
+        assert!(command.windows(2).any(|args| args == ["-c:v", "ffv1"]));
+        assert!(command.windows(2).any(|args| args == ["-pix_fmt", "bgr0"]));
+        assert!(!command.iter().any(|arg| arg == "-color_range"));
+        for flag in ["-crf", "-qp", "-cq", "-b:v", "-preset"] {
+            assert!(!command.iter().any(|arg| arg == flag), "{flag}");
+        }
+        assert_eq!(command.last().unwrap(), "output.mkv");
+    }
+
+    #[test]
+    fn ffmpeg_error_has_last_lines() {
+        let output = "line 1\n\nline 2\nline 3\n";
+        assert_eq!(last_lines(output, 2), "line 2\nline 3");
+        assert_eq!(last_lines(output, 5), "line 1\nline 2\nline 3");
+        assert_eq!(last_lines("", 5), "");
+
+        let stderr = read_stderr(io::Cursor::new(b"Unknown encoder 'foo'\n".to_vec()));
+        assert_eq!(stderr.join().unwrap(), "Unknown encoder 'foo'\n");
+    }
+
+    #[test]
+    fn ffmpeg_spawn_error_has_path() {
+        let path = Path::new("missing-dir/custom-ffmpeg");
+        let err = Muxer::new_audio_only("output.wav", &[], None, Some(path))
+            .err()
+            .unwrap();
+        assert!(matches!(err, MuxerInitError::FfmpegSpawn { .. }));
+        assert_eq!(
+            err.to_string(),
+            format!("could not spawn ffmpeg at {}", path.display())
+        );
+
+        assert_eq!(ffmpeg_program(None), "ffmpeg");
+    }
+
+    #[test]
+    fn extra_ffmpeg_args_cannot_clash_with_output_format() {
+        let extra_args = |args: &[&str]| args.iter().map(|&arg| arg.to_owned()).collect::<Vec<_>>();
+
+        assert!(check_extra_ffmpeg_args(
+            "output.mp4",
+            &extra_args(&[
+                "-vf",
+                "eq=contrast=1.1,unsharp",
+                "-x264-params",
+                "aq-mode=3"
+            ]),
+            Some("mov"),
+        )
+        .is_ok());
+
+        // The extra arguments are output options, so they can pick the format or add inputs.
+        assert!(check_extra_ffmpeg_args(
+            "output.mp4",
+            &extra_args(&["-i", "logo.png", "-f", "matroska"]),
+            None,
+        )
+        .is_ok());
+
+        for arg in ["-f", "-f:v"] {
+            for format in ["mov", "tee"] {
+                let err =
+                    check_extra_ffmpeg_args("output.mp4", &extra_args(&[arg, "nut"]), Some(format))
+                        .unwrap_err();
+                assert!(matches!(
+                    &err,
+                    MuxerInitError::FormatInExtraArgs { option, format: found }
+                        if option == arg && found == &format
+                ));
+                assert!(err.to_string().contains(arg));
+                assert!(err.to_string().contains(format));
+            }
+        }
+
+        // The extra arguments are inserted after the encoder settings and right before the output.
+        let extra_args = extra_args(&["-vf", "unsharp", "-x264-params", "aq-mode=3"]);
+        let command = ffmpeg_command(
+            PixelFormat::I420,
+            60,
+            1,
+            "output.mp4",
+            &MuxerOptions {
+                extra_ffmpeg_args: &extra_args,
+                ..Default::default()
+            },
+        );
+        let position = |arg: &str| command.iter().position(|x| x == arg).unwrap();
+        assert!(position("pipe:") < position("-c:v"));
+        assert!(position("-c:v") < position("-movflags"));
+        assert!(position("-movflags") < position("unsharp"));
+        assert_eq!(
+            command[command.len() - 6..],
+            [
+                "-vf",
+                "unsharp",
+                "-x264-params",
+                "aq-mode=3",
+                "-y",
+                "output.mp4"
+            ]
+        );
+    }
+
+    #[test]
+    fn ffmpeg_command_muxes_captions() {
+        let command = |filename| {
+            ffmpeg_command(
+                PixelFormat::I420,
+                60,
+                1,
+                filename,
+                &MuxerOptions {
+                    captions: Some(Path::new("captions.srt")),
+                    ..Default::default()
+                },
+            )
+        };
+
+        let mp4 = command("output.mp4");
+        let inputs = mp4
+            .windows(2)
+            .filter(|args| args[0] == "-i")
+            .map(|args| args[1].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(inputs, ["pipe:", "captions.srt"]);
+        assert!(mp4.windows(2).any(|args| args == ["-map", "1"]));
+        assert!(mp4.windows(2).any(|args| args == ["-c:s", "mov_text"]));
+
+        let mkv = command("output.mkv");
+        assert!(mkv.windows(2).any(|args| args == ["-c:s", "srt"]));
+
+        let webm = command("output.webm");
+        assert!(webm.windows(2).any(|args| args == ["-c:s", "webvtt"]));
+    }
+
+    #[test]
+    fn ffmpeg_command_tees_to_targets() {
+        let targets = [
+            TeeTarget::parse("flv:rtmp://localhost/live/stream").unwrap(),
+            TeeTarget {
+                path_or_url: "C:\\videos\\backup [1].ts".to_owned(),
+                format: "mpegts".to_owned(),
+            },
+        ];
+        let command = ffmpeg_command(
+            PixelFormat::I420,
+            60,
+            1,
+            "output.mp4",
+            &MuxerOptions {
+                tee_targets: &targets,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            command[command.len() - 4..],
+            [
+                "-f",
+                "tee",
+                "-y",
+                "[movflags=+faststart]output.mp4\
+                 |[f=flv]rtmp://localhost/live/stream\
+                 |[f=mpegts]C:\\\\videos\\\\backup \\[1\\].ts"
+            ]
+        );
+        assert!(command.windows(2).any(|args| args == ["-map", "0"]));
+        assert_eq!(command.iter().filter(|arg| *arg == "-movflags").count(), 0);
+
+        assert_eq!(TeeTarget::parse("rtmp"), None);
+        assert_eq!(TeeTarget::parse(":output.ts"), None);
     }
 }
@@ -0,0 +1,374 @@
+//! Settings for the recorder.
+
+use std::path::{Path, PathBuf};
+
+use super::muxer::{Encoder, TeeTarget, VideoCodec};
+use super::vulkan::DeviceSelector;
+
+/// Optional recorder settings.
+#[derive(Debug, Clone, Default)]
+pub struct RecorderOptions {
+    /// Whether to write audio from a separate thread.
+    ///
+    /// By default the recording thread handles both video and audio, so audio can end up waiting
+    /// behind video conversion. With a separate thread, audio doesn't wait for the video at all:
+    /// audio which comes while a video frame is being written goes into the FFmpeg pipe right
+    /// after that frame.
+    pub separate_audio_thread: bool,
+
+    /// Whether to never submit several video frames at once.
+    ///
+    /// Normally when a game frame spans several video frames, it is recorded that many times in
+    /// one go. In low-latency mode every recorded game frame produces at most one video frame, and
+    /// the rest are carried over to the next game frames, which keeps the output close to real
+    /// time at the cost of smoothness. The video catches up with the audio once the game frames
+    /// get shorter than the video frames. Low-latency mode doesn't work with sampling.
+    pub low_latency: bool,
+
+    /// Whether to write a SHA-256 checksum of the output file next to it.
+    ///
+    /// The checksum is written into `<filename>.sha256` in the `sha256sum` format after FFmpeg
+    /// has finished writing the file. With segments, every segment gets its own checksum as soon
+    /// as it's finished.
+    pub write_checksums: bool,
+
+    /// Size budget, in megabytes, to re-encode the recording into in two passes.
+    ///
+    /// Two-pass encoding needs the whole video up front, so the game is first recorded into a
+    /// lossless intermediate file next to the output, which is then re-encoded into the output
+    /// and removed when the recording is finished.
+    pub two_pass_target_size_mb: Option<u64>,
+
+    /// Whether to record a lossless master along with a compressed preview.
+    ///
+    /// The master is written into the output file, and the preview into `<filename>.preview.mp4`
+    /// using the custom FFmpeg arguments if they are set. Both are fed the same frames, but each
+    /// has its own FFmpeg process, so this roughly doubles the encoding load.
+    pub lossless_with_preview: bool,
+
+    /// Whether to record at the film frame rate of 24000/1001 FPS instead of the requested one.
+    ///
+    /// Game frames are spread over the video frames with the usual frame pacing, so with a 60 FPS
+    /// game every video frame shows either every second or every third game frame. Audio is not
+    /// resampled: it follows the in-game time regardless of the video frame rate, so unlike
+    /// 3:2 pulldown for TV there is no 0.1% slowdown and audio stays in sync.
+    pub film_24fps: bool,
+
+    /// Exact frame rate as a numerator and a denominator, such as `(30000, 1001)` for 29.97 FPS.
+    ///
+    /// This overrides the whole-number FPS passed to [`Recorder::init()`]. The frame time comes
+    /// straight from the fraction, so such frame rates don't drift over long recordings. It is
+    /// ignored with [`film_24fps`](Self::film_24fps).
+    pub fps_fraction: Option<(u64, u64)>,
+
+    /// Duration of the fade from black at the start of the recording, in seconds.
+    pub fade_in_seconds: f64,
+
+    /// Duration of the fade to black at the end of the recording, in seconds.
+    ///
+    /// Fading out needs to know when the recording ends, so with any fades the game is first
+    /// recorded into a lossless intermediate file, which is re-encoded with the fades into the
+    /// output once the recording is finished, like with two-pass encoding. The re-encoding uses
+    /// its own encoder settings rather than the custom FFmpeg arguments.
+    pub fade_out_seconds: f64,
+
+    /// HUD text to draw on top of every video frame, one line per frame.
+    ///
+    /// The lines are written as subtitles into `<filename>.hud.srt`, which FFmpeg draws onto the
+    /// video, so the text for every video frame is the line with the same index.
+    pub hud_lines: Option<Vec<String>>,
+
+    /// SRT or WebVTT file to mux into the output as a caption track.
+    ///
+    /// The captions are converted to the subtitle format of the output container. They are
+    /// ignored for image sequences and raw planes.
+    pub captions: Option<PathBuf>,
+
+    /// Whether to write an edit decision list next to the output file.
+    ///
+    /// The list is written into `<filename>.edl` in the CMX 3600 format and contains the whole
+    /// recording as a single clip. Timecodes are drop-frame for 29.97 and 59.94 FPS and
+    /// non-drop-frame otherwise, so with the film frame rate they count 24 frames per second. Other
+    /// fractional frame rates can't be expressed in the list and aren't supported.
+    pub write_edl: bool,
+
+    /// Whether to write unprocessed R, G and B planes instead of encoding a video.
+    ///
+    /// The planes are written into `<filename>.r`, `<filename>.g` and `<filename>.b` as raw
+    /// 8-bit values, one full-resolution plane per frame, rows from top to bottom. Color
+    /// conversion and subsampling are skipped in this mode: the Vulkan conversion is not used and
+    /// the frames are read from the game with `glReadPixels`. Audio is not written, and the
+    /// options which involve FFmpeg are ignored.
+    pub raw_planes: bool,
+
+    /// Whether to apply ordered dithering when converting the frames to 8 bits per channel.
+    ///
+    /// Dithering uses a fixed 4×4 Bayer matrix, so the output stays reproducible. It reduces
+    /// banding in smooth gradients such as skyboxes. Only frames blended with sampling are
+    /// converted, and only with `glReadPixels` capture: the Vulkan color conversion is not
+    /// dithered.
+    pub dither: bool,
+
+    /// Whether to write the output as a fragmented MP4 which can be uploaded while it's being
+    /// recorded.
+    ///
+    /// Only MP4 and MOV output files are supported. The option is ignored when the recording is
+    /// re-encoded at the end.
+    pub fragmented: bool,
+
+    /// Whether to guarantee that no video frames are lost, at the cost of stalling the game.
+    ///
+    /// Frames are queued for the recording thread, and when the queue is full, the game thread
+    /// blocks in [`Recorder::time_passed()`] and [`Recorder::record_last_frame()`] until the
+    /// encoder catches up. Offline mode turns off everything that drops frames instead, such as
+    /// the low-latency mode. This is the right choice for deterministic offline re-encodes, where
+    /// the game can run as fast as the encoder allows.
+    pub offline: bool,
+
+    /// Reference recording to compare the output against once the recording is finished.
+    ///
+    /// FFmpeg computes the SSIM and the PSNR of every frame of the output against the reference,
+    /// which are written into `<filename>.ssim.log` and `<filename>.psnr.log`. The averages are
+    /// logged. The comparison runs after the recording, so it doesn't slow down the game.
+    pub compare_to: Option<PathBuf>,
+
+    /// Settings for recording a secondary low-resolution, low-FPS proxy for scrubbing in editors.
+    ///
+    /// The proxy is written into `<filename>.proxy.mp4`. It's made from the already converted
+    /// frames, picking them at the proxy FPS and downscaling with nearest-neighbor sampling.
+    pub proxy: Option<ProxyConfig>,
+
+    /// Settings for making a short GIF preview once the recording is finished.
+    ///
+    /// The preview is written into `<filename>.gif`. It's made from the finished recording in two
+    /// FFmpeg passes: the first one generates a palette for the window and the second one uses it
+    /// to encode the GIF.
+    pub gif_preview: Option<GifConfig>,
+
+    /// Loudness to normalize the recorded audio to.
+    ///
+    /// FFmpeg's `loudnorm` filter runs in a single pass while the audio is being encoded, so the
+    /// resulting loudness is approximate.
+    pub loudnorm: Option<LoudnessTarget>,
+
+    /// Settings for blending several game frames into every video frame.
+    ///
+    /// This overrides the sampling exposure and minimum FPS passed to [`Recorder::init()`].
+    pub motion_blur: Option<MotionBlur>,
+
+    /// Codec for the video.
+    ///
+    /// Ignored when custom FFmpeg arguments are given, and for the intermediate file when the
+    /// recording is re-encoded.
+    pub codec: VideoCodec,
+
+    /// Hardware encoder for H.264 and H.265.
+    ///
+    /// If the FFmpeg build doesn't have it, initialization fails with the list of the available
+    /// encoders. If FFmpeg has it but can't use it, for example because of missing hardware, the
+    /// recording falls back to software encoding.
+    pub encoder: Encoder,
+
+    /// FFmpeg arguments added right before the output file.
+    ///
+    /// These go after all other arguments, including custom FFmpeg arguments, so they override
+    /// them. They apply to the intermediate file when the recording is re-encoded.
+    pub extra_ffmpeg_args: Vec<String>,
+
+    /// Where to write the recording.
+    pub output: OutputTarget,
+
+    /// Whether to draw a running SMPTE timecode in the top-left corner of the video.
+    ///
+    /// The timecode starts at `00:00:00:00` and counts the video frames at the recording frame
+    /// rate. It is drawn on top of the HUD.
+    pub burn_timecode: bool,
+
+    /// Whether to record the video without an audio track.
+    ///
+    /// No audio is captured at all in this case, so the output has only the video stream.
+    pub no_sound: bool,
+
+    /// Difference between consecutive video frames, in `[0; 1]`, above which a new chapter starts.
+    ///
+    /// The difference is the mean absolute difference of every fourth byte of the converted frames,
+    /// so it looks at the luma with the Vulkan capture and at all color channels otherwise. The
+    /// chapters are written into `<filename>.chapters.txt` in the FFmpeg metadata format, which
+    /// can be muxed into the video with `-i <filename>.chapters.txt -map_chapters 1`.
+    pub scene_split_threshold: Option<f32>,
+
+    /// Whether to record losslessly with FFV1 for archival.
+    ///
+    /// The output file must be an MKV. The frames are read from the game with `glReadPixels` and
+    /// encoded as full-range RGB, without the color conversion, so this overrides the codec, the
+    /// encoder and the custom FFmpeg arguments. It is ignored when the recording is re-encoded.
+    pub lossless: bool,
+
+    /// Vulkan physical device to capture on, instead of picking it automatically.
+    ///
+    /// The device must be the one the game renders on. If no device matches, it's picked
+    /// automatically as usual.
+    pub vulkan_device: Option<DeviceSelector>,
+
+    /// Maximum number of audio samples to capture in one call to
+    /// [`Recorder::samples_to_capture()`].
+    ///
+    /// A lag spike or a level load can pass a lot of in-game time at once, which would otherwise
+    /// be captured as a single huge burst of audio. With a cap, the rest is carried over and
+    /// captured over the following calls. Finishing the recording with
+    /// [`SoundCaptureMode::Remaining`] still captures everything that is left.
+    pub max_samples_per_call: Option<u32>,
+
+    /// Path to the FFmpeg executable.
+    ///
+    /// If unset, `ffmpeg.exe` in the Half-Life folder is used on Windows, and `ffmpeg` from `PATH`
+    /// elsewhere.
+    pub ffmpeg_path: Option<PathBuf>,
+
+    /// Number of messages, such as video frames and audio, that can wait for the recording thread.
+    ///
+    /// Defaults to 64.
+    pub queue_size: Option<usize>,
+
+    /// What to do with video frames when the queue for the recording thread is full.
+    pub queue_policy: QueuePolicy,
+
+    /// Length of the output segments in seconds.
+    ///
+    /// The output is split into files numbered from 1, such as `name_0001.mp4` and
+    /// `name_0002.mp4`, each with its own FFmpeg process. Segments end on video frame boundaries,
+    /// and the next one is started right before its first video frame, so the audio which arrives
+    /// in between still goes into the previous segment. Options which work on the whole output
+    /// file, such as re-encoding or the HUD, can't be combined with segmenting.
+    pub segment_seconds: Option<f64>,
+
+    /// Whether to lower the output resolution while the recording thread can't keep up.
+    ///
+    /// When the queue for the recording thread stays close to full for most of a segment, the
+    /// next segment is recorded at a lower resolution, down to half of the game resolution. When
+    /// the queue stays close to empty for a whole segment, the next one goes back up a step. The
+    /// frames are downscaled with nearest-neighbor sampling. This needs
+    /// [`segment_seconds`](Self::segment_seconds), since a single output can't change resolution.
+    pub adaptive_resolution: bool,
+
+    /// Additional outputs to write the encoded recording into, such as a live stream.
+    ///
+    /// The output file and the targets are written with FFmpeg's `tee` muxer, so the video is
+    /// encoded once no matter how many targets there are. Previews and proxies don't go to the
+    /// targets. The targets can't be used when the recording is re-encoded at the end, since
+    /// nothing would be written into them until the recording is over.
+    pub tee_targets: Option<Vec<TeeTarget>>,
+}
+
+/// What to do with video frames when the queue for the recording thread is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueuePolicy {
+    /// Wait for the recording thread, so no frames are lost.
+    #[default]
+    Block,
+    /// Don't wait for the recording thread and drop the newest video frame instead.
+    ///
+    /// A frame is dropped when the queue is full, or when the recording thread still has the pixel
+    /// buffer to capture it into. The dropped frame is replaced by repeating the next frame that
+    /// fits into the queue, so the video stays in sync with the game time. Audio still waits for
+    /// the queue. Only the `glReadPixels` capture without sampling supports dropping frames,
+    /// otherwise this is the same as [`QueuePolicy::Block`]. It's also turned off in offline mode.
+    DropNewest,
+}
+
+/// Settings for motion blur.
+///
+/// Motion blur is done with sampling: the game runs at `subframes` times the recording FPS, and
+/// the game frames are accumulated into every video frame with weights proportional to how much of
+/// the shutter time they cover. Game frames which cover a fraction of a sub-frame get a fraction
+/// of the weight.
+#[derive(Debug, Clone, Copy)]
+pub struct MotionBlur {
+    /// Number of game frames per video frame.
+    ///
+    /// With `1` or less, there's no blending and the recording works the same as without motion
+    /// blur.
+    pub subframes: usize,
+    /// Fraction of the video frame time which is blended together, in `[0; 1]`.
+    pub shutter: f64,
+}
+
+impl MotionBlur {
+    /// Returns `self` if it actually blends frames.
+    pub fn filter_blending(blur: Option<Self>) -> Option<Self> {
+        blur.filter(|blur| blur.subframes > 1)
+    }
+}
+
+/// Loudness to normalize the audio to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessTarget {
+    /// Integrated loudness in LUFS.
+    pub integrated_lufs: f32,
+}
+
+/// Where the recording is written.
+#[derive(Debug, Clone, Default)]
+pub enum OutputTarget {
+    /// Video file encoded by FFmpeg.
+    #[default]
+    Video,
+    /// Numbered lossless images, `frame_00000001.png` and so on, in `dir`.
+    ///
+    /// The audio is written into `audio.wav` in `dir`. Like with [`RecorderOptions::raw_planes`],
+    /// the frames are read from the game with `glReadPixels`, and the options which involve
+    /// FFmpeg are ignored.
+    ImageSequence { dir: PathBuf, format: ImageFormat },
+}
+
+impl OutputTarget {
+    /// Returns the output target for the output file name.
+    ///
+    /// A `.png` or `.tga` file name selects an image sequence in the directory with the same name
+    /// without the extension, for example `frames.png` selects `frames/`. Anything else is a video
+    /// file.
+    pub fn from_filename(filename: &str) -> Self {
+        let path = Path::new(filename);
+        let format = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("png") => ImageFormat::Png,
+            Some(ext) if ext.eq_ignore_ascii_case("tga") => ImageFormat::Tga,
+            _ => return Self::Video,
+        };
+
+        Self::ImageSequence {
+            dir: path.with_extension(""),
+            format,
+        }
+    }
+}
+
+/// Image file format for [`OutputTarget::ImageSequence`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Losslessly compressed PNG.
+    Png,
+    /// Uncompressed TGA.
+    Tga,
+}
+
+/// Settings for the proxy recording.
+#[derive(Debug, Clone, Copy)]
+pub struct ProxyConfig {
+    /// Frame rate of the proxy, must not exceed the recording frame rate.
+    pub fps: u64,
+    /// Scale of the proxy resolution relative to the recording, in `(0; 1]`.
+    pub scale: f32,
+}
+
+/// Settings for the GIF preview.
+#[derive(Debug, Clone, Copy)]
+pub struct GifConfig {
+    /// Start of the window in the recording, in seconds.
+    pub start_seconds: f64,
+    /// Duration of the window, in seconds.
+    pub duration_seconds: f64,
+    /// Width of the GIF, the height is picked to keep the aspect ratio.
+    pub width: u32,
+    /// Frame rate of the GIF.
+    pub fps: u32,
+}
@@ -0,0 +1,489 @@
+//! Passes that run over the output once the recording is finished.
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use color_eyre::eyre::{self, ensure, Context};
+use sha2::{Digest, Sha256};
+
+use super::muxer::filter_path;
+use super::options::GifConfig;
+
+/// Settings for comparing the recording against a reference.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub output: PathBuf,
+    pub reference: PathBuf,
+}
+
+/// Settings for writing an edit decision list for the recording.
+#[derive(Debug, Clone)]
+pub struct Edl {
+    /// Path of the EDL file.
+    pub path: PathBuf,
+    /// Output file that the EDL refers to.
+    pub output: PathBuf,
+    /// Timecode rate of the recording.
+    pub rate: TimecodeRate,
+}
+
+/// Frame rate that the EDL timecodes count at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimecodeRate {
+    /// Frames in one timecode second.
+    pub frames: u64,
+    /// Whether the timecodes skip frame numbers to stay in sync with the 1000/1001 NTSC rates.
+    pub drop_frame: bool,
+}
+
+impl TimecodeRate {
+    /// Returns the timecode rate for the exact frame rate, if EDL timecodes can express it.
+    ///
+    /// Whole frame rates count their own frames. The 1000/1001 rates count at the nominal rate,
+    /// with drop-frame timecodes for the multiples of 29.97 FPS like FFmpeg does.
+    pub fn new(fps_num: u64, fps_den: u64) -> Option<Self> {
+        if fps_num % fps_den == 0 {
+            return Some(Self {
+                frames: fps_num / fps_den,
+                drop_frame: false,
+            });
+        }
+
+        if fps_den == 1001 && fps_num % 1000 == 0 {
+            let frames = fps_num / 1000;
+            return Some(Self {
+                frames,
+                drop_frame: frames % 30 == 0,
+            });
+        }
+
+        None
+    }
+}
+
+/// Settings for making a GIF preview of the recording.
+#[derive(Debug, Clone)]
+pub struct GifPreview {
+    /// Finished recording to make the preview from.
+    pub input: PathBuf,
+    /// Path of the GIF file.
+    pub output: PathBuf,
+    /// Path of the intermediate palette image.
+    pub palette: PathBuf,
+    pub config: GifConfig,
+}
+
+/// Settings for re-encoding the intermediate recording once it's finished.
+#[derive(Debug, Clone)]
+pub struct Reencode {
+    /// Lossless recording that gets re-encoded.
+    pub intermediate: PathBuf,
+    /// Final output file.
+    pub output: PathBuf,
+    /// Size budget for the final output file, in megabytes, to encode in two passes.
+    pub target_size_mb: Option<u64>,
+    /// Duration of the fade from black at the start, in seconds.
+    pub fade_in_seconds: f64,
+    /// Duration of the fade to black at the end, in seconds.
+    pub fade_out_seconds: f64,
+}
+
+/// Bitrate of the audio in the two-pass re-encoded recording, in kbit/s.
+const TWO_PASS_AUDIO_BITRATE: u64 = 128;
+
+/// Returns the FFmpeg commands for every pass of re-encoding a recording `duration` seconds long.
+///
+/// With a size budget there are two passes with the video bitrate picked so that the re-encoded
+/// recording fits into the budget, otherwise there is a single pass.
+pub fn reencode_commands(reencode: &Reencode, duration: f64) -> Vec<Vec<String>> {
+    let intermediate = reencode.intermediate.to_string_lossy().into_owned();
+    let output = reencode.output.to_string_lossy().into_owned();
+
+    let mut filters = Vec::new();
+    if reencode.fade_in_seconds > 0. {
+        filters.push(format!("fade=t=in:st=0:d={}", reencode.fade_in_seconds));
+    }
+    if reencode.fade_out_seconds > 0. {
+        let start = (duration - reencode.fade_out_seconds).max(0.);
+        filters.push(format!(
+            "fade=t=out:st={start}:d={}",
+            reencode.fade_out_seconds
+        ));
+    }
+    let filters = filters.join(",");
+
+    #[rustfmt::skip]
+    let mut common = vec![
+        "ffmpeg",
+        "-loglevel", "error",
+        "-y",
+        "-i", intermediate.as_str(),
+    ];
+    if !filters.is_empty() {
+        common.extend_from_slice(&["-vf", filters.as_str()]);
+    }
+    #[rustfmt::skip]
+    common.extend_from_slice(&[
+        "-c:v", "libx264",
+        "-preset", "slow",
+        "-pix_fmt", "yuv420p",
+    ]);
+
+    let audio_bitrate = format!("{TWO_PASS_AUDIO_BITRATE}k");
+    #[rustfmt::skip]
+    let output_args = [
+        "-c:a", "aac",
+        "-b:a", audio_bitrate.as_str(),
+        "-movflags", "+faststart",
+        output.as_str(),
+    ];
+
+    let Some(target_size_mb) = reencode.target_size_mb else {
+        let mut command = common;
+        command.extend_from_slice(&["-crf", "15"]);
+        command.extend_from_slice(&output_args);
+        return vec![command.into_iter().map(str::to_owned).collect()];
+    };
+
+    // Leave room for the audio, but always give the video something.
+    let total_kbits = target_size_mb as f64 * 8. * 1000.;
+    let video_bitrate =
+        ((total_kbits / duration.max(0.001)) as u64).saturating_sub(TWO_PASS_AUDIO_BITRATE);
+    let video_bitrate = format!("{}k", video_bitrate.max(1));
+    let pass_log = format!("{output}.passlog");
+
+    #[rustfmt::skip]
+    common.extend_from_slice(&[
+        "-b:v", video_bitrate.as_str(),
+        "-passlogfile", pass_log.as_str(),
+    ]);
+
+    #[cfg(unix)]
+    let null_output = "/dev/null";
+    #[cfg(windows)]
+    let null_output = "NUL";
+
+    let mut first = common.clone();
+    #[rustfmt::skip]
+    first.extend_from_slice(&[
+        "-pass", "1",
+        "-an",
+        "-f", "null",
+        null_output,
+    ]);
+
+    let mut second = common;
+    second.extend_from_slice(&["-pass", "2"]);
+    second.extend_from_slice(&output_args);
+
+    [first, second]
+        .into_iter()
+        .map(|args| args.into_iter().map(str::to_owned).collect())
+        .collect()
+}
+
+/// Runs the re-encoding and removes the intermediate files afterwards.
+///
+/// Returns the FFmpeg output.
+pub fn run_reencode(reencode: &Reencode, commands: &[Vec<String>]) -> eyre::Result<String> {
+    let _span = info_span!("run_reencode").entered();
+
+    let mut ffmpeg_output = String::new();
+    for (pass, command) in commands.iter().enumerate() {
+        let output = Command::new(&command[0])
+            .args(&command[1..])
+            .output()
+            .wrap_err("error starting ffmpeg")?;
+
+        ffmpeg_output.push_str(&String::from_utf8_lossy(&output.stdout));
+        ffmpeg_output.push_str(&String::from_utf8_lossy(&output.stderr));
+
+        ensure!(
+            output.status.success(),
+            "pass {} failed ({}), keeping the intermediate recording at {}",
+            pass + 1,
+            output.status,
+            reencode.intermediate.display(),
+        );
+    }
+
+    std::fs::remove_file(&reencode.intermediate)
+        .wrap_err("error removing the intermediate recording")?;
+
+    // x264 writes its statistics next to the pass log file name.
+    let pass_log = format!("{}.passlog", reencode.output.to_string_lossy());
+    for suffix in ["-0.log", "-0.log.mbtree"] {
+        let _ = std::fs::remove_file(format!("{pass_log}{suffix}"));
+    }
+
+    Ok(ffmpeg_output)
+}
+
+/// Returns the FFmpeg commands which generate the palette for the GIF preview and then encode the
+/// GIF with it.
+///
+/// Returns an error if the preview window doesn't fit into `duration`.
+pub fn gif_preview_commands(gif: &GifPreview, duration: f64) -> eyre::Result<Vec<Vec<String>>> {
+    let config = &gif.config;
+    let end = config.start_seconds + config.duration_seconds;
+    ensure!(
+        end <= duration,
+        "GIF preview window from {}s to {}s doesn't fit into the {}s long recording",
+        config.start_seconds,
+        end,
+        duration,
+    );
+
+    let input = gif.input.to_string_lossy().into_owned();
+    let output = gif.output.to_string_lossy().into_owned();
+    let palette = gif.palette.to_string_lossy().into_owned();
+
+    let filters = format!(
+        "trim=start={}:duration={},setpts=PTS-STARTPTS,fps={},scale={}:-1:flags=lanczos",
+        config.start_seconds, config.duration_seconds, config.fps, config.width,
+    );
+    let palettegen = format!("{filters},palettegen");
+    let paletteuse = format!("[0:v]{filters}[preview];[preview][1:v]paletteuse");
+
+    #[rustfmt::skip]
+    let first = [
+        "ffmpeg",
+        "-loglevel", "error",
+        "-y",
+        "-i", input.as_str(),
+        "-vf", palettegen.as_str(),
+        palette.as_str(),
+    ];
+
+    #[rustfmt::skip]
+    let second = [
+        "ffmpeg",
+        "-loglevel", "error",
+        "-y",
+        "-i", input.as_str(),
+        "-i", palette.as_str(),
+        "-lavfi", paletteuse.as_str(),
+        output.as_str(),
+    ];
+
+    Ok([&first[..], &second[..]]
+        .into_iter()
+        .map(|args| args.iter().copied().map(str::to_owned).collect())
+        .collect())
+}
+
+/// Runs the GIF preview passes and removes the palette afterwards.
+pub fn run_gif_preview(gif: &GifPreview, commands: &[Vec<String>]) -> eyre::Result<()> {
+    let _span = info_span!("run_gif_preview").entered();
+
+    for (pass, command) in commands.iter().enumerate() {
+        let output = Command::new(&command[0])
+            .args(&command[1..])
+            .output()
+            .wrap_err("error starting ffmpeg")?;
+
+        ensure!(
+            output.status.success(),
+            "pass {} failed ({}): {}",
+            pass + 1,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        );
+    }
+
+    std::fs::remove_file(&gif.palette).wrap_err("error removing the palette")?;
+
+    Ok(())
+}
+
+/// Returns the FFmpeg command which computes the per-frame SSIM and PSNR of the output against the
+/// reference.
+pub fn comparison_command(comparison: &Comparison) -> Vec<String> {
+    let output = comparison.output.to_string_lossy().into_owned();
+    let reference = comparison.reference.to_string_lossy().into_owned();
+
+    let stats_file = |extension: &str| {
+        let mut path = comparison.output.clone().into_os_string();
+        path.push(extension);
+        filter_path(Path::new(&path))
+    };
+    let filters = format!(
+        "[0:v]split[output1][output2];\
+         [1:v]split[reference1][reference2];\
+         [output1][reference1]ssim=stats_file='{}';\
+         [output2][reference2]psnr=stats_file='{}'",
+        stats_file(".ssim.log"),
+        stats_file(".psnr.log"),
+    );
+
+    #[cfg(unix)]
+    let null_output = "/dev/null";
+    #[cfg(windows)]
+    let null_output = "NUL";
+
+    // The averages are printed at the info log level.
+    #[rustfmt::skip]
+    let args = [
+        "ffmpeg",
+        "-hide_banner",
+        "-nostats",
+        "-i", output.as_str(),
+        "-i", reference.as_str(),
+        "-lavfi", filters.as_str(),
+        "-f", "null",
+        null_output,
+    ];
+
+    args.into_iter().map(str::to_owned).collect()
+}
+
+/// Runs the comparison and returns the lines of the FFmpeg output with the averages.
+pub fn run_comparison(command: &[String]) -> eyre::Result<Vec<String>> {
+    let _span = info_span!("run_comparison").entered();
+
+    let output = Command::new(&command[0])
+        .args(&command[1..])
+        .output()
+        .wrap_err("error starting ffmpeg")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    ensure!(
+        output.status.success(),
+        "ffmpeg failed ({}): {}",
+        output.status,
+        stderr.trim(),
+    );
+
+    Ok(stderr
+        .lines()
+        .filter(|line| line.contains(" SSIM ") || line.contains(" PSNR "))
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Returns a CMX 3600 edit decision list with `output` as a single clip `frames` long.
+pub fn edl_contents(output: &Path, frames: usize, rate: TimecodeRate) -> String {
+    let fps = rate.frames as usize;
+    let timecode = |mut frame: usize| {
+        let separator = if rate.drop_frame {
+            // Skip the first frame numbers of every minute except every tenth one.
+            let dropped = fps / 15;
+            let frames_per_minute = fps * 60 - dropped;
+            let frames_per_10_minutes = fps * 600 - dropped * 9;
+
+            let tens = frame / frames_per_10_minutes;
+            let rest = frame % frames_per_10_minutes;
+            frame += dropped * 9 * tens;
+            if rest > dropped {
+                frame += dropped * ((rest - dropped) / frames_per_minute);
+            }
+
+            ';'
+        } else {
+            ':'
+        };
+
+        let seconds = frame / fps;
+        format!(
+            "{:02}:{:02}:{:02}{separator}{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            frame % fps
+        )
+    };
+    let fcm = if rate.drop_frame {
+        "DROP FRAME"
+    } else {
+        "NON-DROP FRAME"
+    };
+
+    let name = output
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let start = timecode(0);
+    let end = timecode(frames);
+
+    format!(
+        "TITLE: {name}\n\
+         FCM: {fcm}\n\
+         \n\
+         001  AX       B     C        {start} {end} {start} {end}\n\
+         * FROM CLIP NAME: {name}\n"
+    )
+}
+
+/// Returns FFmpeg metadata with a chapter starting at every time in `starts`, in seconds.
+///
+/// The last chapter ends at `duration`.
+pub fn chapters_metadata(starts: &[f64], duration: f64) -> String {
+    let millis = |seconds: f64| (seconds * 1000.).round() as u64;
+
+    let mut metadata = String::from(";FFMETADATA1\n");
+    for (idx, &start) in starts.iter().enumerate() {
+        let end = starts.get(idx + 1).copied().unwrap_or(duration);
+        metadata.push_str(&format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle=Scene {}\n",
+            millis(start),
+            millis(end),
+            idx + 1,
+        ));
+    }
+    metadata
+}
+
+/// Writes the SHA-256 checksum of the file at `path` into `<path>.sha256`.
+pub fn write_checksum(path: &Path) -> eyre::Result<()> {
+    let _span = info_span!("write_checksum").entered();
+
+    let mut hasher = Sha256::new();
+    let mut file = File::open(path).wrap_err("error opening the output file")?;
+    io::copy(&mut file, &mut hasher).wrap_err("error reading the output file")?;
+    let hash = hasher.finalize();
+
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+
+    let mut checksum_path = path.as_os_str().to_owned();
+    checksum_path.push(".sha256");
+    std::fs::write(checksum_path, format!("{hash:x}  {file_name}\n"))
+        .wrap_err("error writing the checksum file")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edl_timecodes_follow_fractional_rates() {
+        let output = Path::new("output.mp4");
+
+        // 29.97 FPS skips frame numbers 00 and 01 at the start of every minute but the tenth.
+        let rate = TimecodeRate::new(30000, 1001).unwrap();
+        assert!(rate.drop_frame);
+        let edl = edl_contents(output, 1800, rate);
+        assert!(edl.contains("FCM: DROP FRAME\n"));
+        assert!(edl.contains("00:00:00;00 00:01:00;02"));
+        assert!(edl_contents(output, 1799, rate).contains("00:00:00;00 00:00:59;29"));
+        assert!(edl_contents(output, 17982, rate).contains("00:00:00;00 00:10:00;00"));
+
+        // 59.94 FPS skips four frame numbers instead.
+        let rate = TimecodeRate::new(60000, 1001).unwrap();
+        assert!(edl_contents(output, 3600, rate).contains("00:00:00;00 00:01:00;04"));
+
+        // Film frame rate counts 24 frames per second without dropping any.
+        let rate = TimecodeRate::new(24000, 1001).unwrap();
+        let edl = edl_contents(output, 1440, rate);
+        assert!(edl.contains("FCM: NON-DROP FRAME\n"));
+        assert!(edl.contains("00:00:00:00 00:01:00:00"));
+
+        assert_eq!(TimecodeRate::new(100, 3), None);
+    }
+}
@@ -1,11 +1,31 @@
+use std::io;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use color_eyre::eyre::{self, ensure, eyre, Context};
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crossbeam_channel::{bounded, Receiver, Sender, TryRecvError};
 use rayon::prelude::*;
 
-use super::muxer::{Muxer, MuxerInitError, PixelFormat};
+use super::muxer::{
+    ffmpeg_program, hud_subtitles, Encoder, FrameSink, Muxer, MuxerInitError, MuxerOptions,
+    PixelFormat, VideoCodec,
+};
 use super::opengl::{self, OpenGl, Uuids};
+use super::options::{MotionBlur, OutputTarget, QueuePolicy, RecorderOptions};
+use super::passes::{
+    chapters_metadata, comparison_command, edl_contents, gif_preview_commands, reencode_commands,
+    run_comparison, run_gif_preview, run_reencode, write_checksum, Comparison, Edl, GifPreview,
+    Reencode, TimecodeRate,
+};
+use super::sinks::{
+    padded_size, proxy_size, segment_filename, AdaptiveResolution, AudioSharedSink, CallbackSink,
+    ImageSequenceSink, Overlay, OverlaySink, PadSink, ProxySink, RawPlanesSink, SceneSink,
+    SegmentSink, Segments, SharedSink, TeeSink,
+};
 use super::vulkan::{self, ExternalHandles, Vulkan};
 use super::SoundCaptureMode;
 use crate::utils::*;
@@ -17,6 +37,9 @@ pub struct Recorder {
     /// Video height.
     height: i32,
 
+    /// Black columns and rows added to the right and bottom to make the video size even.
+    padding: (i32, i32),
+
     /// The target time base.
     time_base: f64,
 
@@ -48,8 +71,8 @@ pub struct Recorder {
     /// Whether Vulkan has already acquired the last frame.
     acquired_image: bool,
 
-    /// Vulkan recording and muxing thread.
-    thread: JoinHandle<()>,
+    /// Vulkan recording and muxing thread, taken when the recording finishes.
+    thread: Option<JoinHandle<()>>,
 
     /// Sender for messages to the thread.
     sender: Sender<MainToThread>,
@@ -57,6 +80,9 @@ pub struct Recorder {
     /// Receiver for messages from the thread.
     receiver: Receiver<ThreadToMain>,
 
+    /// Sender for audio samples to the separate audio thread, if it's used.
+    audio_sender: Option<Sender<Vec<u8>>>,
+
     /// Error from the thread if it sent one.
     thread_error: Option<eyre::Report>,
 
@@ -65,12 +91,176 @@ pub struct Recorder {
 
     /// How we're capturing the frames.
     capture_type: CaptureType,
+
+    /// FFmpeg program and arguments used for encoding.
+    encoder_command: Vec<String>,
+
+    /// Encoder used for the video, if it isn't set by custom FFmpeg arguments.
+    encoder: Option<Encoder>,
+
+    /// Whether to pause the recording while the game window is out of focus.
+    auto_pause_on_focus_loss: bool,
+
+    /// Whether the game window is in focus.
+    focused: bool,
+
+    /// Whether the recording was paused with [`Recorder::pause()`].
+    paused: bool,
+
+    /// In-game time that passed while the recording was paused, in seconds.
+    paused_time: f64,
+
+    /// Whether the audio is recorded.
+    capture_sound: bool,
+
+    /// Maximum number of audio samples to capture in one call, if any.
+    max_samples_per_call: Option<u32>,
+
+    /// Whether the last call to [`Recorder::samples_to_capture()`] hit the cap.
+    clamping_samples: bool,
+
+    /// Whether to submit at most one video frame at a time.
+    low_latency: bool,
+
+    /// What to do with video frames when the queue for the recording thread is full.
+    queue_policy: QueuePolicy,
+
+    /// Pixel buffer given back by the recording thread which wasn't captured into yet.
+    ///
+    /// Only used with [`QueuePolicy::DropNewest`], where a capture can be skipped after the
+    /// buffer was already received.
+    pixel_buffer: Option<Box<[u8]>>,
+
+    /// Whether the capture of the current frame was skipped because the recording thread was
+    /// behind, so the frame has to be dropped.
+    capture_skipped: bool,
+
+    /// Output file to write a SHA-256 checksum for once the recording is finished.
+    checksum_file: Option<PathBuf>,
+
+    /// Re-encoding to do once the recording is finished.
+    reencode: Option<Reencode>,
+
+    /// Edit decision list to write once the recording is finished.
+    edl: Option<Edl>,
+
+    /// Comparison to run once the recording is finished.
+    comparison: Option<Comparison>,
+
+    /// GIF preview to make once the recording is finished.
+    gif_preview: Option<GifPreview>,
+
+    /// FFmpeg program for the processing once the recording is finished.
+    ffmpeg_program: String,
+
+    /// Number of video frames recorded so far.
+    recorded_video_frames: usize,
+
+    /// Number of video frames dropped in low-latency mode so far.
+    dropped_video_frames: usize,
+
+    /// Mouse cursor position to draw into the frames, if it's visible.
+    cursor: Option<(i32, i32)>,
+
+    /// Analog stick positions to draw into the frames, if they were set.
+    analog: Option<[f32; 4]>,
+
+    /// In-game time that was recorded, in seconds.
+    game_time: f64,
+
+    /// Number of video frames the thread has written to the sink.
+    encoded_video_frames: usize,
+
+    /// Number of audio samples the threads have written to the sink.
+    encoded_audio_samples: u64,
+
+    /// When the recording started.
+    started: Instant,
+
+    /// Number of video frames the thread has written to the sink so far, updated as it goes.
+    muxed_video_frames: Arc<AtomicU64>,
+
+    /// Indices of the video frames where a new scene starts, if scene detection is enabled.
+    scene_changes: Option<Arc<Mutex<Vec<usize>>>>,
+
+    /// File to write the chapters into once the recording is finished.
+    chapters_file: Option<PathBuf>,
+
+    /// Output resolution of the current segment, if it's adaptive.
+    output_size: Option<Arc<Mutex<(usize, usize)>>>,
+}
+
+/// Summary of a finished recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingStats {
+    /// Number of video frames that were written to the encoder.
+    pub video_frames: usize,
+    /// Number of audio samples that were written to the encoder.
+    pub audio_samples: u64,
+    /// Number of video frames that were dropped by the low-latency mode, dropped because the queue
+    /// for the recording thread was full, or lost because of an error.
+    pub dropped_frames: usize,
+    /// Wall-clock time from the start of the recording until the encoder was closed.
+    pub elapsed: Duration,
+}
+
+/// Comparison of how many video frames a recording should have had and how many it got.
+#[derive(Debug)]
+pub struct FrameReport {
+    /// In-game time that was recorded, in seconds.
+    pub game_time: f64,
+    /// Number of video frames covering the recorded in-game time.
+    pub expected_frames: f64,
+    /// Number of video frames that were written to the encoder.
+    pub encoded_frames: usize,
+    /// FFmpeg output, if any.
+    pub ffmpeg_output: Option<String>,
+    /// Start times of the chapters in seconds of video, empty without scene detection.
+    pub chapters: Vec<f64>,
+    /// Summary of the recording.
+    pub stats: RecordingStats,
+    /// Error which stopped the recording or came up while finishing it, such as FFmpeg exiting
+    /// with a non-zero status.
+    pub error: Option<eyre::Report>,
+}
+
+impl FrameReport {
+    /// Returns how many more frames were expected than were encoded.
+    ///
+    /// Zero means no frames were lost. Negative values mean that frames were duplicated.
+    pub fn drift(&self) -> i64 {
+        self.expected_frames.round() as i64 - self.encoded_frames as i64
+    }
+}
+
+/// Default number of messages that can wait for the recording thread.
+const DEFAULT_QUEUE_SIZE: usize = 64;
+
+/// Returns the estimated size of an FFV1 frame in bytes.
+///
+/// FFV1 usually compresses game footage to about half of the uncompressed RGB size.
+fn ffv1_frame_size_estimate(width: usize, height: usize) -> usize {
+    width * height * 3 / 2
+}
+
+/// Film frame rate as a fraction.
+const FILM_FPS: (u64, u64) = (24000, 1001);
+
+/// Returns the video frame rate as a numerator and a denominator.
+fn frame_rate(fps: u64, options: &RecorderOptions) -> (u64, u64) {
+    if options.film_24fps {
+        FILM_FPS
+    } else {
+        options.fps_fraction.unwrap_or((fps, 1))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CaptureType {
     Vulkan(Uuids),
     ReadPixels,
+    /// No video is captured at all, only the audio is recorded.
+    AudioOnly,
 }
 
 #[derive(Debug)]
@@ -78,24 +268,51 @@ enum MainToThread {
     Finish,
     GiveExternalHandles,
     AcquireImage,
-    Captured { buffer: Box<[u8]> },
-    Record { frames: usize },
-    Accumulate { weight: f32 },
+    /// Frame to convert with Vulkan in place of the one acquired from OpenGL.
+    UploadImage(Box<[u8]>),
+    Captured {
+        buffer: Box<[u8]>,
+    },
+    Record {
+        frames: usize,
+    },
+    Accumulate {
+        weight: f32,
+    },
     Audio(Vec<u8>),
+    Cursor(Option<(i32, i32)>),
+    Analog([f32; 4]),
 }
 
 #[derive(Debug)]
 enum ThreadToMain {
     Error(eyre::Report),
     ExternalHandles(ExternalHandles),
+    /// Number of video frames and audio samples written to the sink, sent before closing it.
+    Stats {
+        video_frames: usize,
+        audio_samples: u64,
+    },
     AcquiredImage,
     PixelBuffer(Box<[u8]>),
     FfmpegOutput(String),
 }
 
+/// Callback which can modify every video frame before it's muxed.
+///
+/// It receives the frame data and the frame width and height. The data is I420 when capturing
+/// with Vulkan and vertically flipped RGB24 otherwise.
+pub type FrameCallback = Box<dyn FnMut(&mut [u8], i32, i32) + Send>;
+
 impl Recorder {
+    /// Starts a recording into `filename`.
+    ///
+    /// If `on_frame` is set, it's called on the recording thread for every video frame. Every
+    /// frame is copied into a separate buffer for the callback, which costs some CPU time and
+    /// memory bandwidth, especially with the Vulkan capture which otherwise passes the frames
+    /// straight from GPU memory to the muxer.
     #[allow(clippy::too_many_arguments)]
-    #[instrument(name = "Recorder::init")]
+    #[instrument(name = "Recorder::init", skip(on_frame))]
     pub unsafe fn init(
         width: i32,
         height: i32,
@@ -106,13 +323,11 @@ impl Recorder {
         custom_ffmpeg_args: Option<&[&str]>,
         sampling_exposure: f64,
         sampling_min_fps: f64,
+        options: RecorderOptions,
+        on_frame: Option<FrameCallback>,
     ) -> eyre::Result<Recorder> {
-        ensure!(
-            width % 2 == 0 && height % 2 == 0,
-            "can't handle odd game resolutions yet: {}×{}",
-            width,
-            height,
-        );
+        let sampling_exposure = MotionBlur::filter_blending(options.motion_blur)
+            .map_or(sampling_exposure, |blur| blur.shutter);
 
         ensure!(
             sampling_exposure >= 0.,
@@ -129,11 +344,127 @@ impl Recorder {
 
         let is_sampling = sampling_exposure != 0.;
 
+        if let Some((num, den)) = options.fps_fraction {
+            ensure!(
+                num > 0 && den > 0,
+                "FPS fraction must be positive, but it is {}/{}",
+                num,
+                den,
+            );
+        }
+
+        if capture_type == CaptureType::AudioOnly {
+            ensure!(
+                !options.no_sound,
+                "can't record only the audio without the audio"
+            );
+
+            let muxer = Muxer::new_audio_only(
+                filename,
+                &options.extra_ffmpeg_args,
+                options.loudnorm.map(|target| target.integrated_lufs),
+                options.ffmpeg_path.as_deref(),
+            )
+            .wrap_err("error initializing audio muxing")?;
+            let encoder_command = muxer.command().to_vec();
+
+            let mut recorder = Self::with_sink(
+                width,
+                height,
+                fps,
+                slowdown,
+                capture_type,
+                None,
+                Box::new(muxer),
+                sampling_exposure,
+                sampling_min_fps,
+                options,
+            );
+            recorder.encoder_command = encoder_command;
+            return Ok(recorder);
+        }
+
+        let is_image_sequence = matches!(options.output, OutputTarget::ImageSequence { .. });
+        if options.raw_planes || is_image_sequence || options.lossless {
+            // The Vulkan capture converts the frames to I420, so it can't give raw RGB data.
+            capture_type = CaptureType::ReadPixels;
+        }
+
+        // The encoders need even resolutions, so odd ones are padded with black on the right and
+        // at the bottom.
+        let (encode_width, encode_height) = padded_size(width as usize, height as usize);
+        let is_padded = (encode_width, encode_height) != (width as usize, height as usize);
+        if is_padded && matches!(capture_type, CaptureType::Vulkan(_)) {
+            // The Vulkan color conversion works on the game-sized image, so pad on the CPU.
+            info!("capturing with glReadPixels to pad the odd game resolution");
+            capture_type = CaptureType::ReadPixels;
+        }
+
+        let (fps_num, fps_den) = frame_rate(fps, &options);
+
+        if let Some(proxy) = options.proxy {
+            ensure!(
+                proxy.fps > 0 && proxy.fps * fps_den <= fps_num,
+                "proxy FPS must be between 1 and {}, but it is {}",
+                fps_num as f64 / fps_den as f64,
+                proxy.fps,
+            );
+            ensure!(
+                proxy.scale > 0. && proxy.scale <= 1.,
+                "proxy scale must be in (0; 1], but it is {}",
+                proxy.scale,
+            );
+        }
+
+        ensure!(
+            !options.write_edl || TimecodeRate::new(fps_num, fps_den).is_some(),
+            "EDL timecodes need a whole or NTSC frame rate, but it is {}/{}",
+            fps_num,
+            fps_den,
+        );
+
+        if let Some(captions) = &options.captions {
+            ensure!(
+                captions.is_file(),
+                "caption file {} doesn't exist",
+                captions.display(),
+            );
+        }
+
+        if let Some(targets) = &options.tee_targets {
+            ensure!(!targets.is_empty(), "at least one tee target is needed");
+        }
+
+        if let Some(gif) = options.gif_preview {
+            ensure!(
+                gif.start_seconds >= 0. && gif.duration_seconds > 0.,
+                "GIF preview window must start at or after 0 and have a positive duration, but \
+                it starts at {} and lasts {}",
+                gif.start_seconds,
+                gif.duration_seconds,
+            );
+            ensure!(
+                gif.width > 0 && gif.fps > 0,
+                "GIF preview width and FPS must be positive, but they are {} and {}",
+                gif.width,
+                gif.fps,
+            );
+        }
+
         let vulkan = if let CaptureType::Vulkan(ref uuids) = capture_type {
-            match vulkan::init(width as u32, height as u32, uuids, is_sampling)
-                .wrap_err("error initalizing Vulkan")
+            match vulkan::init(
+                width as u32,
+                height as u32,
+                uuids,
+                is_sampling,
+                options.vulkan_device.as_ref(),
+            )
+            .wrap_err("error initalizing Vulkan")
             {
-                Ok(vulkan) => Some(vulkan),
+                Ok(vulkan) => {
+                    info!("capturing with Vulkan on {}", vulkan.device_name());
+                    Some(vulkan)
+                }
                 Err(err) => {
                     warn!("{:?}", err);
                     capture_type = CaptureType::ReadPixels;
@@ -144,38 +475,254 @@ impl Recorder {
             None
         };
 
-        let recording_fps = fps as f64 * slowdown;
-        let time_base = 1. / recording_fps;
+        let pixel_format = if vulkan.is_some() {
+            PixelFormat::I420
+        } else {
+            PixelFormat::Rgb24Flipped
+        };
 
-        let sampling_exposure = sampling_exposure * time_base;
+        if options.dither && vulkan.is_some() {
+            warn!("dithering is not supported with Vulkan capture");
+        }
 
-        // Pick a sampling FPS >= the min FPS that divides the recording FPS evenly.
-        let sampling_fps = (sampling_min_fps / recording_fps).ceil() * recording_fps;
-        let sampling_time_step = 1. / sampling_fps;
+        if let OutputTarget::ImageSequence { dir, format } = &options.output {
+            let sink = ImageSequenceSink::create(dir, *format, width as usize, height as usize)
+                .wrap_err("error creating the image sequence")?;
+
+            return Ok(Self::with_sink(
+                width,
+                height,
+                fps,
+                slowdown,
+                capture_type,
+                vulkan,
+                Box::new(sink),
+                sampling_exposure,
+                sampling_min_fps,
+                options,
+            ));
+        }
 
-        let sampling_buffers = if is_sampling && vulkan.is_none() {
-            let count = width as usize * height as usize * 3;
-            Some((vec![0u16; count].into(), vec![0u8; count].into()))
+        if options.raw_planes {
+            let sink = RawPlanesSink::create(filename, width as usize, height as usize)
+                .wrap_err("error creating the raw plane files")?;
+
+            return Ok(Self::with_sink(
+                width,
+                height,
+                fps,
+                slowdown,
+                capture_type,
+                vulkan,
+                Box::new(sink),
+                sampling_exposure,
+                sampling_min_fps,
+                options,
+            ));
+        }
+
+        if options.lossless {
+            let is_mkv = Path::new(filename)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map_or(false, |ext| ext.eq_ignore_ascii_case("mkv"));
+            ensure!(
+                is_mkv,
+                "lossless recording needs an .mkv output file, but it is {}",
+                filename,
+            );
+        }
+
+        let (codec, encoder) = if options.lossless {
+            (VideoCodec::Ffv1, Encoder::X264)
         } else {
-            None
+            (options.codec, options.encoder.clone())
         };
 
-        let pixel_format = if vulkan.is_some() {
-            PixelFormat::I420
+        let needs_reencode = options.two_pass_target_size_mb.is_some()
+            || options.fade_in_seconds > 0.
+            || options.fade_out_seconds > 0.;
+        let reencode = needs_reencode.then(|| {
+            let mut intermediate = PathBuf::from(filename).into_os_string();
+            intermediate.push(".intermediate.mov");
+            Reencode {
+                intermediate: intermediate.into(),
+                output: PathBuf::from(filename),
+                target_size_mb: options.two_pass_target_size_mb,
+                fade_in_seconds: options.fade_in_seconds.max(0.),
+                fade_out_seconds: options.fade_out_seconds.max(0.),
+            }
+        });
+
+        ensure!(
+            !(needs_reencode && options.tee_targets.is_some()),
+            "tee targets can't be used with two-pass encoding and fades"
+        );
+
+        ensure!(
+            !options.adaptive_resolution || options.segment_seconds.is_some(),
+            "adaptive resolution needs segmented output"
+        );
+
+        if let Some(seconds) = options.segment_seconds {
+            ensure!(
+                seconds > 0.,
+                "segment length must be positive, but it is {}",
+                seconds,
+            );
+
+            let conflicting = [
+                (needs_reencode, "two-pass encoding and fades"),
+                (options.write_edl, "EDL files"),
+                (options.compare_to.is_some(), "comparison"),
+                (options.gif_preview.is_some(), "GIF previews"),
+                (options.hud_lines.is_some(), "the HUD"),
+                (options.captions.is_some(), "captions"),
+                (options.scene_split_threshold.is_some(), "scene chapters"),
+                (options.separate_audio_thread, "the separate audio thread"),
+                (options.tee_targets.is_some(), "tee targets"),
+            ];
+            if let Some((_, name)) = conflicting.iter().find(|(conflicts, _)| *conflicts) {
+                return Err(eyre!("{} can't be used with segmented output", name));
+            }
+        }
+
+        let (muxer_filename, muxer_ffmpeg_args) = match &reencode {
+            Some(reencode) => {
+                #[rustfmt::skip]
+                let args: &[&str] = &[
+                    "-c:v", "libx264",
+                    "-preset", "ultrafast",
+                    "-qp", "0",
+                    "-c:a", "pcm_s16le",
+                ];
+                (
+                    reencode.intermediate.to_string_lossy().into_owned(),
+                    Some(args),
+                )
+            }
+            // FFV1 has no quality settings, so there's nothing to override.
+            None if options.lossless => (filename.to_owned(), None),
+            None if options.lossless_with_preview => {
+                #[rustfmt::skip]
+                let args: &[&str] = &[
+                    "-c:v", "libx264",
+                    "-preset", "ultrafast",
+                    "-qp", "0",
+                ];
+                (filename.to_owned(), Some(args))
+            }
+            None => (filename.to_owned(), custom_ffmpeg_args),
+        };
+        let muxer_filename = if options.segment_seconds.is_some() {
+            segment_filename(&muxer_filename, 1)
         } else {
-            PixelFormat::Rgb24Flipped
+            muxer_filename
         };
 
-        let muxer = match Muxer::new(
-            width as u64,
-            height as u64,
-            fps,
-            pixel_format,
-            filename,
-            custom_ffmpeg_args,
-        ) {
+        // Codecs that the encoder doesn't support use their software encoders, so only check the
+        // encoder which will actually be used.
+        let effective_encoder = MuxerOptions {
+            codec,
+            encoder: encoder.clone(),
+            custom_ffmpeg_args: muxer_ffmpeg_args,
+            ..Default::default()
+        }
+        .effective_encoder();
+        if let Some(effective) = effective_encoder.as_ref().filter(|&e| *e != encoder) {
+            warn!(
+                "encoder {} does not support {:?}, falling back to {}",
+                encoder.name(),
+                codec,
+                effective.name(),
+            );
+        }
+
+        if let Some(encoder) = effective_encoder.filter(|encoder| *encoder != Encoder::X264) {
+            match Muxer::detect_encoders(options.ffmpeg_path.as_deref()) {
+                Ok(available) => {
+                    let kind = std::mem::discriminant(&encoder);
+                    ensure!(
+                        available
+                            .iter()
+                            .any(|encoder| std::mem::discriminant(encoder) == kind),
+                        "encoder {} is not supported by your ffmpeg build (available: {})",
+                        encoder.name(),
+                        available
+                            .iter()
+                            .map(Encoder::name)
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    );
+                }
+                // The encoder is still probed when creating the muxer.
+                Err(err) => warn!("could not detect the ffmpeg encoders: {:?}", err),
+            }
+        }
+
+        // Write the subtitles only after all checks, so a bad setup doesn't leave them behind.
+        let hud_file = match &options.hud_lines {
+            Some(lines) => {
+                let mut path = PathBuf::from(filename).into_os_string();
+                path.push(".hud.srt");
+                let path = PathBuf::from(path);
+
+                std::fs::write(&path, hud_subtitles(lines, fps_num, fps_den))
+                    .wrap_err("error writing the HUD subtitles")?;
+                Some(path)
+            }
+            None => None,
+        };
+
+        let new_muxer = |encoder: Encoder| {
+            Muxer::new(
+                encode_width as u64,
+                encode_height as u64,
+                fps_num,
+                fps_den,
+                pixel_format,
+                &muxer_filename,
+                &MuxerOptions {
+                    codec,
+                    encoder,
+                    custom_ffmpeg_args: muxer_ffmpeg_args,
+                    extra_ffmpeg_args: &options.extra_ffmpeg_args,
+                    hud_subtitles: hud_file.as_deref(),
+                    captions: options.captions.as_deref(),
+                    tee_targets: options.tee_targets.as_deref().unwrap_or_default(),
+                    burn_timecode: options.burn_timecode,
+                    fragmented: options.fragmented && reencode.is_none(),
+                    loudness_lufs: options.loudnorm.map(|target| target.integrated_lufs),
+                    ffmpeg_path: options.ffmpeg_path.as_deref(),
+                    no_sound: options.no_sound,
+                },
+            )
+        };
+        let muxer = match new_muxer(encoder) {
+            Err(err @ MuxerInitError::EncoderUnavailable(_)) => {
+                warn!("{}, falling back to software encoding", err);
+                new_muxer(Encoder::X264)
+            }
+            #[cfg(unix)]
+            Err(MuxerInitError::VaapiDevice { device, source }) => {
+                warn!(
+                    "could not open the VAAPI device {}: {}, falling back to software encoding",
+                    device, source,
+                );
+                new_muxer(Encoder::X264)
+            }
+            result => result,
+        };
+
+        let muxer = match muxer {
             Ok(muxer) => muxer,
-            Err(err @ MuxerInitError::FfmpegSpawn(_)) => {
+            Err(err @ MuxerInitError::FfmpegSpawn { .. }) if options.ffmpeg_path.is_some() => {
+                return Err(err).wrap_err(
+                    "could not start ffmpeg. Make sure the ffmpeg path points to the ffmpeg \
+                    executable",
+                );
+            }
+            Err(err @ MuxerInitError::FfmpegSpawn { .. }) => {
                 return Err(err).wrap_err(
                     #[cfg(unix)]
                     "could not start ffmpeg. Make sure you have \
@@ -190,79 +737,531 @@ impl Recorder {
             }
         };
 
-        // When recording with sampling and exposure < 1, muxing the final frame can span many
-        // in-game frames that send audio samples, but are ignored for the purposes of video
-        // capture. We make the main-to-thread channel size big so that sending those audio samples
-        // doesn't block on waiting for the frame to be muxed.
-        let (to_thread_sender, from_main_receiver) = bounded(64);
-        let (to_main_sender, from_thread_receiver) = bounded(2);
+        let encoder_command = muxer.command().to_vec();
+        let encoder = muxer.encoder().cloned();
+
+        let (muxer, segments): (Box<dyn FrameSink>, _) = match options.segment_seconds {
+            Some(seconds) => {
+                let muxer: Arc<Mutex<Box<dyn FrameSink>>> = Arc::new(Mutex::new(Box::new(muxer)));
+
+                let checksum_filename = options.write_checksums.then(|| filename.to_owned());
+                let filename = filename.to_owned();
+                let segment_encoder = encoder.clone().unwrap_or_default();
+                let custom_args = muxer_ffmpeg_args
+                    .map(|args| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+                let extra_args = options.extra_ffmpeg_args.clone();
+                let burn_timecode = options.burn_timecode;
+                let fragmented = options.fragmented;
+                let loudness_lufs = options.loudnorm.map(|target| target.integrated_lufs);
+                let ffmpeg_path = options.ffmpeg_path.clone();
+                let capture_sound = !options.no_sound;
+                let new_muxer = move |number, (width, height): (usize, usize)| {
+                    let custom_args = custom_args
+                        .as_ref()
+                        .map(|args| args.iter().map(String::as_str).collect::<Vec<_>>());
+                    let muxer = Muxer::new(
+                        width as u64,
+                        height as u64,
+                        fps_num,
+                        fps_den,
+                        pixel_format,
+                        &segment_filename(&filename, number),
+                        &MuxerOptions {
+                            codec,
+                            encoder: segment_encoder.clone(),
+                            custom_ffmpeg_args: custom_args.as_deref(),
+                            extra_ffmpeg_args: &extra_args,
+                            burn_timecode,
+                            fragmented,
+                            loudness_lufs,
+                            ffmpeg_path: ffmpeg_path.as_deref(),
+                            no_sound: !capture_sound,
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+                    if (width, height) == (encode_width, encode_height) {
+                        return Ok(Box::new(muxer) as Box<dyn FrameSink>);
+                    }
+
+                    // Every frame goes into the downscaled segment.
+                    Ok(Box::new(ProxySink::new(
+                        Box::new(muxer),
+                        pixel_format,
+                        (encode_width, encode_height),
+                        (width, height),
+                        1.,
+                    )) as Box<dyn FrameSink>)
+                };
 
-        let pixels = if vulkan.is_none() {
-            let buffer: Box<[u8]> = vec![0u8; width as usize * height as usize * 3].into();
-            let pixels = buffer.clone();
-            to_main_sender
-                .send(ThreadToMain::PixelBuffer(buffer))
-                .unwrap();
-            Some(pixels)
+                let frames_per_segment = (seconds * fps_num as f64 / fps_den as f64).round();
+                let segments = Segments {
+                    muxer: muxer.clone(),
+                    new_muxer: Box::new(new_muxer),
+                    frames_per_segment: frames_per_segment.max(1.) as usize,
+                    frames_in_segment: 0,
+                    number: 1,
+                    output: String::new(),
+                    checksum_filename,
+                    size: (encode_width, encode_height),
+                    adaptive: None,
+                };
+                (Box::new(SharedSink(muxer)), Some(segments))
+            }
+            None => (Box::new(muxer), None),
+        };
+
+        if options.lossless && reencode.is_none() {
+            let frame_size = ffv1_frame_size_estimate(encode_width, encode_height);
+            info!(
+                "lossless recording takes roughly {:.1} MB per frame, {:.0} MB per second",
+                frame_size as f64 / 1e6,
+                frame_size as f64 * fps_num as f64 / fps_den as f64 / 1e6,
+            );
+        }
+
+        let sink: Box<dyn FrameSink> = if options.lossless_with_preview {
+            #[rustfmt::skip]
+            let default_args: &[&str] = &[
+                "-c:v", "libx264",
+                "-preset", "veryfast",
+                "-crf", "28",
+            ];
+
+            let preview = Muxer::new(
+                encode_width as u64,
+                encode_height as u64,
+                fps_num,
+                fps_den,
+                pixel_format,
+                &format!("{filename}.preview.mp4"),
+                &MuxerOptions {
+                    custom_ffmpeg_args: Some(custom_ffmpeg_args.unwrap_or(default_args)),
+                    hud_subtitles: hud_file.as_deref(),
+                    burn_timecode: options.burn_timecode,
+                    ffmpeg_path: options.ffmpeg_path.as_deref(),
+                    no_sound: options.no_sound,
+                    ..Default::default()
+                },
+            )
+            .wrap_err("error initializing preview muxing")?;
+
+            Box::new(TeeSink(muxer, Box::new(preview)))
         } else {
-            None
+            muxer
         };
 
-        let thread = thread::Builder::new()
-            .name("Recording Thread".to_string())
-            .spawn(move || {
-                thread(
-                    vulkan,
-                    muxer,
-                    pixels,
-                    sampling_buffers,
-                    to_main_sender,
-                    from_main_receiver,
-                )
+        let sink: Box<dyn FrameSink> = if let Some(proxy) = options.proxy {
+            #[rustfmt::skip]
+            let args: &[&str] = &[
+                "-c:v", "libx264",
+                "-preset", "veryfast",
+                "-crf", "28",
+            ];
+
+            let (proxy_width, proxy_height) = proxy_size(encode_width, encode_height, proxy.scale);
+            let proxy_muxer = Muxer::new(
+                proxy_width as u64,
+                proxy_height as u64,
+                proxy.fps,
+                1,
+                pixel_format,
+                &format!("{filename}.proxy.mp4"),
+                &MuxerOptions {
+                    custom_ffmpeg_args: Some(args),
+                    hud_subtitles: hud_file.as_deref(),
+                    burn_timecode: options.burn_timecode,
+                    ffmpeg_path: options.ffmpeg_path.as_deref(),
+                    no_sound: options.no_sound,
+                    ..Default::default()
+                },
+            )
+            .wrap_err("error initializing proxy muxing")?;
+
+            let proxy_sink = ProxySink::new(
+                Box::new(proxy_muxer),
+                pixel_format,
+                (encode_width, encode_height),
+                (proxy_width, proxy_height),
+                proxy.fps as f64 * fps_den as f64 / fps_num as f64,
+            );
+            Box::new(TeeSink(sink, Box::new(proxy_sink)))
+        } else {
+            sink
+        };
+
+        let sink: Box<dyn FrameSink> = if is_padded {
+            Box::new(PadSink::new(
+                sink,
+                (width as usize, height as usize),
+                (encode_width, encode_height),
+            ))
+        } else {
+            sink
+        };
+
+        let sink: Box<dyn FrameSink> = if let Some(callback) = on_frame {
+            Box::new(CallbackSink {
+                inner: sink,
+                callback,
+                width,
+                height,
+                frame: Vec::new(),
             })
-            .unwrap();
+        } else {
+            sink
+        };
+
+        // Segment checksums are written by the recording thread as the segments are finished.
+        let checksum_file =
+            (options.write_checksums && segments.is_none()).then(|| PathBuf::from(filename));
+
+        let comparison = options.compare_to.clone().map(|reference| Comparison {
+            output: PathBuf::from(filename),
+            reference,
+        });
 
-        Ok(Recorder {
+        let edl = options.write_edl.then(|| {
+            let mut path = PathBuf::from(filename).into_os_string();
+            path.push(".edl");
+            Edl {
+                path: path.into(),
+                output: PathBuf::from(filename),
+                // Checked above.
+                rate: TimecodeRate::new(fps_num, fps_den).unwrap(),
+            }
+        });
+
+        let gif_preview = options.gif_preview.map(|config| {
+            let sidecar = |suffix: &str| {
+                let mut path = PathBuf::from(filename).into_os_string();
+                path.push(suffix);
+                PathBuf::from(path)
+            };
+            GifPreview {
+                input: PathBuf::from(filename),
+                output: sidecar(".gif"),
+                palette: sidecar(".palette.png"),
+                config,
+            }
+        });
+
+        let chapters_file = options.scene_split_threshold.map(|_| {
+            let mut path = PathBuf::from(filename).into_os_string();
+            path.push(".chapters.txt");
+            PathBuf::from(path)
+        });
+
+        let mut recorder = Self::with_segments(
             width,
             height,
-            time_base,
+            fps,
             slowdown,
-            video_remainder: 0.,
-            sound_remainder: 0.,
+            capture_type,
+            vulkan,
+            sink,
+            segments,
             sampling_exposure,
-            sampling_time_step,
-            sampling_last_frame_start: 0.,
-            opengl: None,
-            acquired_image: false,
-            thread,
-            sender: to_thread_sender,
-            receiver: from_thread_receiver,
-            thread_error: None,
-            ffmpeg_output: None,
+            sampling_min_fps,
+            options,
+        );
+        recorder.encoder_command = encoder_command;
+        recorder.encoder = encoder;
+        recorder.checksum_file = checksum_file;
+        recorder.reencode = reencode;
+        recorder.padding = (encode_width as i32 - width, encode_height as i32 - height);
+        recorder.comparison = comparison;
+        recorder.edl = edl;
+        recorder.gif_preview = gif_preview;
+        recorder.chapters_file = chapters_file;
+
+        Ok(recorder)
+    }
+
+    /// Starts the recording thread which writes the frames into `sink`.
+    #[allow(clippy::too_many_arguments)]
+    fn with_sink(
+        width: i32,
+        height: i32,
+        fps: u64,
+        slowdown: f64,
+        capture_type: CaptureType,
+        vulkan: Option<Vulkan>,
+        sink: Box<dyn FrameSink>,
+        sampling_exposure: f64,
+        sampling_min_fps: f64,
+        options: RecorderOptions,
+    ) -> Recorder {
+        Self::with_segments(
+            width,
+            height,
+            fps,
+            slowdown,
             capture_type,
-        })
+            vulkan,
+            sink,
+            None,
+            sampling_exposure,
+            sampling_min_fps,
+            options,
+        )
     }
 
-    #[instrument(skip_all)]
-    fn send_to_thread(&mut self, message: MainToThread) {
-        if self.sender.send(message).is_ok() {
-            // The happy path.
-            return;
+    /// Starts the recording thread which writes the frames into `sink`, starting new segments
+    /// with `segments` if it's set.
+    #[allow(clippy::too_many_arguments)]
+    fn with_segments(
+        width: i32,
+        height: i32,
+        fps: u64,
+        slowdown: f64,
+        capture_type: CaptureType,
+        vulkan: Option<Vulkan>,
+        sink: Box<dyn FrameSink>,
+        mut segments: Option<Segments>,
+        sampling_exposure: f64,
+        sampling_min_fps: f64,
+        options: RecorderOptions,
+    ) -> Recorder {
+        let motion_blur = MotionBlur::filter_blending(options.motion_blur);
+        let sampling_exposure = motion_blur.map_or(sampling_exposure, |blur| blur.shutter);
+        let is_sampling = sampling_exposure != 0.;
+
+        if options.offline && options.low_latency {
+            warn!("low-latency mode delays frames, so it is disabled in offline mode");
+        }
+        if is_sampling && options.low_latency {
+            warn!("low-latency mode is not supported with sampling");
         }
 
-        // The channel was closed. Try to get the error.
-        while let Ok(message) = self.receiver.try_recv() {
-            match message {
-                ThreadToMain::Error(err) => {
-                    self.thread_error = Some(err);
-                }
-                ThreadToMain::FfmpegOutput(output) => self.ffmpeg_output = Some(output),
-                _ => (),
+        let queue_policy = match options.queue_policy {
+            QueuePolicy::DropNewest if options.offline => {
+                warn!("dropping frames is disabled in offline mode");
+                QueuePolicy::Block
             }
-        }
-    }
+            QueuePolicy::DropNewest if is_sampling || capture_type != CaptureType::ReadPixels => {
+                warn!("dropping frames is only supported with glReadPixels and no sampling");
+                QueuePolicy::Block
+            }
+            policy => policy,
+        };
 
-    #[instrument(skip_all)]
+        let (fps_num, fps_den) = frame_rate(fps, &options);
+        let recording_fps = fps_num as f64 / fps_den as f64 * slowdown;
+        let time_base = fps_den as f64 / (fps_num as f64 * slowdown);
+
+        let sampling_exposure = sampling_exposure * time_base;
+
+        let sampling_fps = match motion_blur {
+            Some(blur) => recording_fps * blur.subframes as f64,
+            // Pick a sampling FPS >= the min FPS that divides the recording FPS evenly.
+            None => (sampling_min_fps / recording_fps).ceil() * recording_fps,
+        };
+        let sampling_time_step = 1. / sampling_fps;
+
+        // Without video there are no frames to keep buffers for.
+        let is_audio_only = capture_type == CaptureType::AudioOnly;
+
+        let sampling_buffers = if is_sampling && vulkan.is_none() && !is_audio_only {
+            let count = width as usize * height as usize * 3;
+            Some((vec![0u16; count].into(), vec![0u8; count].into()))
+        } else {
+            None
+        };
+        let dither_width = options.dither.then_some(width as usize);
+
+        // When recording with sampling and exposure < 1, muxing the final frame can span many
+        // in-game frames that send audio samples, but are ignored for the purposes of video
+        // capture. We make the main-to-thread channel size big so that sending those audio samples
+        // doesn't block on waiting for the frame to be muxed.
+        let queue_size = options.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE).max(1);
+        let (to_thread_sender, from_main_receiver) = bounded(queue_size);
+        let (to_main_sender, from_thread_receiver) = bounded(2);
+
+        let output_size = match &mut segments {
+            Some(segments) if options.adaptive_resolution => {
+                let receiver = from_main_receiver.clone();
+                let adaptive = AdaptiveResolution::new(
+                    Box::new(move || receiver.len() as f64 / queue_size as f64),
+                    segments.size,
+                );
+                let output_size = adaptive.output_size.clone();
+                segments.adaptive = Some(adaptive);
+                Some(output_size)
+            }
+            _ => None,
+        };
+
+        let pixels = if vulkan.is_none() && !is_audio_only {
+            let buffer: Box<[u8]> = vec![0u8; width as usize * height as usize * 3].into();
+            let pixels = buffer.clone();
+            to_main_sender
+                .send(ThreadToMain::PixelBuffer(buffer))
+                .unwrap();
+            Some(pixels)
+        } else {
+            None
+        };
+
+        let (sink, scene_changes) = match options.scene_split_threshold {
+            Some(threshold) if !is_audio_only => {
+                let scene_changes = Arc::new(Mutex::new(Vec::new()));
+                let compared_len = if vulkan.is_some() {
+                    // The luma plane of I420.
+                    width as usize * height as usize
+                } else {
+                    width as usize * height as usize * 3
+                };
+                let sink: Box<dyn FrameSink> = Box::new(SceneSink {
+                    inner: sink,
+                    compared_len,
+                    threshold,
+                    previous: Vec::new(),
+                    frame_idx: 0,
+                    scene_changes: scene_changes.clone(),
+                });
+                (sink, Some(scene_changes))
+            }
+            _ => (sink, None),
+        };
+
+        let (sink, audio_sender, audio_thread) = if options.separate_audio_thread {
+            let sink = AudioSharedSink {
+                sink: Arc::new(Mutex::new(sink)),
+                pending_audio: Arc::new(Mutex::new(Vec::new())),
+            };
+            let audio_sink = sink.clone();
+
+            let (audio_sender, audio_receiver) = bounded(64);
+            let audio_thread = thread::Builder::new()
+                .name("Recording Audio Thread".to_string())
+                .spawn(move || audio_thread(audio_sink, audio_receiver))
+                .unwrap();
+
+            let sink: Box<dyn FrameSink> = Box::new(sink);
+            (sink, Some(audio_sender), Some(audio_thread))
+        } else {
+            (sink, None, None)
+        };
+
+        let pixel_format = if vulkan.is_some() {
+            PixelFormat::I420
+        } else {
+            PixelFormat::Rgb24Flipped
+        };
+        let overlay = Overlay {
+            width: width as usize,
+            height: height as usize,
+            pixel_format,
+            cursor: None,
+            analog: None,
+            frame: Vec::new(),
+        };
+
+        let muxed_video_frames = Arc::new(AtomicU64::new(0));
+        let thread_muxed_video_frames = muxed_video_frames.clone();
+
+        let thread = thread::Builder::new()
+            .name("Recording Thread".to_string())
+            .spawn(move || {
+                thread(
+                    vulkan,
+                    sink,
+                    segments,
+                    audio_thread,
+                    overlay,
+                    pixels,
+                    sampling_buffers,
+                    dither_width,
+                    thread_muxed_video_frames,
+                    to_main_sender,
+                    from_main_receiver,
+                )
+            })
+            .unwrap();
+
+        Recorder {
+            width,
+            height,
+            padding: (0, 0),
+            time_base,
+            slowdown,
+            video_remainder: 0.,
+            sound_remainder: 0.,
+            sampling_exposure,
+            sampling_time_step,
+            sampling_last_frame_start: 0.,
+            opengl: None,
+            acquired_image: false,
+            thread: Some(thread),
+            sender: to_thread_sender,
+            receiver: from_thread_receiver,
+            audio_sender,
+            thread_error: None,
+            ffmpeg_output: None,
+            capture_type,
+            encoder_command: Vec::new(),
+            encoder: None,
+            auto_pause_on_focus_loss: false,
+            focused: true,
+            paused: false,
+            paused_time: 0.,
+            capture_sound: !options.no_sound,
+            max_samples_per_call: options.max_samples_per_call,
+            clamping_samples: false,
+            low_latency: options.low_latency && !options.offline && !is_sampling,
+            queue_policy,
+            pixel_buffer: None,
+            capture_skipped: false,
+            checksum_file: None,
+            reencode: None,
+            edl: None,
+            gif_preview: None,
+            ffmpeg_program: ffmpeg_program(options.ffmpeg_path.as_deref()),
+            comparison: None,
+            recorded_video_frames: 0,
+            dropped_video_frames: 0,
+            cursor: None,
+            analog: None,
+            game_time: 0.,
+            encoded_video_frames: 0,
+            encoded_audio_samples: 0,
+            started: Instant::now(),
+            muxed_video_frames,
+            scene_changes,
+            chapters_file: None,
+            output_size,
+        }
+    }
+
+    #[instrument(skip_all)]
+    fn send_to_thread(&mut self, message: MainToThread) {
+        if self.sender.send(message).is_ok() {
+            // The happy path.
+            return;
+        }
+
+        // The channel was closed. Try to get the error.
+        while let Ok(message) = self.receiver.try_recv() {
+            match message {
+                ThreadToMain::Error(err) => {
+                    self.thread_error = Some(err);
+                }
+                ThreadToMain::FfmpegOutput(output) => self.ffmpeg_output = Some(output),
+                ThreadToMain::Stats {
+                    video_frames,
+                    audio_samples,
+                } => {
+                    self.encoded_video_frames = video_frames;
+                    self.encoded_audio_samples = audio_samples;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
     fn recv_from_thread(&mut self) -> eyre::Result<ThreadToMain> {
         match self.receiver.recv() {
             Err(_) => Err(self
@@ -317,19 +1316,64 @@ impl Recorder {
                 self.opengl.as_ref().unwrap().capture()
             }
             CaptureType::ReadPixels => {
-                let mut buffer = match self.recv_from_thread()? {
-                    ThreadToMain::PixelBuffer(buffer) => buffer,
-                    _ => unreachable!(),
-                };
+                let (width, height) = (self.width, self.height);
+                self.capture_pixels(|buffer| {
+                    opengl::capture_with_read_pixels(marker, width, height, buffer)
+                        .wrap_err("error capturing with glReadPixels")
+                })
+            }
+            CaptureType::AudioOnly => Ok(()),
+        }
+    }
+
+    /// Captures the frame into the pixel buffer with `capture` and sends it to the thread.
+    ///
+    /// With [`QueuePolicy::DropNewest`], the capture is skipped rather than waiting for the thread.
+    fn capture_pixels(
+        &mut self,
+        capture: impl FnOnce(&mut [u8]) -> eyre::Result<()>,
+    ) -> eyre::Result<()> {
+        let mut buffer = if self.queue_policy == QueuePolicy::DropNewest {
+            // The thread gives the buffer back only once it gets to the previous frame, so waiting
+            // for the buffer means waiting for everything queued before that frame.
+            let buffer = match self.pixel_buffer.take() {
+                Some(buffer) => Some(buffer),
+                None => match self.receiver.try_recv() {
+                    Ok(ThreadToMain::PixelBuffer(buffer)) => Some(buffer),
+                    Ok(ThreadToMain::Error(err)) => return Err(err),
+                    Ok(_) => unreachable!(),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => {
+                        return Err(self
+                            .thread_error
+                            .take()
+                            .unwrap_or_else(|| eyre!("recording thread error")))
+                    }
+                },
+            };
+
+            match buffer {
+                // Sending the captured frame mustn't wait either.
+                Some(buffer) if !self.sender.is_full() => buffer,
+                buffer => {
+                    self.pixel_buffer = buffer;
+                    self.capture_skipped = true;
+                    return Ok(());
+                }
+            }
+        } else {
+            match self.recv_from_thread()? {
+                ThreadToMain::PixelBuffer(buffer) => buffer,
+                _ => unreachable!(),
+            }
+        };
 
-                opengl::capture_with_read_pixels(marker, self.width, self.height, &mut buffer)
-                    .wrap_err("error capturing with glReadPixels")?;
+        capture(&mut buffer)?;
 
-                self.send_to_thread(MainToThread::Captured { buffer });
+        self.send_to_thread(MainToThread::Captured { buffer });
+        self.capture_skipped = false;
 
-                Ok(())
-            }
-        }
+        Ok(())
     }
 
     fn is_sampling(&self) -> bool {
@@ -397,11 +1441,26 @@ impl Recorder {
 
     #[instrument("Recorder::record", skip(self))]
     unsafe fn record(&mut self, frames: usize) {
+        if self.queue_policy == QueuePolicy::DropNewest
+            && (self.capture_skipped || self.sender.is_full())
+        {
+            // The thread will mux whatever pixels it has when the next frame fits into the queue,
+            // so this frame is lost. Put its duration back so the next frame covers it.
+            self.video_remainder += frames as f64;
+            self.dropped_video_frames += frames;
+            return;
+        }
+
+        self.recorded_video_frames += frames;
         self.send_to_thread(MainToThread::Record { frames });
     }
 
     #[instrument(skip_all)]
     pub unsafe fn record_last_frame(&mut self) -> eyre::Result<()> {
+        if self.capture_type == CaptureType::AudioOnly {
+            return Ok(());
+        }
+
         if self.is_sampling() {
             loop {
                 let weight = self.current_sampling_weight();
@@ -440,7 +1499,12 @@ impl Recorder {
                     < 0.00001
             );
         } else {
-            let frames = self.current_frame_length();
+            let mut frames = self.current_frame_length();
+            if self.low_latency {
+                // Rather than duplicating this frame, the extra frames stay in the remainder, so
+                // the next game frames cover them and the video stays in sync with the audio.
+                frames = frames.min(1);
+            }
             self.video_remainder -= frames as f64;
 
             if frames > 0 {
@@ -452,6 +1516,17 @@ impl Recorder {
     }
 
     pub fn time_passed(&mut self, time: f64) {
+        if self.is_paused() {
+            self.paused_time += time;
+            return;
+        }
+
+        if self.capture_type == CaptureType::AudioOnly {
+            self.sound_remainder += time * self.slowdown;
+            return;
+        }
+
+        self.game_time += time;
         self.video_remainder += time / self.frame_time();
         self.sound_remainder += time * self.slowdown;
 
@@ -463,9 +1538,33 @@ impl Recorder {
     }
 
     pub fn samples_to_capture(&mut self, samples_per_second: i32, mode: SoundCaptureMode) -> i32 {
+        if !self.capture_sound {
+            return 0;
+        }
+
         let samples = self.sound_remainder * samples_per_second as f64;
         let samples_rounded = match mode {
-            SoundCaptureMode::Normal => samples.floor(),
+            SoundCaptureMode::Normal => {
+                let samples_rounded = samples.floor();
+                match self.max_samples_per_call {
+                    Some(max) if samples_rounded > max as f64 => {
+                        if !self.clamping_samples {
+                            warn!(
+                                "capturing {} audio samples at most instead of {}, the rest will \
+                                be captured later",
+                                max, samples_rounded,
+                            );
+                        }
+                        self.clamping_samples = true;
+                        max as f64
+                    }
+                    _ => {
+                        self.clamping_samples = false;
+                        samples_rounded
+                    }
+                }
+            }
+            // Flush everything at the end regardless of the cap.
             SoundCaptureMode::Remaining { extra } => {
                 (samples + extra as f64 * samples_per_second as f64).ceil()
             }
@@ -478,30 +1577,190 @@ impl Recorder {
 
     #[instrument(name = "Recorder::write_audio_frame", skip_all)]
     pub fn write_audio_frame(&mut self, samples: Vec<u8>) {
-        self.send_to_thread(MainToThread::Audio(samples));
+        if !self.capture_sound || self.is_paused() {
+            return;
+        }
+
+        if let Some(sender) = &self.audio_sender {
+            // If the audio thread has exited, the recording thread reports its error.
+            let _ = sender.send(samples);
+        } else {
+            self.send_to_thread(MainToThread::Audio(samples));
+        }
+    }
+
+    /// Finishes the recording and returns its summary.
+    ///
+    /// Returns an error if the recording failed, including when FFmpeg exits with a non-zero
+    /// status after the last frame. [`Recorder::finish_with_report()`] returns the summary even
+    /// then, with the counts reached until the error.
+    pub fn finish(self) -> eyre::Result<RecordingStats> {
+        let report = self.finish_with_report();
+        match report.error {
+            Some(err) => Err(err),
+            None => Ok(report.stats),
+        }
     }
 
+    /// Finishes the recording and returns a report comparing the expected and the encoded number
+    /// of video frames.
+    ///
+    /// Errors of the recording and of the steps after it, such as the re-encoding, end up in the
+    /// report rather than being logged, so the caller must check [`FrameReport::error`]. Only the
+    /// first error is kept, and the later ones are logged.
     #[instrument(name = "Recorder::finish", skip_all)]
-    pub fn finish(mut self) -> Option<String> {
+    pub fn finish_with_report(mut self) -> FrameReport {
+        // Let the audio thread exit, if there is one.
+        self.audio_sender = None;
+
         self.send_to_thread(MainToThread::Finish);
 
         while let Ok(message) = self.receiver.recv() {
             match message {
+                // Later errors are usually caused by the first one, such as FFmpeg exiting with an
+                // error after a failed write.
+                ThreadToMain::Error(err) if self.thread_error.is_some() => {
+                    warn!("another recording thread error: {:?}", err);
+                }
                 ThreadToMain::Error(err) => {
                     self.thread_error = Some(err);
                 }
                 ThreadToMain::FfmpegOutput(output) => self.ffmpeg_output = Some(output),
+                ThreadToMain::Stats {
+                    video_frames,
+                    audio_samples,
+                } => {
+                    self.encoded_video_frames = video_frames;
+                    self.encoded_audio_samples = audio_samples;
+                }
                 _ => (),
             }
         }
 
-        self.thread.join().unwrap();
+        self.thread.take().unwrap().join().unwrap();
+
+        let stats = RecordingStats {
+            video_frames: self.encoded_video_frames,
+            audio_samples: self.encoded_audio_samples,
+            dropped_frames: self.dropped_video_frames
+                + self
+                    .recorded_video_frames
+                    .saturating_sub(self.encoded_video_frames),
+            elapsed: self.started.elapsed(),
+        };
+
+        let mut error = self.thread_error.take();
+
+        if let Some(commands) = self.reencode_commands() {
+            let reencode = self.reencode.as_ref().unwrap();
+            // Without the re-encoding there's only the intermediate recording, so this has to
+            // fail the recording.
+            match run_reencode(reencode, &commands) {
+                Ok(output) => {
+                    self.ffmpeg_output
+                        .get_or_insert_with(String::new)
+                        .push_str(&output);
+                }
+                Err(err) => {
+                    keep_first_error(&mut error, err.wrap_err("error re-encoding the recording"))
+                }
+            }
+        }
+
+        if let Some(comparison) = &self.comparison {
+            let mut command = comparison_command(comparison);
+            command[0] = self.ffmpeg_program.clone();
+            match run_comparison(&command) {
+                Ok(averages) => {
+                    for average in averages {
+                        info!("{}", average);
+                    }
+                }
+                Err(err) => keep_first_error(
+                    &mut error,
+                    err.wrap_err("error comparing the recording to the reference"),
+                ),
+            }
+        }
+
+        // The preview is made from the final output, so this goes after the re-encoding.
+        if let Some(commands) = self.gif_preview_commands() {
+            let gif = self.gif_preview.as_ref().unwrap();
+            if let Err(err) = commands.and_then(|commands| run_gif_preview(gif, &commands)) {
+                keep_first_error(&mut error, err.wrap_err("error making the GIF preview"));
+            }
+        }
+
+        if let Some(edl) = &self.edl {
+            let contents = edl_contents(&edl.output, self.recorded_video_frames, edl.rate);
+            if let Err(err) = std::fs::write(&edl.path, contents) {
+                keep_first_error(&mut error, eyre!(err).wrap_err("error writing the EDL"));
+            }
+        }
+
+        let video_frame_time = self.frame_time() * self.slowdown;
+        let chapters = self
+            .scene_changes
+            .as_ref()
+            .map(|changes| {
+                let changes = changes.lock().unwrap();
+                iter::once(0.)
+                    .chain(changes.iter().map(|&frame| frame as f64 * video_frame_time))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if let Some(path) = &self.chapters_file {
+            let duration = self.encoded_video_frames as f64 * video_frame_time;
+            if let Err(err) = std::fs::write(path, chapters_metadata(&chapters, duration)) {
+                keep_first_error(
+                    &mut error,
+                    eyre!(err).wrap_err("error writing the chapters"),
+                );
+            }
+        }
+
+        // The sink is closed by now, so the file is complete.
+        if let Some(path) = &self.checksum_file {
+            if let Err(err) = write_checksum(path) {
+                keep_first_error(&mut error, err.wrap_err("error writing the checksum"));
+            }
+        }
+
+        FrameReport {
+            game_time: self.game_time,
+            expected_frames: self.game_time / self.frame_time(),
+            encoded_frames: self.encoded_video_frames,
+            ffmpeg_output: self.ffmpeg_output.take(),
+            chapters,
+            stats,
+            error,
+        }
+    }
 
-        if let Some(err) = self.thread_error {
-            error!("recording thread error: {:?}", err);
+    /// Returns the FFmpeg commands for every pass of the re-encoding, if it's enabled.
+    fn reencode_commands(&self) -> Option<Vec<Vec<String>>> {
+        let reencode = self.reencode.as_ref()?;
+        let duration = self.recorded_video_frames as f64 * self.frame_time();
+        let mut commands = reencode_commands(reencode, duration);
+        for command in &mut commands {
+            command[0] = self.ffmpeg_program.clone();
         }
+        Some(commands)
+    }
 
-        self.ffmpeg_output.take()
+    /// Returns the FFmpeg commands for both passes of the GIF preview, if it's enabled.
+    ///
+    /// Returns an error if the preview window doesn't fit into the recording.
+    fn gif_preview_commands(&self) -> Option<eyre::Result<Vec<Vec<String>>>> {
+        let gif = self.gif_preview.as_ref()?;
+        let duration = self.recorded_video_frames as f64 * self.frame_time();
+        Some(gif_preview_commands(gif, duration).map(|mut commands| {
+            for command in &mut commands {
+                command[0] = self.ffmpeg_program.clone();
+            }
+            commands
+        }))
     }
 
     pub fn reset_opengl(&mut self) {
@@ -516,6 +1775,48 @@ impl Recorder {
         self.height
     }
 
+    /// Returns the encoder used for the video, or [`None`] if it's set by custom FFmpeg arguments.
+    pub fn encoder(&self) -> Option<&Encoder> {
+        self.encoder.as_ref()
+    }
+
+    /// Returns the number of black columns and rows added to make the video size even.
+    pub fn padded(&self) -> (i32, i32) {
+        self.padding
+    }
+
+    /// Returns the width of the video being written.
+    ///
+    /// With [`RecorderOptions::adaptive_resolution`], this is the width of the current segment.
+    pub fn output_width(&self) -> i32 {
+        match &self.output_size {
+            Some(size) => size.lock().unwrap().0 as i32,
+            None => self.width + self.padding.0,
+        }
+    }
+
+    /// Returns the height of the video being written.
+    ///
+    /// With [`RecorderOptions::adaptive_resolution`], this is the height of the current segment.
+    pub fn output_height(&self) -> i32 {
+        match &self.output_size {
+            Some(size) => size.lock().unwrap().1 as i32,
+            None => self.height + self.padding.1,
+        }
+    }
+
+    /// Returns the number of video frames muxed so far.
+    ///
+    /// The frames are muxed on the recording thread, so this lags behind the recorded frames.
+    pub fn recorded_frames(&self) -> u64 {
+        self.muxed_video_frames.load(Ordering::Relaxed)
+    }
+
+    /// Returns the duration of the video frames muxed so far, in seconds.
+    pub fn recorded_seconds(&self) -> f64 {
+        self.recorded_frames() as f64 * self.frame_time()
+    }
+
     fn frame_time(&self) -> f64 {
         self.time_base
     }
@@ -531,26 +1832,189 @@ impl Recorder {
     pub fn capture_type(&self) -> &CaptureType {
         &self.capture_type
     }
+
+    /// Returns the FFmpeg program and arguments used for encoding.
+    ///
+    /// The video resolution and frame rate aren't part of the command as they are stored in the
+    /// header of the stream that FFmpeg reads from its standard input.
+    pub fn encoder_command(&self) -> Vec<String> {
+        self.encoder_command.clone()
+    }
+
+    /// Sets whether the recording should pause while the game window is out of focus.
+    pub fn set_auto_pause_on_focus_loss(&mut self, enabled: bool) {
+        self.auto_pause_on_focus_loss = enabled;
+    }
+
+    /// Notifies the recorder about the game window gaining or losing focus.
+    ///
+    /// This should be called from the window event loop.
+    pub fn notify_focus(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    /// Pauses the recording until [`Recorder::resume()`] is called.
+    ///
+    /// Unlike [`Recorder::finish()`], this keeps the output open, so the parts of the game before
+    /// and after the pause end up in a single continuous video.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resumes the recording paused with [`Recorder::pause()`].
+    ///
+    /// The time that passed while paused is skipped entirely rather than caught up on. The
+    /// leftover fraction of an audio sample from before the pause is dropped too, so the audio
+    /// starts in sync with the video.
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.sound_remainder = 0.;
+    }
+
+    /// Returns whether the recording is currently paused.
+    ///
+    /// While paused, in-game time doesn't advance the recording, so no video frames or audio
+    /// samples are captured.
+    pub fn is_paused(&self) -> bool {
+        self.paused || (self.auto_pause_on_focus_loss && !self.focused)
+    }
+
+    /// Returns the in-game time that passed while the recording was paused, in seconds.
+    pub fn paused_time(&self) -> f64 {
+        self.paused_time
+    }
+
+    /// Updates the mouse cursor drawn into the recorded frames.
+    ///
+    /// `x` and `y` are in pixels from the top-left corner of the game window. The cursor is drawn
+    /// on the recording thread into every frame recorded after this call while `visible` is
+    /// `true`.
+    pub fn set_cursor(&mut self, x: i32, y: i32, visible: bool) {
+        let cursor = visible.then_some((x, y));
+        if cursor != self.cursor {
+            self.cursor = cursor;
+            self.send_to_thread(MainToThread::Cursor(cursor));
+        }
+    }
+
+    /// Updates the analog stick positions drawn into the recorded frames.
+    ///
+    /// `lx` and `ly` are the left stick axes, `rx` and `ry` are the right stick axes, all in the
+    /// `[-1; 1]` range with positive values pointing right and up. Once set, the sticks are drawn
+    /// in the bottom-left corner of every recorded frame.
+    pub fn set_analog_input(&mut self, lx: f32, ly: f32, rx: f32, ry: f32) {
+        let analog = Some([lx, ly, rx, ry].map(|axis| axis.clamp(-1., 1.)));
+        if analog != self.analog {
+            self.analog = analog;
+            self.send_to_thread(MainToThread::Analog(analog.unwrap()));
+        }
+    }
+
+    /// Records `frames` frames of a scrolling color bar pattern into `filename`.
+    ///
+    /// This runs the capturing, muxing and encoding path end-to-end without the game, which is
+    /// useful for checking that FFmpeg and the codec settings work. With
+    /// [`CaptureType::Vulkan`], the frames are uploaded in place of the ones from OpenGL and go
+    /// through the Vulkan color conversion. Odd resolutions are padded like in a normal recording.
+    pub unsafe fn self_test(
+        width: i32,
+        height: i32,
+        fps: u64,
+        filename: &str,
+        frames: usize,
+        capture_type: CaptureType,
+    ) -> eyre::Result<()> {
+        let recorder = Self::init(
+            width,
+            height,
+            fps,
+            1.,
+            capture_type,
+            filename,
+            None,
+            0.,
+            0.,
+            RecorderOptions::default(),
+            None,
+        )?;
+
+        recorder.record_test_pattern(frames)
+    }
+
+    /// Records `frames` frames of the test pattern and finishes the recording.
+    fn record_test_pattern(mut self, frames: usize) -> eyre::Result<()> {
+        let width = self.width;
+
+        for frame in 0..frames {
+            match self.capture_type {
+                CaptureType::Vulkan(_) => {
+                    let mut pixels = vec![0; width as usize * self.height as usize * 3];
+                    draw_test_pattern(&mut pixels, width, frame);
+                    self.send_to_thread(MainToThread::UploadImage(pixels.into()));
+
+                    // The image is already there, so it mustn't be acquired from OpenGL.
+                    self.acquired_image = true;
+                }
+                CaptureType::ReadPixels => self.capture_pixels(|buffer| {
+                    draw_test_pattern(buffer, width, frame);
+                    Ok(())
+                })?,
+                CaptureType::AudioOnly => (),
+            }
+
+            self.time_passed(self.frame_time());
+            unsafe { self.record_last_frame() }?;
+            self.acquired_image = false;
+
+            let samples = self.samples_to_capture(22050, SoundCaptureMode::Normal);
+            self.write_audio_frame(vec![0; samples as usize * 4]);
+        }
+
+        self.finish()?;
+        Ok(())
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn thread(
     vulkan: Option<Vulkan>,
-    mut muxer: Muxer,
+    mut muxer: Box<dyn FrameSink>,
+    mut segments: Option<Segments>,
+    audio_thread: Option<JoinHandle<(u64, eyre::Result<()>)>>,
+    mut overlay: Overlay,
     mut pixels: Option<Box<[u8]>>,
     mut sampling_buffers: Option<(Box<[u16]>, Box<[u8]>)>,
+    dither_width: Option<usize>,
+    muxed_video_frames: Arc<AtomicU64>,
     s: Sender<ThreadToMain>,
     r: Receiver<MainToThread>,
 ) {
+    let mut encoded_video_frames = 0;
+    let mut encoded_audio_samples = 0;
+
     while let Ok(message) = r.recv() {
+        let (frames, samples) = match &message {
+            MainToThread::Record { frames } => (*frames, 0),
+            MainToThread::Audio(data) => (0, audio_sample_count(data)),
+            _ => (0, 0),
+        };
+
         match process_message(
             vulkan.as_ref(),
-            &mut muxer,
+            &mut *muxer,
+            segments.as_mut(),
+            &mut overlay,
             &s,
             &mut pixels,
             &mut sampling_buffers,
+            dither_width,
             message,
         ) {
             Ok(done) => {
+                encoded_video_frames += frames;
+                encoded_audio_samples += samples;
+                muxed_video_frames.fetch_add(frames as u64, Ordering::Relaxed);
+
                 if done {
                     break;
                 }
@@ -562,16 +2026,107 @@ fn thread(
         }
     }
 
-    let output = muxer.close();
-    s.send(ThreadToMain::FfmpegOutput(output)).unwrap();
-}
+    if let Some(audio_thread) = audio_thread {
+        // The audio thread exits when the main thread stops sending it audio. Drop our receiver
+        // first so the main thread doesn't block on sending us messages in the meantime.
+        drop(r);
 
-fn process_message(
-    vulkan: Option<&Vulkan>,
-    muxer: &mut Muxer,
-    s: &Sender<ThreadToMain>,
+        let (samples, result) = audio_thread.join().unwrap();
+        encoded_audio_samples += samples;
+        if let Err(err) = result {
+            s.send(ThreadToMain::Error(err)).unwrap();
+        }
+    }
+
+    // Sent even after an error, so the counts reached until then are reported.
+    s.send(ThreadToMain::Stats {
+        video_frames: encoded_video_frames,
+        audio_samples: encoded_audio_samples,
+    })
+    .unwrap();
+
+    // Drop the segments first, as they share the muxer of the last segment.
+    let last_segment_checksum = segments.as_ref().and_then(Segments::checksum_path);
+    let segments_output = segments.map(|segments| segments.output);
+
+    match muxer.close() {
+        Ok(output) => {
+            let output = match segments_output {
+                Some(mut segments_output) => {
+                    segments_output.push_str(&output);
+                    segments_output
+                }
+                None => output,
+            };
+            s.send(ThreadToMain::FfmpegOutput(output)).unwrap();
+
+            if let Some(path) = last_segment_checksum {
+                if let Err(err) = write_checksum(&path) {
+                    s.send(ThreadToMain::Error(
+                        err.wrap_err("error writing the checksum"),
+                    ))
+                    .unwrap();
+                }
+            }
+        }
+        Err(err) => s
+            .send(ThreadToMain::Error(
+                eyre::Report::new(err).wrap_err("error finishing the recording"),
+            ))
+            .unwrap(),
+    }
+}
+
+/// Stores `err` into `error` unless there's an earlier error already, in which case it's logged.
+///
+/// Later errors are usually caused by the first one, so the first one is the one to report.
+fn keep_first_error(error: &mut Option<eyre::Report>, err: eyre::Report) {
+    match error {
+        Some(_) => warn!("another error while finishing the recording: {:?}", err),
+        None => *error = Some(err),
+    }
+}
+
+/// Writes the audio from `r` into `sink` until the sender is dropped or writing fails.
+///
+/// Returns the number of audio samples given to the sink along with the error, if any.
+fn audio_thread(sink: AudioSharedSink, r: Receiver<Vec<u8>>) -> (u64, eyre::Result<()>) {
+    let mut written_samples = 0;
+
+    while let Ok(samples) = r.recv() {
+        let _span = info_span!("audio").entered();
+
+        if let Err(err) = sink.write_audio_without_waiting(&samples) {
+            return (written_samples, Err(err.into()));
+        }
+        written_samples += audio_sample_count(&samples);
+    }
+
+    // The last audio could still be waiting for a video frame to be written.
+    if let Err(err) = sink.flush_audio() {
+        return (written_samples, Err(err.into()));
+    }
+
+    (written_samples, Ok(()))
+}
+
+/// Returns the number of audio samples in `data`.
+///
+/// One sample is two 16-bit channels.
+fn audio_sample_count(data: &[u8]) -> u64 {
+    data.len() as u64 / 4
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_message(
+    vulkan: Option<&Vulkan>,
+    muxer: &mut dyn FrameSink,
+    segments: Option<&mut Segments>,
+    overlay: &mut Overlay,
+    s: &Sender<ThreadToMain>,
     pixels: &mut Option<Box<[u8]>>,
     sampling_buffers: &mut Option<(Box<[u16]>, Box<[u8]>)>,
+    dither_width: Option<usize>,
     message: MainToThread,
 ) -> eyre::Result<bool> {
     match message {
@@ -589,6 +2144,11 @@ fn process_message(
 
             s.send(ThreadToMain::AcquiredImage).unwrap();
         }
+        MainToThread::UploadImage(pixels) => {
+            let _span = info_span!("upload").entered();
+
+            unsafe { vulkan.unwrap().upload_image(&pixels) }?;
+        }
         MainToThread::Captured { buffer } => {
             let old_pixels = pixels.replace(buffer);
 
@@ -614,8 +2174,30 @@ fn process_message(
 
             assert!(sampling_buffers.is_some() || pixels.is_some() || vulkan.is_some());
 
+            let mut segment_sink;
+            let muxer: &mut dyn FrameSink = if let Some(segments) = segments {
+                segment_sink = SegmentSink {
+                    inner: muxer,
+                    segments,
+                };
+                &mut segment_sink
+            } else {
+                muxer
+            };
+
+            let mut overlay_sink;
+            let muxer: &mut dyn FrameSink = if overlay.is_visible() {
+                overlay_sink = OverlaySink {
+                    inner: muxer,
+                    overlay,
+                };
+                &mut overlay_sink
+            } else {
+                muxer
+            };
+
             if let Some((sampling_buffer, output_buffer)) = sampling_buffers.as_mut() {
-                convert_and_zero(output_buffer, sampling_buffer);
+                convert_and_zero(output_buffer, sampling_buffer, dither_width);
 
                 for _ in 0..frames {
                     muxer.write_video_frame(output_buffer)?;
@@ -633,6 +2215,12 @@ fn process_message(
 
             muxer.write_audio_frame(&samples)?;
         }
+        MainToThread::Cursor(position) => {
+            overlay.cursor = position;
+        }
+        MainToThread::Analog(analog) => {
+            overlay.analog = Some(analog);
+        }
     }
 
     Ok(false)
@@ -664,14 +2252,1629 @@ fn accumulate(sampling_buffer: &mut [u16], pixels: &[u8], weight: f32) {
         });
 }
 
+/// 4×4 Bayer matrix for ordered dithering.
+const BAYER: [[u16; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Converts the RGB24 `sampling_buffer` into `output_buffer` and zeroes it.
+///
+/// If `dither_width` is set, the frame, which must be this wide, is converted with ordered
+/// dithering rather than rounding.
 #[instrument(skip_all)]
-fn convert_and_zero(output_buffer: &mut [u8], sampling_buffer: &mut [u16]) {
-    for (out, sample) in output_buffer.iter_mut().zip(&*sampling_buffer) {
-        // Using saturating_add is 80% faster according to benchmarks, likely because it removes the
-        // bounds check, which allows the loop to be vectorized.
-        *out = (sample.saturating_add(128) / 256) as u8;
+fn convert_and_zero(
+    output_buffer: &mut [u8],
+    sampling_buffer: &mut [u16],
+    dither_width: Option<usize>,
+) {
+    if let Some(width) = dither_width {
+        for (i, (out, sample)) in output_buffer.iter_mut().zip(&*sampling_buffer).enumerate() {
+            let pixel = i / 3;
+            let (x, y) = (pixel % width, pixel / width);
+            let threshold = BAYER[y % 4][x % 4] * 16 + 8;
+            *out = (sample.saturating_add(threshold) / 256) as u8;
+        }
+    } else {
+        for (out, sample) in output_buffer.iter_mut().zip(&*sampling_buffer) {
+            // Using saturating_add is 80% faster according to benchmarks, likely because it removes
+            // the bounds check, which allows the loop to be vectorized.
+            *out = (sample.saturating_add(128) / 256) as u8;
+        }
     }
 
     // Zeroing the buffer separately is 50% faster according to benchmarks.
     sampling_buffer.fill(0);
 }
+
+/// Fills an RGB24 `buffer` with color bars scrolled horizontally by `frame` pixels.
+fn draw_test_pattern(buffer: &mut [u8], width: i32, frame: usize) {
+    const BARS: [[u8; 3]; 7] = [
+        [192, 192, 192],
+        [192, 192, 0],
+        [0, 192, 192],
+        [0, 192, 0],
+        [192, 0, 192],
+        [192, 0, 0],
+        [0, 0, 192],
+    ];
+
+    let width = width as usize;
+    for row in buffer.chunks_exact_mut(width * 3) {
+        for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+            let bar = (x + frame) * BARS.len() / width % BARS.len();
+            pixel.copy_from_slice(&BARS[bar]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    use itertools::Itertools;
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::modules::capture::options::{GifConfig, ImageFormat, ProxyConfig};
+    use crate::modules::capture::sinks::NewSegmentMuxer;
+
+    #[derive(Debug, Default)]
+    struct MemorySinkData {
+        video_frames: Vec<Vec<u8>>,
+        audio: Vec<u8>,
+    }
+
+    /// Sink storing everything in memory. Clones share the same storage.
+    #[derive(Debug, Clone, Default)]
+    struct MemorySink(Arc<Mutex<MemorySinkData>>);
+
+    impl MemorySink {
+        fn video_frame_count(&self) -> usize {
+            self.0.lock().unwrap().video_frames.len()
+        }
+
+        fn video_frames(&self) -> Vec<Vec<u8>> {
+            self.0.lock().unwrap().video_frames.clone()
+        }
+
+        fn audio_len(&self) -> usize {
+            self.0.lock().unwrap().audio.len()
+        }
+    }
+
+    impl FrameSink for MemorySink {
+        fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.0.lock().unwrap().video_frames.push(data.to_vec());
+            Ok(())
+        }
+
+        fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.0.lock().unwrap().audio.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn close(self: Box<Self>) -> Result<String, io::Error> {
+            Ok(String::new())
+        }
+    }
+
+    fn recorder_with_sink(fps: u64, sink: &MemorySink) -> Recorder {
+        recorder_with_options(fps, Box::new(sink.clone()), RecorderOptions::default())
+    }
+
+    fn recorder_with_options(
+        fps: u64,
+        sink: Box<dyn FrameSink>,
+        options: RecorderOptions,
+    ) -> Recorder {
+        Recorder::with_sink(
+            2,
+            2,
+            fps,
+            1.,
+            CaptureType::ReadPixels,
+            None,
+            sink,
+            0.,
+            0.,
+            options,
+        )
+    }
+
+    fn advance_frame(recorder: &mut Recorder, time: f64) {
+        recorder.time_passed(time);
+        unsafe { recorder.record_last_frame() }.unwrap();
+    }
+
+    #[test]
+    fn focus_loss_pauses_recording() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_sink(60, &sink);
+        recorder.set_auto_pause_on_focus_loss(true);
+
+        advance_frame(&mut recorder, 1. / 60.);
+        assert!(!recorder.is_paused());
+
+        recorder.notify_focus(false);
+        assert!(recorder.is_paused());
+        advance_frame(&mut recorder, 1. / 60.);
+        advance_frame(&mut recorder, 1. / 60.);
+
+        recorder.notify_focus(true);
+        assert!(!recorder.is_paused());
+        advance_frame(&mut recorder, 1. / 60.);
+
+        recorder.finish().unwrap();
+        assert_eq!(sink.video_frame_count(), 2);
+        assert_eq!(sink.audio_len(), 0);
+    }
+
+    #[test]
+    fn audio_only_records_no_video() {
+        let sink = MemorySink::default();
+        let mut recorder = Recorder::with_sink(
+            2,
+            2,
+            60,
+            1.,
+            CaptureType::AudioOnly,
+            None,
+            Box::new(sink.clone()),
+            0.,
+            0.,
+            RecorderOptions::default(),
+        );
+
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 1. / 60.);
+            let samples = recorder.samples_to_capture(22050, SoundCaptureMode::Normal);
+            recorder.write_audio_frame(vec![0; samples as usize * 4]);
+        }
+        assert_eq!(recorder.video_remainder, 0.);
+
+        recorder.finish().unwrap();
+        assert_eq!(sink.video_frame_count(), 0);
+        assert_eq!(sink.audio_len(), (22050 / 20) * 4);
+    }
+
+    #[test]
+    fn no_sound_skips_audio() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_options(
+            60,
+            Box::new(sink.clone()),
+            RecorderOptions {
+                no_sound: true,
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 1. / 60.);
+            assert_eq!(
+                recorder.samples_to_capture(22050, SoundCaptureMode::Normal),
+                0
+            );
+            recorder.write_audio_frame(vec![0; 4]);
+        }
+
+        recorder.finish().unwrap();
+        assert_eq!(sink.video_frame_count(), 3);
+        assert_eq!(sink.audio_len(), 0);
+    }
+
+    #[test]
+    fn pause_skips_time_without_catching_up() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_sink(60, &sink);
+
+        advance_frame(&mut recorder, 1. / 60.);
+        assert_eq!(
+            recorder.samples_to_capture(22050, SoundCaptureMode::Normal),
+            367
+        );
+
+        recorder.pause();
+        assert!(recorder.is_paused());
+        for _ in 0..30 {
+            advance_frame(&mut recorder, 1. / 60.);
+            recorder.write_audio_frame(vec![0; 4]);
+            assert_eq!(
+                recorder.samples_to_capture(22050, SoundCaptureMode::Normal),
+                0
+            );
+        }
+        assert!((recorder.paused_time() - 0.5).abs() < 1e-9);
+
+        recorder.resume();
+        assert!(!recorder.is_paused());
+        advance_frame(&mut recorder, 1. / 60.);
+        // The half sample left over from before the pause is dropped.
+        assert_eq!(
+            recorder.samples_to_capture(22050, SoundCaptureMode::Normal),
+            367
+        );
+
+        recorder.finish().unwrap();
+        assert_eq!(sink.video_frame_count(), 2);
+        assert_eq!(sink.audio_len(), 0);
+    }
+
+    #[test]
+    fn self_test_writes_moving_pattern() {
+        let sink = MemorySink::default();
+        let recorder = recorder_with_sink(60, &sink);
+        recorder.record_test_pattern(3).unwrap();
+
+        let frames = sink.video_frames();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|frame| frame.len() == 2 * 2 * 3));
+        assert_ne!(frames[0], frames[1], "the pattern should move");
+        assert!(sink.audio_len() > 0);
+
+        // Odd resolutions are padded like in a normal recording.
+        let sink = MemorySink::default();
+        let padded = PadSink::new(Box::new(sink.clone()), (3, 3), (4, 4));
+        let recorder = Recorder::with_sink(
+            3,
+            3,
+            60,
+            1.,
+            CaptureType::ReadPixels,
+            None,
+            Box::new(padded),
+            0.,
+            0.,
+            RecorderOptions::default(),
+        );
+        recorder.record_test_pattern(3).unwrap();
+
+        let frames = sink.video_frames();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|frame| frame.len() == 4 * 4 * 3));
+        assert_ne!(frames[0], frames[1], "the pattern should move");
+    }
+
+    /// Sink that waits for a signal before writing every video frame.
+    struct GatedSink {
+        gate: Receiver<()>,
+        inner: MemorySink,
+    }
+
+    impl FrameSink for GatedSink {
+        fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.gate.recv().unwrap();
+            self.inner.write_video_frame(data)
+        }
+
+        fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.inner.write_audio_frame(data)
+        }
+
+        fn close(self: Box<Self>) -> Result<String, io::Error> {
+            Box::new(self.inner).close()
+        }
+    }
+
+    #[test]
+    fn separate_audio_thread_does_not_wait_for_video() {
+        const FRAMES: usize = 40;
+        // More than fit into the channel to the audio thread.
+        const AUDIO_FRAMES: usize = 200;
+
+        let (release, gate) = bounded(0);
+        let inner = MemorySink::default();
+        let sink = GatedSink {
+            gate,
+            inner: inner.clone(),
+        };
+        let options = RecorderOptions {
+            separate_audio_thread: true,
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(sink), options);
+
+        for _ in 0..FRAMES {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+
+        // The video frames aren't let through until the audio is written. If the audio waited for
+        // the video, this would fill the channel to the audio thread and block forever.
+        for _ in 0..AUDIO_FRAMES {
+            recorder.write_audio_frame(vec![0; 4]);
+        }
+
+        let releaser = thread::spawn(move || {
+            for _ in 0..FRAMES {
+                release.send(()).unwrap();
+            }
+        });
+        recorder.finish().unwrap();
+        releaser.join().unwrap();
+
+        assert_eq!(inner.video_frame_count(), FRAMES);
+        assert_eq!(inner.audio_len(), AUDIO_FRAMES * 4);
+    }
+
+    #[test]
+    fn low_latency_records_single_frames() {
+        let sink = MemorySink::default();
+        let options = RecorderOptions {
+            low_latency: true,
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(sink.clone()), options);
+
+        // Each of these game frames spans five video frames.
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 5. / 60.);
+        }
+        assert_eq!(recorder.recorded_video_frames, 3);
+
+        // The extra frames are carried over to the next game frames, one per game frame.
+        assert_eq!(recorder.current_frame_length(), 12);
+        for left in (0..12).rev() {
+            advance_frame(&mut recorder, 0.);
+            assert_eq!(recorder.current_frame_length(), left);
+        }
+
+        recorder.finish().unwrap();
+        assert_eq!(sink.video_frame_count(), 15);
+    }
+
+    /// Sink that writes everything into a file.
+    struct FileSink(File);
+
+    impl FrameSink for FileSink {
+        fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.0.write_all(data)
+        }
+
+        fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.0.write_all(data)
+        }
+
+        fn close(self: Box<Self>) -> Result<String, io::Error> {
+            Ok(String::new())
+        }
+    }
+
+    #[test]
+    fn checksum_matches_output() {
+        let path = std::env::temp_dir().join(format!(
+            "bxt-rs-checksum-matches-output-{}.bin",
+            std::process::id()
+        ));
+        let mut checksum_path = path.as_os_str().to_owned();
+        checksum_path.push(".sha256");
+
+        let sink = FileSink(File::create(&path).unwrap());
+        let mut recorder = recorder_with_options(60, Box::new(sink), RecorderOptions::default());
+        recorder.checksum_file = Some(path.clone());
+
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 1. / 60.);
+            recorder.write_audio_frame(vec![1, 2, 3, 4]);
+        }
+        recorder.finish().unwrap();
+
+        let contents = std::fs::read(&path).unwrap();
+        let checksum = std::fs::read_to_string(&checksum_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&checksum_path).unwrap();
+
+        assert!(!contents.is_empty());
+        let expected = format!(
+            "{:x}  {}\n",
+            Sha256::digest(&contents),
+            path.file_name().unwrap().to_string_lossy()
+        );
+        assert_eq!(checksum, expected);
+    }
+
+    #[test]
+    fn two_pass_commands_use_computed_bitrate() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_sink(60, &sink);
+        recorder.reencode = Some(Reencode {
+            intermediate: PathBuf::from("output.mp4.intermediate.mov"),
+            output: PathBuf::from("output.mp4"),
+            target_size_mb: Some(10),
+            fade_in_seconds: 0.,
+            fade_out_seconds: 0.,
+        });
+
+        // Two seconds of video.
+        for _ in 0..120 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+
+        let commands = recorder.reencode_commands().unwrap();
+        let [first, second] = &commands[..] else {
+            panic!("expected two passes, got {commands:?}");
+        };
+
+        // 10 MB over 2 seconds is 40000 kbit/s, minus the audio bitrate.
+        for command in [first, second] {
+            assert_eq!(command[0], "ffmpeg");
+            assert!(command.windows(2).any(|args| args == ["-b:v", "39872k"]));
+            assert!(command
+                .windows(2)
+                .any(|args| args == ["-i", "output.mp4.intermediate.mov"]));
+        }
+        assert!(first.windows(2).any(|args| args == ["-pass", "1"]));
+        assert!(second.windows(2).any(|args| args == ["-pass", "2"]));
+        assert_eq!(second.last().unwrap(), "output.mp4");
+
+        // Don't actually run the re-encoding.
+        recorder.reencode = None;
+        recorder.finish().unwrap();
+        assert_eq!(sink.video_frame_count(), 120);
+    }
+
+    #[test]
+    fn cursor_is_drawn_when_visible() {
+        let sink = MemorySink::default();
+        let mut recorder = Recorder::with_sink(
+            32,
+            32,
+            60,
+            1.,
+            CaptureType::ReadPixels,
+            None,
+            Box::new(sink.clone()),
+            0.,
+            0.,
+            RecorderOptions::default(),
+        );
+
+        recorder.set_cursor(4, 4, true);
+        advance_frame(&mut recorder, 1. / 60.);
+        recorder.set_cursor(4, 4, false);
+        advance_frame(&mut recorder, 1. / 60.);
+        recorder.finish().unwrap();
+
+        let frames = sink.video_frames();
+        assert_eq!(frames.len(), 2);
+        assert!(
+            frames[1].iter().all(|&x| x == 0),
+            "the base frame should be untouched"
+        );
+
+        // The fill just below the tip of the arrow, keeping in mind that the frame is flipped.
+        let offset = ((32 - 1 - (4 + 2)) * 32 + (4 + 1)) * 3;
+        assert_eq!(frames[0][offset..offset + 3], [255; 3]);
+        assert_ne!(frames[0], frames[1]);
+    }
+
+    #[test]
+    fn frame_report_drift() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_sink(60, &sink);
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+        let report = recorder.finish_with_report();
+        assert_eq!(report.encoded_frames, 3);
+        assert_eq!(report.drift(), 0);
+
+        // Low-latency mode delays all but one video frame of every game frame.
+        let options = RecorderOptions {
+            low_latency: true,
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(MemorySink::default()), options);
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 5. / 60.);
+        }
+        let report = recorder.finish_with_report();
+        assert_eq!(report.encoded_frames, 3);
+        assert_eq!(report.drift(), 12);
+
+        // Until shorter game frames let the video catch up.
+        let options = RecorderOptions {
+            low_latency: true,
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(MemorySink::default()), options);
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 5. / 60.);
+        }
+        for _ in 0..24 {
+            advance_frame(&mut recorder, 1. / 120.);
+        }
+        let report = recorder.finish_with_report();
+        assert_eq!(report.encoded_frames, 27);
+        assert_eq!(report.drift(), 0);
+    }
+
+    #[test]
+    fn tee_sink_feeds_both_sinks() {
+        let master = MemorySink::default();
+        let preview = MemorySink::default();
+        let sink = TeeSink(Box::new(master.clone()), Box::new(preview.clone()));
+        let mut recorder = recorder_with_options(60, Box::new(sink), RecorderOptions::default());
+
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 1. / 60.);
+            recorder.write_audio_frame(vec![0; 4]);
+        }
+        recorder.finish().unwrap();
+
+        assert_eq!(master.video_frame_count(), 3);
+        assert_eq!(preview.video_frame_count(), 3);
+        assert_eq!(master.video_frames(), preview.video_frames());
+        assert_eq!(master.audio_len(), preview.audio_len());
+    }
+
+    #[test]
+    fn film_24fps_paces_frames_evenly() {
+        let options = RecorderOptions {
+            film_24fps: true,
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(MemorySink::default()), options);
+
+        // Game frames that produced a video frame.
+        let mut recorded_at = Vec::new();
+        for frame in 0..60 {
+            let before = recorder.recorded_video_frames;
+            advance_frame(&mut recorder, 1. / 60.);
+            match recorder.recorded_video_frames - before {
+                0 => (),
+                1 => recorded_at.push(frame),
+                frames => panic!("game frame {frame} produced {frames} video frames"),
+            }
+        }
+        recorder.finish().unwrap();
+
+        assert!((23..=25).contains(&recorded_at.len()), "{recorded_at:?}");
+        for (prev, next) in recorded_at.iter().tuple_windows() {
+            assert!((2..=3).contains(&(next - prev)), "{recorded_at:?}");
+        }
+    }
+
+    #[test]
+    fn fps_fraction_sets_exact_time_base() {
+        let options = RecorderOptions {
+            fps_fraction: Some((30000, 1001)),
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(30, Box::new(MemorySink::default()), options);
+        assert_eq!(recorder.frame_time(), 1001. / 30000.);
+
+        // One game frame per video frame never drifts into a dropped or a duplicated frame.
+        for _ in 0..3000 {
+            let before = recorder.recorded_video_frames;
+            advance_frame(&mut recorder, 1001. / 30000.);
+            assert_eq!(recorder.recorded_video_frames - before, 1);
+        }
+        recorder.finish().unwrap();
+
+        // Film frame rate takes priority.
+        let options = RecorderOptions {
+            fps_fraction: Some((30000, 1001)),
+            film_24fps: true,
+            ..Default::default()
+        };
+        assert_eq!(frame_rate(30, &options), FILM_FPS);
+    }
+
+    #[test]
+    fn fade_filters_use_durations() {
+        let mut recorder = recorder_with_sink(60, &MemorySink::default());
+        recorder.reencode = Some(Reencode {
+            intermediate: PathBuf::from("output.mp4.intermediate.mov"),
+            output: PathBuf::from("output.mp4"),
+            target_size_mb: None,
+            fade_in_seconds: 1.,
+            fade_out_seconds: 0.5,
+        });
+
+        // Two seconds of video.
+        for _ in 0..120 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+
+        let commands = recorder.reencode_commands().unwrap();
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0]
+            .windows(2)
+            .any(|args| args == ["-vf", "fade=t=in:st=0:d=1,fade=t=out:st=1.5:d=0.5"]));
+        assert_eq!(commands[0].last().unwrap(), "output.mp4");
+
+        // Don't actually run the re-encoding.
+        recorder.reencode = None;
+        recorder.finish().unwrap();
+    }
+
+    #[test]
+    fn edl_out_timecode_matches_duration() {
+        let path = std::env::temp_dir().join(format!(
+            "bxt-rs-edl-out-timecode-matches-duration-{}.edl",
+            std::process::id()
+        ));
+
+        let mut recorder = recorder_with_sink(60, &MemorySink::default());
+        recorder.edl = Some(Edl {
+            path: path.clone(),
+            output: PathBuf::from("output.mp4"),
+            rate: TimecodeRate::new(60, 1).unwrap(),
+        });
+
+        // 2.5 seconds of video.
+        for _ in 0..150 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+        recorder.finish().unwrap();
+
+        let edl = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(edl.starts_with("TITLE: output.mp4\n"));
+        assert!(edl.contains(
+            "001  AX       B     C        \
+             00:00:00:00 00:00:02:30 00:00:00:00 00:00:02:30\n"
+        ));
+    }
+
+    #[test]
+    fn proxy_fps_is_checked_against_the_exact_rate() {
+        let options = RecorderOptions {
+            fps_fraction: Some((30000, 1001)),
+            proxy: Some(ProxyConfig {
+                fps: 30,
+                scale: 0.5,
+            }),
+            ..Default::default()
+        };
+        // The check fails before anything gets initialized.
+        let result = unsafe {
+            Recorder::init(
+                64,
+                64,
+                30,
+                1.,
+                CaptureType::ReadPixels,
+                "/nonexistent/output.mp4",
+                None,
+                0.,
+                0.,
+                options,
+                None,
+            )
+        };
+        let err = result.err().unwrap();
+        assert!(err.to_string().contains("proxy FPS"));
+    }
+
+    #[test]
+    fn dithering_smooths_gradient() {
+        const WIDTH: usize = 64;
+        const HEIGHT: usize = 4;
+
+        // A gradient rising by a single 8-bit step over the whole width.
+        let gradient = (0..WIDTH * HEIGHT)
+            .flat_map(|pixel| std::iter::repeat(100 * 256 + (pixel % WIDTH) as u16 * 4).take(3))
+            .collect::<Vec<_>>();
+
+        // Returns the number of distinct red channel averages over 4×4 blocks.
+        let distinct_block_averages = |dither_width| {
+            let mut sampling_buffer = gradient.clone();
+            let mut output_buffer = vec![0; gradient.len()];
+            convert_and_zero(&mut output_buffer, &mut sampling_buffer, dither_width);
+            assert!(sampling_buffer.iter().all(|&sample| sample == 0));
+
+            (0..WIDTH / 4)
+                .map(|block| {
+                    (0..HEIGHT)
+                        .flat_map(|y| (0..4).map(move |x| (y * WIDTH + block * 4 + x) * 3))
+                        .map(|i| output_buffer[i] as u32)
+                        .sum::<u32>()
+                })
+                .unique()
+                .count()
+        };
+
+        let plain = distinct_block_averages(None);
+        let dithered = distinct_block_averages(Some(WIDTH));
+        assert_eq!(plain, 2);
+        assert!(dithered > plain, "{dithered} <= {plain}");
+    }
+
+    /// Sink that takes a while to write every video frame.
+    struct SlowSink(MemorySink);
+
+    impl FrameSink for SlowSink {
+        fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            std::thread::sleep(Duration::from_millis(1));
+            self.0.write_video_frame(data)
+        }
+
+        fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.0.write_audio_frame(data)
+        }
+
+        fn close(self: Box<Self>) -> Result<String, io::Error> {
+            Box::new(self.0).close()
+        }
+    }
+
+    #[test]
+    fn offline_mode_drops_no_frames() {
+        let sink = MemorySink::default();
+        let options = RecorderOptions {
+            offline: true,
+            low_latency: true,
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(SlowSink(sink.clone())), options);
+
+        // Each of these game frames spans two video frames, and the game frames come in much
+        // faster than the sink writes them, so the queue fills up.
+        for _ in 0..100 {
+            advance_frame(&mut recorder, 2. / 60.);
+        }
+
+        let report = recorder.finish_with_report();
+        assert_eq!(report.encoded_frames, 200);
+        assert_eq!(report.drift(), 0);
+        assert_eq!(sink.video_frame_count(), 200);
+    }
+
+    #[test]
+    fn comparison_uses_ssim_and_psnr() {
+        let mut recorder = recorder_with_sink(60, &MemorySink::default());
+        recorder.comparison = Some(Comparison {
+            output: PathBuf::from("output.mp4"),
+            reference: PathBuf::from("reference.mov"),
+        });
+
+        let command = comparison_command(recorder.comparison.as_ref().unwrap());
+        assert_eq!(command[0], "ffmpeg");
+        assert!(command.windows(2).any(|args| args == ["-i", "output.mp4"]));
+        assert!(command
+            .windows(2)
+            .any(|args| args == ["-i", "reference.mov"]));
+
+        let filters = &command[command.iter().position(|arg| arg == "-lavfi").unwrap() + 1];
+        assert!(filters.contains("ssim=stats_file='output.mp4.ssim.log'"));
+        assert!(filters.contains("psnr=stats_file='output.mp4.psnr.log'"));
+
+        // Don't actually run the comparison.
+        recorder.comparison = None;
+        recorder.finish().unwrap();
+    }
+
+    #[test]
+    fn analog_stick_dot_follows_input() {
+        let sink = MemorySink::default();
+        let mut recorder = Recorder::with_sink(
+            64,
+            64,
+            60,
+            1.,
+            CaptureType::ReadPixels,
+            None,
+            Box::new(sink.clone()),
+            0.,
+            0.,
+            RecorderOptions::default(),
+        );
+
+        recorder.set_analog_input(0., 0., 0., 0.);
+        advance_frame(&mut recorder, 1. / 60.);
+        recorder.set_analog_input(1., 1., 0., 0.);
+        advance_frame(&mut recorder, 1. / 60.);
+        recorder.finish().unwrap();
+
+        // Returns whether the pixel at `x`, `y` from the top-left corner is white, keeping in mind
+        // that the frame is flipped.
+        let is_white = |frame: &[u8], x: usize, y: usize| {
+            let offset = ((64 - 1 - y) * 64 + x) * 3;
+            frame[offset..offset + 3] == [255; 3]
+        };
+
+        let frames = sink.video_frames();
+        let center = (10, 64 - 1 - 10);
+
+        // Centered stick.
+        assert!(is_white(&frames[0], center.0, center.1));
+
+        // Stick pushed to the top-right.
+        assert!(!is_white(&frames[1], center.0, center.1));
+        assert!(is_white(&frames[1], center.0 + 6, center.1 - 6));
+    }
+
+    #[test]
+    fn proxy_gets_fewer_smaller_frames() {
+        let sink = MemorySink::default();
+        let (proxy_width, proxy_height) = proxy_size(8, 8, 0.5);
+        assert_eq!((proxy_width, proxy_height), (4, 4));
+
+        let proxy_sink = ProxySink::new(
+            Box::new(sink.clone()),
+            PixelFormat::Rgb24Flipped,
+            (8, 8),
+            (proxy_width, proxy_height),
+            15. / 60.,
+        );
+        let mut recorder = Recorder::with_sink(
+            8,
+            8,
+            60,
+            1.,
+            CaptureType::ReadPixels,
+            None,
+            Box::new(proxy_sink),
+            0.,
+            0.,
+            RecorderOptions::default(),
+        );
+
+        for _ in 0..60 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+        recorder.finish().unwrap();
+
+        let frames = sink.video_frames();
+        assert!((14..=16).contains(&frames.len()), "{} frames", frames.len());
+        assert!(frames.iter().all(|frame| frame.len() == 4 * 4 * 3));
+    }
+
+    /// Advances the recorder by a game frame with every pixel set to `value`.
+    fn advance_frame_with_pixels(recorder: &mut Recorder, time: f64, value: u8) {
+        recorder.time_passed(time);
+
+        recorder
+            .capture_pixels(|buffer| {
+                buffer.fill(value);
+                Ok(())
+            })
+            .unwrap();
+
+        unsafe { recorder.record_last_frame() }.unwrap();
+    }
+
+    #[test]
+    fn motion_blur_weights_add_up() {
+        let sink = MemorySink::default();
+        let options = RecorderOptions {
+            motion_blur: Some(MotionBlur {
+                subframes: 4,
+                shutter: 1.,
+            }),
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(sink.clone()), options);
+        assert!((recorder.time_for_current_frame() - 1. / 240.).abs() < 1e-9);
+
+        // Game frames spanning a non-integer number of sub-frames.
+        for _ in 0..100 {
+            advance_frame_with_pixels(&mut recorder, 1. / 100., 255);
+        }
+        recorder.finish().unwrap();
+
+        // Fully white frames blend into fully white frames.
+        let frames = sink.video_frames();
+        assert!((59..=60).contains(&frames.len()), "{} frames", frames.len());
+        for frame in frames {
+            assert!(frame.iter().all(|&x| x >= 254), "{frame:?}");
+        }
+    }
+
+    #[test]
+    fn motion_blur_with_one_subframe_does_not_blend() {
+        let sink = MemorySink::default();
+        let options = RecorderOptions {
+            motion_blur: Some(MotionBlur {
+                subframes: 1,
+                shutter: 1.,
+            }),
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(sink.clone()), options);
+        assert!(!recorder.is_sampling());
+        assert!((recorder.time_for_current_frame() - 1. / 60.).abs() < 1e-9);
+
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+        recorder.finish().unwrap();
+        assert_eq!(sink.video_frame_count(), 3);
+    }
+
+    #[test]
+    fn odd_resolution_is_padded_with_black() {
+        assert_eq!(padded_size(1919, 1079), (1920, 1080));
+        assert_eq!(padded_size(1920, 1080), (1920, 1080));
+
+        let sink = MemorySink::default();
+        let pad_sink = PadSink::new(Box::new(sink.clone()), (3, 3), padded_size(3, 3));
+        let mut recorder = Recorder::with_sink(
+            3,
+            3,
+            60,
+            1.,
+            CaptureType::ReadPixels,
+            None,
+            Box::new(pad_sink),
+            0.,
+            0.,
+            RecorderOptions::default(),
+        );
+
+        advance_frame_with_pixels(&mut recorder, 1. / 60., 255);
+        advance_frame_with_pixels(&mut recorder, 1. / 60., 255);
+        recorder.finish().unwrap();
+
+        let frames = sink.video_frames();
+        assert!(!frames.is_empty());
+        for frame in frames {
+            assert_eq!(frame.len(), 4 * 4 * 3);
+
+            // The first row is the padding at the bottom of the flipped frame.
+            let (padding, rows) = frame.split_at(4 * 3);
+            assert!(padding.iter().all(|&x| x == 0));
+            for row in rows.chunks_exact(4 * 3) {
+                assert!(row[..3 * 3].iter().all(|&x| x == 255));
+                assert!(row[3 * 3..].iter().all(|&x| x == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn frame_callback_modifies_muxed_frames() {
+        let sink = MemorySink::default();
+        let callback_sink = CallbackSink {
+            inner: Box::new(sink.clone()),
+            callback: Box::new(|frame, width, height| {
+                assert_eq!((width, height), (2, 2));
+                frame[0] = 42;
+            }),
+            width: 2,
+            height: 2,
+            frame: Vec::new(),
+        };
+        let mut recorder = Recorder::with_sink(
+            2,
+            2,
+            60,
+            1.,
+            CaptureType::ReadPixels,
+            None,
+            Box::new(callback_sink),
+            0.,
+            0.,
+            RecorderOptions::default(),
+        );
+
+        advance_frame_with_pixels(&mut recorder, 1. / 60., 255);
+        advance_frame_with_pixels(&mut recorder, 1. / 60., 255);
+        recorder.finish().unwrap();
+
+        let frames = sink.video_frames();
+        assert!(!frames.is_empty());
+        for frame in frames {
+            assert_eq!(frame[0], 42);
+            assert!(frame[1..].iter().all(|&x| x == 255));
+        }
+    }
+
+    #[test]
+    fn recorded_frames_follow_muxing() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_sink(60, &sink);
+
+        for _ in 0..10 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+        recorder.reset_opengl();
+        for _ in 0..5 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+
+        // Wait for the recording thread to catch up.
+        let expected = recorder.recorded_video_frames as u64;
+        let start = Instant::now();
+        while recorder.recorded_frames() < expected && start.elapsed().as_secs() < 5 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(recorder.recorded_frames(), expected);
+        assert!((recorder.recorded_seconds() - expected as f64 / 60.).abs() < 1e-9);
+
+        recorder.finish().unwrap();
+        assert_eq!(sink.video_frames().len() as u64, expected);
+    }
+
+    #[test]
+    fn image_sequence_duplicates_held_frames() {
+        let dir = std::env::temp_dir().join(format!(
+            "bxt-rs-image-sequence-duplicates-held-frames-{}",
+            std::process::id()
+        ));
+
+        let sink = ImageSequenceSink::create(&dir, ImageFormat::Tga, 2, 2).unwrap();
+        let mut recorder = recorder_with_options(60, Box::new(sink), RecorderOptions::default());
+        // A game frame lasting three video frames.
+        advance_frame(&mut recorder, 3. / 60.);
+        advance_frame(&mut recorder, 1. / 60.);
+        recorder.finish().unwrap();
+
+        let frames = (1..=4)
+            .map(|frame| std::fs::read(dir.join(format!("frame_{frame:08}.tga"))).unwrap())
+            .collect::<Vec<_>>();
+        assert!(frames.iter().all(|frame| *frame == frames[0]));
+        assert!(!dir.join("frame_00000005.tga").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(
+            OutputTarget::from_filename("output.mp4"),
+            OutputTarget::Video
+        ));
+        match OutputTarget::from_filename("captures/frames.PNG") {
+            OutputTarget::ImageSequence { dir, format } => {
+                assert_eq!(dir, Path::new("captures/frames"));
+                assert_eq!(format, ImageFormat::Png);
+            }
+            target => panic!("unexpected {target:?}"),
+        }
+    }
+
+    #[test]
+    fn scene_changes_start_chapters() {
+        let sink = MemorySink::default();
+        let options = RecorderOptions {
+            scene_split_threshold: Some(0.5),
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(sink.clone()), options);
+
+        // A small change in brightness doesn't start a new scene.
+        for value in [0, 0, 255, 255, 0, 255, 245] {
+            advance_frame_with_pixels(&mut recorder, 1. / 60., value);
+        }
+
+        let report = recorder.finish_with_report();
+        assert_eq!(sink.video_frame_count(), 7);
+
+        let frames = report
+            .chapters
+            .iter()
+            .map(|start| (start * 60.).round() as usize)
+            .collect::<Vec<_>>();
+        assert_eq!(frames, [0, 2, 4, 5]);
+
+        let metadata = chapters_metadata(&report.chapters, 7. / 60.);
+        assert!(metadata.starts_with(";FFMETADATA1\n"));
+        assert!(metadata.contains("START=33\nEND=67\ntitle=Scene 2\n"));
+        assert!(metadata.ends_with("START=83\nEND=117\ntitle=Scene 4\n"));
+    }
+
+    #[test]
+    fn samples_are_capped_and_carried_over() {
+        let options = RecorderOptions {
+            max_samples_per_call: Some(1024),
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(MemorySink::default()), options);
+
+        // A lag spike worth 4096 samples.
+        advance_frame(&mut recorder, 0.125);
+        assert_eq!(
+            recorder.samples_to_capture(32768, SoundCaptureMode::Normal),
+            1024
+        );
+        assert_eq!(
+            recorder.samples_to_capture(32768, SoundCaptureMode::Normal),
+            1024
+        );
+
+        // The rest is not capped when finishing.
+        advance_frame(&mut recorder, 0.125);
+        assert_eq!(
+            recorder.samples_to_capture(32768, SoundCaptureMode::Remaining { extra: 0. }),
+            6144
+        );
+        assert_eq!(
+            recorder.samples_to_capture(32768, SoundCaptureMode::Normal),
+            0
+        );
+
+        recorder.finish().unwrap();
+    }
+
+    #[test]
+    fn gif_preview_commands_use_window() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_sink(60, &sink);
+        recorder.gif_preview = Some(GifPreview {
+            input: PathBuf::from("output.mp4"),
+            output: PathBuf::from("output.mp4.gif"),
+            palette: PathBuf::from("output.mp4.palette.png"),
+            config: GifConfig {
+                start_seconds: 0.5,
+                duration_seconds: 1.,
+                width: 320,
+                fps: 15,
+            },
+        });
+
+        // Two seconds of video.
+        for _ in 0..120 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+
+        let commands = recorder.gif_preview_commands().unwrap().unwrap();
+        let [palettegen, paletteuse] = &commands[..] else {
+            panic!("expected two passes, got {commands:?}");
+        };
+
+        let filters = "trim=start=0.5:duration=1,setpts=PTS-STARTPTS,fps=15,\
+                       scale=320:-1:flags=lanczos";
+        let palettegen_filters = format!("{filters},palettegen");
+        assert!(palettegen
+            .windows(2)
+            .any(|args| args == ["-vf", palettegen_filters.as_str()]));
+        assert_eq!(palettegen.last().unwrap(), "output.mp4.palette.png");
+
+        let paletteuse_filters = format!("[0:v]{filters}[preview];[preview][1:v]paletteuse");
+        assert!(paletteuse
+            .windows(2)
+            .any(|args| args == ["-i", "output.mp4.palette.png"]));
+        assert!(paletteuse
+            .windows(2)
+            .any(|args| args == ["-lavfi", paletteuse_filters.as_str()]));
+        assert_eq!(paletteuse.last().unwrap(), "output.mp4.gif");
+
+        // The window must fit into the recording.
+        recorder.gif_preview.as_mut().unwrap().config.start_seconds = 1.5;
+        assert!(recorder.gif_preview_commands().unwrap().is_err());
+
+        // Don't actually make the preview.
+        recorder.gif_preview = None;
+        recorder.finish().unwrap();
+    }
+
+    /// Sink that fails to write video frames after the first `frames_left`.
+    struct FailingSink {
+        inner: MemorySink,
+        frames_left: usize,
+    }
+
+    impl FrameSink for FailingSink {
+        fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            if self.frames_left == 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "sink is full"));
+            }
+
+            self.frames_left -= 1;
+            self.inner.write_video_frame(data)
+        }
+
+        fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.inner.write_audio_frame(data)
+        }
+
+        fn close(self: Box<Self>) -> Result<String, io::Error> {
+            Box::new(self.inner).close()
+        }
+    }
+
+    #[test]
+    fn finish_returns_stats() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_sink(60, &sink);
+
+        for _ in 0..3 {
+            recorder.write_audio_frame(vec![0; 100 * 4]);
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+
+        let stats = recorder.finish().unwrap();
+        assert_eq!(stats.video_frames, 3);
+        assert_eq!(stats.audio_samples, 300);
+        assert_eq!(stats.dropped_frames, 0);
+    }
+
+    #[test]
+    fn finish_returns_stats_after_error() {
+        let sink = FailingSink {
+            inner: MemorySink::default(),
+            frames_left: 2,
+        };
+        let mut recorder = recorder_with_options(60, Box::new(sink), RecorderOptions::default());
+
+        recorder.write_audio_frame(vec![0; 10 * 4]);
+        for _ in 0..5 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+
+        let report = recorder.finish_with_report();
+        assert!(report.error.is_some());
+        let stats = report.stats;
+        assert_eq!(stats.video_frames, 2);
+        assert_eq!(stats.audio_samples, 10);
+        assert_eq!(stats.dropped_frames, 3);
+    }
+
+    #[test]
+    fn full_queue_drops_frames_without_waiting() {
+        const FRAMES: usize = 40;
+
+        let (release, gate) = bounded(0);
+        let inner = MemorySink::default();
+        let sink = GatedSink {
+            gate,
+            inner: inner.clone(),
+        };
+        let options = RecorderOptions {
+            queue_size: Some(4),
+            queue_policy: QueuePolicy::DropNewest,
+            ..Default::default()
+        };
+        let mut recorder = recorder_with_options(60, Box::new(sink), options);
+
+        // The sink doesn't take any video frames until the loop is over, so with the default
+        // policy this would wait forever for the pixel buffer or for the queue.
+        for value in 0..FRAMES {
+            advance_frame_with_pixels(&mut recorder, 1. / 60., value as u8);
+        }
+
+        // The dropped frames are put back into the remainder, so the timing is kept.
+        assert!(recorder.dropped_video_frames > 0);
+        assert_eq!(
+            recorder.recorded_video_frames + recorder.current_frame_length(),
+            FRAMES
+        );
+        let recorded = recorder.recorded_video_frames;
+
+        let releaser = thread::spawn(move || while release.send(()).is_ok() {});
+        recorder.finish().unwrap();
+        releaser.join().unwrap();
+
+        // Only the frames which made it into the queue are muxed, and each with its own pixels.
+        let frames = inner.video_frames();
+        assert_eq!(frames.len(), recorded);
+        assert_eq!(frames[0][0], 0);
+        assert!(frames.windows(2).all(|pair| pair[0][0] <= pair[1][0]));
+    }
+
+    #[test]
+    fn motion_blur_with_one_subframe_is_bit_identical() {
+        let record = |motion_blur| {
+            let sink = MemorySink::default();
+            let options = RecorderOptions {
+                motion_blur,
+                ..Default::default()
+            };
+            let mut recorder = recorder_with_options(60, Box::new(sink.clone()), options);
+            for value in [0, 17, 128, 255, 3] {
+                advance_frame_with_pixels(&mut recorder, 1. / 60., value);
+            }
+            recorder.finish().unwrap();
+            sink.video_frames()
+        };
+
+        let plain = record(None);
+        assert_eq!(plain.len(), 5);
+
+        let blurred = record(Some(MotionBlur {
+            subframes: 1,
+            shutter: 0.5,
+        }));
+        assert_eq!(blurred, plain);
+    }
+
+    #[test]
+    fn segments_split_on_frame_boundaries() {
+        let first = MemorySink::default();
+        let sinks = Arc::new(Mutex::new(vec![first.clone()]));
+
+        let muxer: Arc<Mutex<Box<dyn FrameSink>>> = Arc::new(Mutex::new(Box::new(first)));
+        let new_sinks = sinks.clone();
+        let segments = Segments {
+            muxer: muxer.clone(),
+            new_muxer: Box::new(move |number, _| {
+                let mut sinks = new_sinks.lock().unwrap();
+                assert_eq!(number, sinks.len() + 1);
+                let sink = MemorySink::default();
+                sinks.push(sink.clone());
+                Ok(Box::new(sink) as Box<dyn FrameSink>)
+            }),
+            frames_per_segment: 3,
+            frames_in_segment: 0,
+            number: 1,
+            output: String::new(),
+            checksum_filename: None,
+            size: (2, 2),
+            adaptive: None,
+        };
+
+        let mut recorder = Recorder::with_segments(
+            2,
+            2,
+            60,
+            1.,
+            CaptureType::ReadPixels,
+            None,
+            Box::new(SharedSink(muxer)),
+            Some(segments),
+            0.,
+            0.,
+            RecorderOptions::default(),
+        );
+
+        advance_frame(&mut recorder, 1. / 60.);
+        advance_frame(&mut recorder, 1. / 60.);
+        // Two video frames at once, the second one goes into the next segment.
+        advance_frame(&mut recorder, 2. / 60.);
+        recorder.write_audio_frame(vec![0; 8]);
+        advance_frame(&mut recorder, 1. / 60.);
+        advance_frame(&mut recorder, 1. / 60.);
+        // The segment is full, but this audio still goes into it.
+        recorder.write_audio_frame(vec![0; 4]);
+        advance_frame(&mut recorder, 1. / 60.);
+
+        let stats = recorder.finish().unwrap();
+        assert_eq!(stats.video_frames, 7);
+
+        let sinks = sinks.lock().unwrap();
+        let frames = sinks
+            .iter()
+            .map(MemorySink::video_frame_count)
+            .collect::<Vec<_>>();
+        assert_eq!(frames, [3, 3, 1]);
+        let audio = sinks.iter().map(MemorySink::audio_len).collect::<Vec<_>>();
+        assert_eq!(audio, [0, 12, 0]);
+    }
+
+    #[test]
+    fn every_segment_gets_a_checksum() {
+        let filename = std::env::temp_dir()
+            .join(format!(
+                "bxt-rs-every-segment-gets-a-checksum-{}.bin",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned();
+
+        let segment_base = filename.clone();
+        let mut new_muxer: NewSegmentMuxer = Box::new(move |number, _| {
+            let file = File::create(segment_filename(&segment_base, number))?;
+            Ok(Box::new(FileSink(file)) as Box<dyn FrameSink>)
+        });
+
+        let muxer = Arc::new(Mutex::new(new_muxer(1, (2, 2)).unwrap()));
+        let segments = Segments {
+            muxer: muxer.clone(),
+            new_muxer,
+            frames_per_segment: 2,
+            frames_in_segment: 0,
+            number: 1,
+            output: String::new(),
+            checksum_filename: Some(filename.clone()),
+            size: (2, 2),
+            adaptive: None,
+        };
+
+        let mut recorder = Recorder::with_segments(
+            2,
+            2,
+            60,
+            1.,
+            CaptureType::ReadPixels,
+            None,
+            Box::new(SharedSink(muxer)),
+            Some(segments),
+            0.,
+            0.,
+            RecorderOptions::default(),
+        );
+
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+        recorder.finish().unwrap();
+
+        // Both the segment finished during the recording and the last one have checksums.
+        for number in 1..=2 {
+            let path = PathBuf::from(segment_filename(&filename, number));
+            let mut checksum_path = path.as_os_str().to_owned();
+            checksum_path.push(".sha256");
+
+            let contents = std::fs::read(&path).unwrap();
+            let checksum = std::fs::read_to_string(&checksum_path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            std::fs::remove_file(&checksum_path).unwrap();
+
+            let expected = format!(
+                "{:x}  {}\n",
+                Sha256::digest(&contents),
+                path.file_name().unwrap().to_string_lossy()
+            );
+            assert_eq!(checksum, expected);
+        }
+    }
+
+    #[test]
+    fn adaptive_resolution_steps_down_and_back_up() {
+        let sizes = Arc::new(Mutex::new(Vec::new()));
+        let new_sizes = sizes.clone();
+        let fill = Arc::new(Mutex::new(0.));
+        let queue_fill = fill.clone();
+
+        let adaptive =
+            AdaptiveResolution::new(Box::new(move || *queue_fill.lock().unwrap()), (640, 480));
+        let output_size = adaptive.output_size.clone();
+        let muxer: Arc<Mutex<Box<dyn FrameSink>>> =
+            Arc::new(Mutex::new(Box::new(MemorySink::default())));
+        let mut segments = Segments {
+            muxer,
+            new_muxer: Box::new(move |_, size| {
+                new_sizes.lock().unwrap().push(size);
+                Ok(Box::new(MemorySink::default()) as Box<dyn FrameSink>)
+            }),
+            frames_per_segment: 4,
+            frames_in_segment: 0,
+            number: 1,
+            output: String::new(),
+            checksum_filename: None,
+            size: (640, 480),
+            adaptive: Some(adaptive),
+        };
+
+        let mut record_segment = |queue_fills: [f64; 4]| {
+            for queue_fill in queue_fills {
+                *fill.lock().unwrap() = queue_fill;
+                segments.make_room().unwrap();
+            }
+        };
+
+        // The queue filling up for a moment doesn't lower the resolution.
+        record_segment([0.5, 1., 0.5, 0.5]);
+        // A near-full queue for most of the segment does, twice in a row.
+        record_segment([0.9, 1., 0.8, 0.5]);
+        record_segment([1., 1., 1., 1.]);
+        // There's no lower resolution to step to.
+        record_segment([1., 1., 1., 1.]);
+        // A partly drained queue keeps the resolution.
+        record_segment([0.1, 0.1, 0.5, 0.]);
+        // A fully drained one steps back up.
+        record_segment([0.1, 0.1, 0.1, 0.]);
+        record_segment([0., 0., 0., 0.]);
+        // Starts the last segment.
+        record_segment([0., 0., 0., 0.]);
+
+        assert_eq!(
+            *sizes.lock().unwrap(),
+            [
+                (640, 480),
+                (480, 360),
+                (320, 240),
+                (320, 240),
+                (320, 240),
+                (480, 360),
+                (640, 480),
+            ]
+        );
+        assert_eq!(*output_size.lock().unwrap(), (640, 480));
+    }
+
+    #[test]
+    fn adaptive_resolution_needs_segments() {
+        let options = RecorderOptions {
+            adaptive_resolution: true,
+            ..Default::default()
+        };
+        let result = unsafe {
+            Recorder::init(
+                64,
+                64,
+                30,
+                1.,
+                CaptureType::ReadPixels,
+                "/nonexistent/output.mp4",
+                None,
+                0.,
+                0.,
+                options,
+                None,
+            )
+        };
+        let err = result.err().unwrap();
+        assert!(err.to_string().contains("adaptive resolution"));
+    }
+
+    #[test]
+    fn rejected_setup_writes_no_hud_subtitles() {
+        let filename = std::env::temp_dir()
+            .join(format!(
+                "bxt-rs-rejected-setup-writes-no-hud-subtitles-{}.mp4",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned();
+
+        let options = RecorderOptions {
+            hud_lines: Some(vec!["frame 1".to_owned()]),
+            segment_seconds: Some(10.),
+            ..Default::default()
+        };
+        let result = unsafe {
+            Recorder::init(
+                64,
+                64,
+                30,
+                1.,
+                CaptureType::ReadPixels,
+                &filename,
+                None,
+                0.,
+                0.,
+                options,
+                None,
+            )
+        };
+        let err = result.err().unwrap();
+        assert!(err.to_string().contains("the HUD"));
+        assert!(!Path::new(&format!("{filename}.hud.srt")).exists());
+    }
+
+    /// Sink whose FFmpeg exits with an error once the input is closed.
+    struct FailingCloseSink(MemorySink);
+
+    impl FrameSink for FailingCloseSink {
+        fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.0.write_video_frame(data)
+        }
+
+        fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+            self.0.write_audio_frame(data)
+        }
+
+        fn close(self: Box<Self>) -> Result<String, io::Error> {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "ffmpeg exited with exit status: 1",
+            ))
+        }
+    }
+
+    #[test]
+    fn finish_returns_ffmpeg_exit_failure() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_options(
+            60,
+            Box::new(FailingCloseSink(sink.clone())),
+            RecorderOptions::default(),
+        );
+
+        for _ in 0..3 {
+            advance_frame(&mut recorder, 1. / 60.);
+        }
+
+        let err = recorder.finish().unwrap_err();
+        assert!(format!("{err:#}").contains("exit status: 1"));
+        assert_eq!(sink.video_frame_count(), 3);
+    }
+
+    #[test]
+    fn finish_returns_errors_after_recording() {
+        let sink = MemorySink::default();
+        let mut recorder = recorder_with_sink(60, &sink);
+        recorder.edl = Some(Edl {
+            path: PathBuf::from("/nonexistent/output.edl"),
+            output: PathBuf::from("output.mp4"),
+            rate: TimecodeRate::new(60, 1).unwrap(),
+        });
+
+        advance_frame(&mut recorder, 1. / 60.);
+
+        let err = recorder.finish().unwrap_err();
+        assert!(format!("{err:#}").contains("error writing the EDL"));
+        assert_eq!(sink.video_frame_count(), 1);
+    }
+}
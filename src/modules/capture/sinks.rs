@@ -0,0 +1,967 @@
+//! Frame sinks which the recorder puts between the capture and the muxer.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, TryLockError};
+
+use super::muxer::{FrameSink, PixelFormat};
+use super::options::ImageFormat;
+use super::passes::write_checksum;
+use super::recorder::FrameCallback;
+
+/// Sink that writes everything into two sinks.
+pub struct TeeSink(pub Box<dyn FrameSink>, pub Box<dyn FrameSink>);
+
+impl FrameSink for TeeSink {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.0.write_video_frame(data)?;
+        self.1.write_video_frame(data)
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.0.write_audio_frame(data)?;
+        self.1.write_audio_frame(data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        // Close both sinks even if the first one fails.
+        let first = self.0.close();
+        let second = self.1.close();
+        let mut output = first?;
+        output.push_str(&second?);
+        Ok(output)
+    }
+}
+
+/// Sink that writes the R, G and B planes of the frames into separate raw files.
+///
+/// Expects [`PixelFormat::Rgb24Flipped`] video frames. Audio is discarded.
+pub struct RawPlanesSink {
+    pub width: usize,
+    pub files: [BufWriter<File>; 3],
+    /// Buffer for the plane being written.
+    pub plane: Vec<u8>,
+}
+
+impl RawPlanesSink {
+    /// Creates `<filename>.r`, `<filename>.g` and `<filename>.b`.
+    pub fn create(filename: &str, width: usize, height: usize) -> Result<Self, io::Error> {
+        let create =
+            |extension| File::create(format!("{filename}.{extension}")).map(BufWriter::new);
+
+        Ok(Self {
+            width,
+            files: [create("r")?, create("g")?, create("b")?],
+            plane: Vec::with_capacity(width * height),
+        })
+    }
+}
+
+impl FrameSink for RawPlanesSink {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        for (channel, file) in self.files.iter_mut().enumerate() {
+            self.plane.clear();
+
+            // The rows are stored bottom to top.
+            for row in data.chunks_exact(self.width * 3).rev() {
+                self.plane
+                    .extend(row.iter().skip(channel).step_by(3).copied());
+            }
+
+            file.write_all(&self.plane)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_audio_frame(&mut self, _data: &[u8]) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        for mut file in self.files {
+            file.flush()?;
+        }
+
+        Ok(String::new())
+    }
+}
+
+/// Sink that writes every video frame into a numbered image file and the audio into a WAV file.
+///
+/// Expects [`PixelFormat::Rgb24Flipped`] video frames.
+pub struct ImageSequenceSink {
+    pub dir: PathBuf,
+    pub format: ImageFormat,
+    pub width: usize,
+    pub height: usize,
+    /// Number of the next frame, starting at `1`.
+    pub next_frame: u64,
+    pub audio: BufWriter<File>,
+    /// Number of audio bytes written so far.
+    pub audio_len: u32,
+    /// Buffer for the encoded image.
+    pub image: Vec<u8>,
+}
+
+impl ImageSequenceSink {
+    /// Creates `dir` if needed and starts the audio file in it.
+    pub fn create(
+        dir: &Path,
+        format: ImageFormat,
+        width: usize,
+        height: usize,
+    ) -> Result<Self, io::Error> {
+        std::fs::create_dir_all(dir)?;
+
+        let mut audio = BufWriter::new(File::create(dir.join("audio.wav"))?);
+        audio.write_all(&wav_header(0))?;
+
+        Ok(Self {
+            dir: dir.to_owned(),
+            format,
+            width,
+            height,
+            next_frame: 1,
+            audio,
+            audio_len: 0,
+            image: Vec::new(),
+        })
+    }
+}
+
+impl FrameSink for ImageSequenceSink {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.image.clear();
+        let extension = match self.format {
+            ImageFormat::Png => {
+                encode_png(&mut self.image, data, self.width, self.height)?;
+                "png"
+            }
+            ImageFormat::Tga => {
+                encode_tga(&mut self.image, data, self.width, self.height);
+                "tga"
+            }
+        };
+
+        let path = self
+            .dir
+            // Eight digits are enough for over a day of recording at 1000 FPS.
+            .join(format!("frame_{:08}.{extension}", self.next_frame));
+        std::fs::write(path, &self.image)?;
+
+        self.next_frame += 1;
+        Ok(())
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.audio.write_all(data)?;
+        self.audio_len = self.audio_len.saturating_add(data.len() as u32);
+        Ok(())
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        let audio_len = self.audio_len;
+        let mut audio = self.audio.into_inner().map_err(|err| err.into_error())?;
+        audio.seek(SeekFrom::Start(0))?;
+        audio.write_all(&wav_header(audio_len))?;
+
+        Ok(String::new())
+    }
+}
+
+/// Returns the header of a WAV file with `data_len` bytes of the recorded audio.
+fn wav_header(data_len: u32) -> [u8; 44] {
+    const SAMPLE_RATE: u32 = 22050;
+    const CHANNELS: u16 = 2;
+    const BITS_PER_SAMPLE: u16 = 16;
+    const BLOCK_ALIGN: u16 = CHANNELS * BITS_PER_SAMPLE / 8;
+
+    let mut header = [0; 44];
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&(36u32.saturating_add(data_len)).to_le_bytes());
+    header[8..16].copy_from_slice(b"WAVEfmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes());
+    header[20..22].copy_from_slice(&1u16.to_le_bytes()); // PCM
+    header[22..24].copy_from_slice(&CHANNELS.to_le_bytes());
+    header[24..28].copy_from_slice(&SAMPLE_RATE.to_le_bytes());
+    header[28..32].copy_from_slice(&(SAMPLE_RATE * BLOCK_ALIGN as u32).to_le_bytes());
+    header[32..34].copy_from_slice(&BLOCK_ALIGN.to_le_bytes());
+    header[34..36].copy_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data_len.to_le_bytes());
+    header
+}
+
+/// Encodes a vertically flipped RGB24 frame as an uncompressed TGA image into `out`.
+fn encode_tga(out: &mut Vec<u8>, data: &[u8], width: usize, height: usize) {
+    let mut header = [0; 18];
+    header[2] = 2; // Uncompressed true-color.
+    header[12..14].copy_from_slice(&(width as u16).to_le_bytes());
+    header[14..16].copy_from_slice(&(height as u16).to_le_bytes());
+    header[16] = 24; // Bits per pixel.
+    out.extend_from_slice(&header);
+
+    // TGA stores the rows bottom to top by default, just like the frame, but in BGR order.
+    for pixel in data.chunks_exact(3) {
+        out.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+    }
+}
+
+/// Encodes a vertically flipped RGB24 frame as a PNG image into `out`.
+fn encode_png(
+    out: &mut Vec<u8>,
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Recording can't wait for a slow encoder.
+    encoder.set_compression(png::Compression::Fast);
+
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+    // PNG stores the rows from top to bottom.
+    for row in data.chunks_exact(width * 3).rev() {
+        stream.write_all(row)?;
+    }
+    stream.finish()?;
+    writer.finish()
+}
+
+/// Returns the resolution rounded up to even numbers.
+pub fn padded_size(width: usize, height: usize) -> (usize, usize) {
+    (width + width % 2, height + height % 2)
+}
+
+/// Sink that pads [`PixelFormat::Rgb24Flipped`] video frames with black on the right and at the
+/// bottom.
+pub struct PadSink {
+    pub inner: Box<dyn FrameSink>,
+    pub size: (usize, usize),
+    pub padded_size: (usize, usize),
+    /// Buffer for the padded frame.
+    pub frame: Vec<u8>,
+}
+
+impl PadSink {
+    pub fn new(
+        inner: Box<dyn FrameSink>,
+        size: (usize, usize),
+        padded_size: (usize, usize),
+    ) -> Self {
+        Self {
+            inner,
+            size,
+            padded_size,
+            frame: Vec::new(),
+        }
+    }
+}
+
+impl FrameSink for PadSink {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let (width, height) = self.size;
+        let (padded_width, padded_height) = self.padded_size;
+
+        self.frame.clear();
+
+        // The rows are flipped, so the bottom padding comes first.
+        self.frame
+            .resize((padded_height - height) * padded_width * 3, 0);
+        for row in data.chunks_exact(width * 3) {
+            self.frame.extend_from_slice(row);
+            self.frame
+                .resize(self.frame.len() + (padded_width - width) * 3, 0);
+        }
+
+        let frame = std::mem::take(&mut self.frame);
+        let result = self.inner.write_video_frame(&frame);
+        self.frame = frame;
+        result
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.inner.write_audio_frame(data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        self.inner.close()
+    }
+}
+
+/// Sink that lets a [`FrameCallback`] modify the video frames before passing them on.
+pub struct CallbackSink {
+    pub inner: Box<dyn FrameSink>,
+    pub callback: FrameCallback,
+    pub width: i32,
+    pub height: i32,
+    /// Buffer for the frame passed to the callback.
+    pub frame: Vec<u8>,
+}
+
+impl FrameSink for CallbackSink {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.frame.clear();
+        self.frame.extend_from_slice(data);
+        (self.callback)(&mut self.frame, self.width, self.height);
+        self.inner.write_video_frame(&self.frame)
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.inner.write_audio_frame(data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        self.inner.close()
+    }
+}
+
+/// Sink that records the video frames which differ a lot from the previous ones.
+pub struct SceneSink {
+    pub inner: Box<dyn FrameSink>,
+    /// Number of bytes at the start of every frame which are compared.
+    pub compared_len: usize,
+    /// Mean difference of the compared bytes, in `[0; 1]`, above which a new scene starts.
+    pub threshold: f32,
+    /// Compared bytes of the previous frame.
+    pub previous: Vec<u8>,
+    /// Index of the next video frame.
+    pub frame_idx: usize,
+    /// Indices of the video frames where a new scene starts.
+    pub scene_changes: Arc<Mutex<Vec<usize>>>,
+}
+
+impl SceneSink {
+    /// Distance between the compared bytes, which keeps the comparison cheap for large frames.
+    const STEP: usize = 4;
+}
+
+impl FrameSink for SceneSink {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let current = data[..self.compared_len.min(data.len())]
+            .iter()
+            .step_by(Self::STEP);
+
+        if !self.previous.is_empty() {
+            let total = current
+                .clone()
+                .zip(&self.previous)
+                .map(|(&a, &b)| a.abs_diff(b) as u64)
+                .sum::<u64>();
+            let difference = total as f64 / (self.previous.len() as f64 * 255.);
+
+            if difference > self.threshold as f64 {
+                self.scene_changes.lock().unwrap().push(self.frame_idx);
+            }
+        }
+
+        self.previous.clear();
+        self.previous.extend(current);
+        self.frame_idx += 1;
+
+        self.inner.write_video_frame(data)
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.inner.write_audio_frame(data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        self.inner.close()
+    }
+}
+
+/// Returns the resolution of a proxy at `scale`, rounded down to even numbers.
+pub fn proxy_size(width: usize, height: usize, scale: f32) -> (usize, usize) {
+    let scale = |size: usize| ((size as f32 * scale) as usize / 2 * 2).max(2);
+    (scale(width), scale(height))
+}
+
+/// Sink that passes on the video frames downscaled and at a lower frame rate.
+pub struct ProxySink {
+    pub inner: Box<dyn FrameSink>,
+    pub pixel_format: PixelFormat,
+    pub size: (usize, usize),
+    pub proxy_size: (usize, usize),
+    /// Proxy frames per source frame, at most `1`.
+    pub ratio: f64,
+    /// Proxy frames owed, separate from the recording's own video remainder.
+    pub remainder: f64,
+    /// Buffer for the downscaled frame.
+    pub frame: Vec<u8>,
+}
+
+impl ProxySink {
+    pub fn new(
+        inner: Box<dyn FrameSink>,
+        pixel_format: PixelFormat,
+        size: (usize, usize),
+        proxy_size: (usize, usize),
+        ratio: f64,
+    ) -> Self {
+        Self {
+            inner,
+            pixel_format,
+            size,
+            proxy_size,
+            ratio: ratio.min(1.),
+            // Start with the very first frame.
+            remainder: 1.,
+            frame: Vec::new(),
+        }
+    }
+
+    /// Downscales a `size` plane from `src` into `proxy_size` plane in `self.frame`.
+    fn downscale_plane(
+        &mut self,
+        src: &[u8],
+        (width, height): (usize, usize),
+        (proxy_width, proxy_height): (usize, usize),
+        bytes_per_pixel: usize,
+    ) {
+        for y in 0..proxy_height {
+            let src_y = y * height / proxy_height;
+            for x in 0..proxy_width {
+                let src_x = x * width / proxy_width;
+                let offset = (src_y * width + src_x) * bytes_per_pixel;
+                self.frame
+                    .extend_from_slice(&src[offset..offset + bytes_per_pixel]);
+            }
+        }
+    }
+}
+
+impl FrameSink for ProxySink {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let write = self.remainder >= 1.;
+        if write {
+            self.remainder -= 1.;
+        }
+        self.remainder += self.ratio;
+
+        if !write {
+            return Ok(());
+        }
+
+        let (width, height) = self.size;
+        let (proxy_width, proxy_height) = self.proxy_size;
+
+        self.frame.clear();
+        match self.pixel_format {
+            PixelFormat::I420 => {
+                let (luma, chroma) = data.split_at(width * height);
+                let (u, v) = chroma.split_at(width * height / 4);
+                self.downscale_plane(luma, self.size, self.proxy_size, 1);
+                for plane in [u, v] {
+                    self.downscale_plane(
+                        plane,
+                        (width / 2, height / 2),
+                        (proxy_width / 2, proxy_height / 2),
+                        1,
+                    );
+                }
+            }
+            PixelFormat::Rgb24Flipped => {
+                self.downscale_plane(data, self.size, self.proxy_size, 3);
+            }
+        }
+
+        let frame = std::mem::take(&mut self.frame);
+        let result = self.inner.write_video_frame(&frame);
+        self.frame = frame;
+        result
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.inner.write_audio_frame(data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        self.inner.close()
+    }
+}
+
+/// Sink shared between the recording thread and the audio thread or the segments.
+pub struct SharedSink(pub Arc<Mutex<Box<dyn FrameSink>>>);
+
+impl FrameSink for SharedSink {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.0.lock().unwrap().write_video_frame(data)
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.0.lock().unwrap().write_audio_frame(data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        match Arc::try_unwrap(self.0) {
+            Ok(sink) => sink.into_inner().unwrap().close(),
+            Err(_) => {
+                warn!("could not close the sink as it is still used elsewhere");
+                Ok(String::new())
+            }
+        }
+    }
+}
+
+/// Sink shared between the recording thread and the audio thread.
+///
+/// Writing a video frame blocks for as long as FFmpeg is behind. Audio which comes in the meantime
+/// doesn't wait for the sink and is kept in `pending_audio` instead, to be written right after the
+/// video frame.
+#[derive(Clone)]
+pub struct AudioSharedSink {
+    pub sink: Arc<Mutex<Box<dyn FrameSink>>>,
+    pub pending_audio: Arc<Mutex<Vec<u8>>>,
+}
+
+impl AudioSharedSink {
+    /// Writes `data` after the pending audio, or adds it to the pending audio if a video frame is
+    /// being written.
+    pub fn write_audio_without_waiting(&self, data: &[u8]) -> Result<(), io::Error> {
+        match self.sink.try_lock() {
+            Ok(mut sink) => {
+                let mut pending_audio = self.pending_audio.lock().unwrap();
+                pending_audio.extend_from_slice(data);
+                write_pending_audio(&mut **sink, &mut pending_audio)
+            }
+            Err(TryLockError::WouldBlock) => {
+                self.pending_audio.lock().unwrap().extend_from_slice(data);
+                Ok(())
+            }
+            Err(TryLockError::Poisoned(err)) => panic!("{err}"),
+        }
+    }
+
+    /// Writes the pending audio, waiting for the video frame being written, if any.
+    pub fn flush_audio(&self) -> Result<(), io::Error> {
+        let mut sink = self.sink.lock().unwrap();
+        write_pending_audio(&mut **sink, &mut self.pending_audio.lock().unwrap())
+    }
+}
+
+impl FrameSink for AudioSharedSink {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let mut sink = self.sink.lock().unwrap();
+        sink.write_video_frame(data)?;
+        write_pending_audio(&mut **sink, &mut self.pending_audio.lock().unwrap())
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let mut sink = self.sink.lock().unwrap();
+        write_pending_audio(&mut **sink, &mut self.pending_audio.lock().unwrap())?;
+        sink.write_audio_frame(data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        self.flush_audio()?;
+        Box::new(SharedSink(self.sink)).close()
+    }
+}
+
+/// Writes `pending_audio` into `sink` and clears it.
+fn write_pending_audio(
+    sink: &mut dyn FrameSink,
+    pending_audio: &mut Vec<u8>,
+) -> Result<(), io::Error> {
+    if pending_audio.is_empty() {
+        return Ok(());
+    }
+
+    let result = sink.write_audio_frame(pending_audio);
+    pending_audio.clear();
+    result
+}
+
+/// Starts the muxer for the segment with the given number and output resolution.
+pub type NewSegmentMuxer =
+    Box<dyn FnMut(usize, (usize, usize)) -> Result<Box<dyn FrameSink>, io::Error> + Send>;
+
+/// Output split into segments of a fixed number of video frames, each in its own file.
+pub struct Segments {
+    /// Muxer of the current segment, which is also written into through a [`SharedSink`].
+    pub muxer: Arc<Mutex<Box<dyn FrameSink>>>,
+    pub new_muxer: NewSegmentMuxer,
+    pub frames_per_segment: usize,
+    pub frames_in_segment: usize,
+    /// Number of the current segment, starting from 1.
+    pub number: usize,
+    /// FFmpeg output of the finished segments.
+    pub output: String,
+    /// Output file name which the segment file names are based on, if every finished segment
+    /// should get a checksum.
+    pub checksum_filename: Option<String>,
+    /// Resolution of the video frames written into the segments.
+    pub size: (usize, usize),
+    /// Lowering of the segment resolution under load, if enabled.
+    pub adaptive: Option<AdaptiveResolution>,
+}
+
+impl Segments {
+    /// Makes room for one more video frame, swapping in the muxer for the next segment and closing
+    /// the current one if it's full.
+    pub fn make_room(&mut self) -> Result<(), io::Error> {
+        if self.frames_in_segment == self.frames_per_segment {
+            let size = match &mut self.adaptive {
+                Some(adaptive) => adaptive.next_segment_size(self.frames_in_segment),
+                None => self.size,
+            };
+            let next = (self.new_muxer)(self.number + 1, size)?;
+            let previous = std::mem::replace(&mut *self.muxer.lock().unwrap(), next);
+            self.output += &previous.close()?;
+
+            if let Some(path) = self.checksum_path() {
+                write_checksum(&path).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            }
+
+            self.number += 1;
+            self.frames_in_segment = 0;
+        }
+
+        if let Some(adaptive) = &mut self.adaptive {
+            adaptive.sample_queue();
+        }
+
+        self.frames_in_segment += 1;
+        Ok(())
+    }
+
+    /// Returns the file to write the checksum of the current segment for, if there is one.
+    pub fn checksum_path(&self) -> Option<PathBuf> {
+        self.checksum_filename
+            .as_ref()
+            .map(|filename| PathBuf::from(segment_filename(filename, self.number)))
+    }
+}
+
+/// Scales of the game resolution that the adaptive resolution steps through.
+const ADAPTIVE_SCALES: [f32; 3] = [1., 0.75, 0.5];
+
+/// Queue fill above which the recording thread counts as falling behind.
+const QUEUE_NEAR_FULL: f64 = 0.75;
+
+/// Queue fill below which the recording thread counts as keeping up.
+const QUEUE_NEAR_EMPTY: f64 = 0.25;
+
+/// Returns how full the queue for the recording thread is, from 0 to 1.
+type QueueFill = Box<dyn Fn() -> f64 + Send>;
+
+/// Picks the resolution of every segment from how full the queue was during the previous one.
+pub struct AdaptiveResolution {
+    pub queue_fill: QueueFill,
+    /// Full resolution of the segments.
+    pub size: (usize, usize),
+    /// Index of the current scale in [`ADAPTIVE_SCALES`].
+    pub step: usize,
+    /// Video frames of the current segment which found the queue near full.
+    pub pressured_frames: usize,
+    /// Video frames of the current segment which found the queue near empty.
+    pub drained_frames: usize,
+    /// Resolution of the current segment, shared with the main thread.
+    pub output_size: Arc<Mutex<(usize, usize)>>,
+}
+
+impl AdaptiveResolution {
+    pub fn new(queue_fill: QueueFill, size: (usize, usize)) -> Self {
+        Self {
+            queue_fill,
+            size,
+            step: 0,
+            pressured_frames: 0,
+            drained_frames: 0,
+            output_size: Arc::new(Mutex::new(size)),
+        }
+    }
+
+    /// Records how full the queue is for a video frame of the current segment.
+    fn sample_queue(&mut self) {
+        let fill = (self.queue_fill)();
+        if fill >= QUEUE_NEAR_FULL {
+            self.pressured_frames += 1;
+        } else if fill <= QUEUE_NEAR_EMPTY {
+            self.drained_frames += 1;
+        }
+    }
+
+    /// Returns the resolution of the next segment, given the number of video frames in the
+    /// finished one.
+    fn next_segment_size(&mut self, frames: usize) -> (usize, usize) {
+        if self.pressured_frames * 2 > frames {
+            self.step = (self.step + 1).min(ADAPTIVE_SCALES.len() - 1);
+        } else if self.drained_frames == frames {
+            self.step = self.step.saturating_sub(1);
+        }
+        self.pressured_frames = 0;
+        self.drained_frames = 0;
+
+        // The full resolution is even, so the first scale keeps it as is.
+        let size = proxy_size(self.size.0, self.size.1, ADAPTIVE_SCALES[self.step]);
+        *self.output_size.lock().unwrap() = size;
+        size
+    }
+}
+
+/// Sink which starts a new segment before the video frames which don't fit into the current one.
+pub struct SegmentSink<'a> {
+    pub inner: &'a mut dyn FrameSink,
+    pub segments: &'a mut Segments,
+}
+
+impl FrameSink for SegmentSink<'_> {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.segments.make_room()?;
+        self.inner.write_video_frame(data)
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.inner.write_audio_frame(data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        // The underlying sink is closed by its owner.
+        Ok(String::new())
+    }
+}
+
+/// Returns the file name of the segment with the given number, such as `name_0001.mp4`.
+pub fn segment_filename(filename: &str, number: usize) -> String {
+    let path = Path::new(filename);
+    let mut name = path.with_extension("").into_os_string();
+    name.push(format!("_{number:04}"));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    name.to_string_lossy().into_owned()
+}
+
+/// Mouse cursor arrow sprite: `X` is the outline, `.` is the fill, spaces are transparent.
+const CURSOR_SPRITE: [&str; 16] = [
+    "X",
+    "XX",
+    "X.X",
+    "X..X",
+    "X...X",
+    "X....X",
+    "X.....X",
+    "X......X",
+    "X.......X",
+    "X........X",
+    "X.....XXXXX",
+    "X..X..X",
+    "X.X X..X",
+    "XX  X..X",
+    "X    X..X",
+    "      XX",
+];
+
+/// Radius of the analog stick indicators in pixels.
+const STICK_RADIUS: isize = 8;
+
+/// State for drawing the mouse cursor and the analog sticks into the recorded frames.
+pub struct Overlay {
+    pub width: usize,
+    pub height: usize,
+    pub pixel_format: PixelFormat,
+    /// Position of the cursor's tip from the top-left corner, if it's visible.
+    pub cursor: Option<(i32, i32)>,
+    /// Left and right analog stick axes, if they were set.
+    pub analog: Option<[f32; 4]>,
+    /// Buffer for the frame with the overlay drawn on top.
+    pub frame: Vec<u8>,
+}
+
+impl Overlay {
+    pub fn is_visible(&self) -> bool {
+        self.cursor.is_some() || self.analog.is_some()
+    }
+
+    /// Draws the overlay on top of `frame` into the internal buffer and returns it.
+    fn draw(&mut self, frame: &[u8]) -> &[u8] {
+        self.frame.clear();
+        self.frame.extend_from_slice(frame);
+
+        if let Some([lx, ly, rx, ry]) = self.analog {
+            let y = self.height as isize - 1 - 2 - STICK_RADIUS;
+            self.draw_stick(2 + STICK_RADIUS, y, lx, ly);
+            self.draw_stick(2 + STICK_RADIUS * 3 + 3, y, rx, ry);
+        }
+
+        if let Some((cursor_x, cursor_y)) = self.cursor {
+            for (dy, row) in CURSOR_SPRITE.iter().enumerate() {
+                for (dx, pixel) in row.bytes().enumerate() {
+                    let (luma, rgb) = match pixel {
+                        b'X' => (16, 0),
+                        b'.' => (235, 255),
+                        _ => continue,
+                    };
+
+                    let x = cursor_x as isize + dx as isize;
+                    let y = cursor_y as isize + dy as isize;
+                    self.put_pixel(x, y, luma, rgb);
+                }
+            }
+        }
+
+        &self.frame
+    }
+
+    /// Draws a stick indicator centered at `center_x`, `center_y` with a dot at the stick position.
+    fn draw_stick(&mut self, center_x: isize, center_y: isize, x: f32, y: f32) {
+        // Outline of the stick range.
+        for d in -STICK_RADIUS..=STICK_RADIUS {
+            for (dx, dy) in [
+                (d, -STICK_RADIUS),
+                (d, STICK_RADIUS),
+                (-STICK_RADIUS, d),
+                (STICK_RADIUS, d),
+            ] {
+                self.put_pixel(center_x + dx, center_y + dy, 16, 0);
+            }
+        }
+
+        // Positive y points up, so it goes against the frame rows.
+        let dot_x = center_x + (x * (STICK_RADIUS - 2) as f32).round() as isize;
+        let dot_y = center_y - (y * (STICK_RADIUS - 2) as f32).round() as isize;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                self.put_pixel(dot_x + dx, dot_y + dy, 235, 255);
+            }
+        }
+    }
+
+    /// Sets the pixel at `x`, `y` from the top-left corner, if it's inside the frame.
+    fn put_pixel(&mut self, x: isize, y: isize, luma: u8, rgb: u8) {
+        if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+
+        match self.pixel_format {
+            // Only touch the luma plane, which is at the start of the frame.
+            PixelFormat::I420 => self.frame[y * self.width + x] = luma,
+            PixelFormat::Rgb24Flipped => {
+                let offset = ((self.height - 1 - y) * self.width + x) * 3;
+                self.frame[offset..offset + 3].fill(rgb);
+            }
+        }
+    }
+}
+
+/// Sink that draws the overlay into video frames before passing them on.
+pub struct OverlaySink<'a> {
+    pub inner: &'a mut dyn FrameSink,
+    pub overlay: &'a mut Overlay,
+}
+
+impl FrameSink for OverlaySink<'_> {
+    fn write_video_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let frame = self.overlay.draw(data);
+        self.inner.write_video_frame(frame)
+    }
+
+    fn write_audio_frame(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.inner.write_audio_frame(data)
+    }
+
+    fn close(self: Box<Self>) -> Result<String, io::Error> {
+        // The underlying sink is closed by its owner.
+        Ok(String::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_planes_are_bit_exact() {
+        let filename = std::env::temp_dir()
+            .join(format!(
+                "bxt-rs-raw-planes-are-bit-exact-{}",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned();
+
+        let mut sink = Box::new(RawPlanesSink::create(&filename, 2, 2).unwrap());
+
+        // Bottom row first, as from glReadPixels.
+        #[rustfmt::skip]
+        let frame = [
+            0, 1, 2,   3, 4, 5,
+            6, 7, 8,   9, 10, 11,
+        ];
+        sink.write_video_frame(&frame).unwrap();
+        sink.write_audio_frame(&[1, 2, 3, 4]).unwrap();
+        sink.write_video_frame(&[255; 12]).unwrap();
+        sink.close().unwrap();
+
+        let mut planes = Vec::new();
+        for extension in ["r", "g", "b"] {
+            let path = format!("{filename}.{extension}");
+            planes.push(std::fs::read(&path).unwrap());
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        assert_eq!(planes[0], [6, 9, 0, 3, 255, 255, 255, 255]);
+        assert_eq!(planes[1], [7, 10, 1, 4, 255, 255, 255, 255]);
+        assert_eq!(planes[2], [8, 11, 2, 5, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn image_sequence_numbers_frames_and_writes_audio() {
+        let dir = std::env::temp_dir().join(format!(
+            "bxt-rs-image-sequence-numbers-frames-and-writes-audio-{}",
+            std::process::id()
+        ));
+
+        // Bottom row first, as from glReadPixels.
+        #[rustfmt::skip]
+        let frame = [
+            0, 1, 2,   3, 4, 5,
+            6, 7, 8,   9, 10, 11,
+        ];
+
+        for format in [ImageFormat::Png, ImageFormat::Tga] {
+            let mut sink = Box::new(ImageSequenceSink::create(&dir, format, 2, 2).unwrap());
+            sink.write_video_frame(&frame).unwrap();
+            sink.write_audio_frame(&[1, 2, 3, 4]).unwrap();
+            sink.write_video_frame(&[255; 12]).unwrap();
+            sink.close().unwrap();
+        }
+
+        let png = File::open(dir.join("frame_00000001.png")).unwrap();
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (2, 2));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        // The rows go from top to bottom.
+        assert_eq!(pixels, [6, 7, 8, 9, 10, 11, 0, 1, 2, 3, 4, 5]);
+        assert!(dir.join("frame_00000002.png").exists());
+
+        let tga = std::fs::read(dir.join("frame_00000001.tga")).unwrap();
+        assert_eq!(tga.len(), 18 + 12);
+        assert_eq!(tga[18..], [2, 1, 0, 5, 4, 3, 8, 7, 6, 11, 10, 9]);
+        assert!(dir.join("frame_00000002.tga").exists());
+
+        let wav = std::fs::read(dir.join("audio.wav")).unwrap();
+        assert_eq!(wav[..4], *b"RIFF");
+        assert_eq!(wav[40..44], 4u32.to_le_bytes());
+        assert_eq!(wav[44..], [1, 2, 3, 4]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn segment_filenames_are_numbered() {
+        assert_eq!(segment_filename("name.mp4", 1), "name_0001.mp4");
+        assert_eq!(segment_filename("my.video.mkv", 12), "my.video_0012.mkv");
+        assert_eq!(segment_filename("name", 2), "name_0002");
+    }
+}
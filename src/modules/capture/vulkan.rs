@@ -1,6 +1,7 @@
 //! Color conversion and pixel exporting using Vulkan.
 
 use std::ffi::CStr;
+use std::fmt;
 use std::io::Cursor;
 use std::{slice, str};
 
@@ -8,15 +9,17 @@ use ash::util::read_spv;
 use ash::vk;
 use color_eyre::eyre::{self, ensure, eyre};
 
-use super::muxer::Muxer;
+use super::muxer::FrameSink;
 use super::opengl::Uuids;
 use super::ExternalObject;
 
 pub struct Vulkan {
+    device_name: String,
     width: u32,
     height: u32,
     is_sampling: bool,
     queue_family_index: u32,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
     device: ash::Device,
     command_pool: vk::CommandPool,
     command_buffer_acquire: vk::CommandBuffer,
@@ -62,6 +65,43 @@ pub struct Vulkan {
     pipeline_accumulate: vk::Pipeline,
 }
 
+/// Vulkan physical device to capture on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Index in the list of physical devices.
+    Index(usize),
+    /// Part of the device name, case-insensitive.
+    Name(String),
+}
+
+impl DeviceSelector {
+    /// Parses a selector: a number is a device index, anything else is a part of the device name.
+    pub fn parse(selector: &str) -> Self {
+        let selector = selector.trim();
+        match selector.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::Name(selector.to_owned()),
+        }
+    }
+
+    /// Returns whether the device at `index` named `name` is selected.
+    fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            Self::Index(selected) => *selected == index,
+            Self::Name(part) => name.to_lowercase().contains(&part.to_lowercase()),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "#{index}"),
+            Self::Name(part) => write!(f, "\"{part}\""),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ExternalHandles {
     pub external_image_frame_memory: ExternalObject,
@@ -133,6 +173,11 @@ impl Drop for Vulkan {
 }
 
 impl Vulkan {
+    /// Returns the name of the physical device used for capturing.
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
     #[cfg(unix)]
     pub fn external_image_frame_memory(&self) -> eyre::Result<ExternalObject> {
         let create_info = vk::MemoryGetFdInfoKHR::builder()
@@ -318,10 +363,126 @@ impl Vulkan {
         Ok(())
     }
 
+    /// Uploads a frame in place of acquiring it from OpenGL.
+    ///
+    /// `pixels` are RGB24 rows from the bottom one up, like from `glReadPixels`. This is meant for
+    /// generated frames, so it waits for the upload to finish rather than keeping a staging buffer
+    /// around.
+    #[instrument(skip_all)]
+    pub unsafe fn upload_image(&self, pixels: &[u8]) -> eyre::Result<()> {
+        assert_eq!(pixels.len(), self.width as usize * self.height as usize * 3);
+
+        let create_info = vk::BufferCreateInfo::builder()
+            .size(self.width as u64 * self.height as u64 * 4)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = self.device.create_buffer(&create_info, None)?;
+
+        let memory_requirements = self.device.get_buffer_memory_requirements(buffer);
+        let memory = find_memorytype_index(
+            &memory_requirements,
+            &self.memory_properties,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+        .ok_or_else(|| eyre!("couldn't find upload buffer memory type"))
+        .and_then(|memory_type_index| {
+            let create_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(memory_requirements.size)
+                .memory_type_index(memory_type_index);
+            Ok(self.device.allocate_memory(&create_info, None)?)
+        });
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(err) => {
+                self.device.destroy_buffer(buffer, None);
+                return Err(err);
+            }
+        };
+
+        let result = self.upload_image_from(buffer, memory, pixels);
+
+        self.device.free_memory(memory, None);
+        self.device.destroy_buffer(buffer, None);
+        result
+    }
+
+    unsafe fn upload_image_from(
+        &self,
+        buffer: vk::Buffer,
+        memory: vk::DeviceMemory,
+        pixels: &[u8],
+    ) -> eyre::Result<()> {
+        self.device.bind_buffer_memory(buffer, memory, 0)?;
+
+        let data =
+            self.device
+                .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())?;
+        {
+            let data: &mut [u8] = slice::from_raw_parts_mut(
+                data.cast(),
+                self.width as usize * self.height as usize * 4,
+            );
+            for (rgba, rgb) in data.chunks_exact_mut(4).zip(pixels.chunks_exact(3)) {
+                rgba[..3].copy_from_slice(rgb);
+                rgba[3] = 255;
+            }
+        }
+        self.device.unmap_memory(memory);
+
+        // Wait for the previous iteration of this command buffer to complete.
+        self.device
+            .wait_for_fences(&[self.fence_acquire], true, u64::MAX)?;
+        self.device.reset_fences(&[self.fence_acquire])?;
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device
+            .begin_command_buffer(self.command_buffer_acquire, &begin_info)?;
+
+        // Like in acquire_image(), the barrier for the acquired image must've been inserted by
+        // previous code.
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                layer_count: 1,
+                ..Default::default()
+            })
+            .image_extent(vk::Extent3D {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            });
+        self.device.cmd_copy_buffer_to_image(
+            self.command_buffer_acquire,
+            buffer,
+            self.image_acquired,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[*region],
+        );
+
+        self.device
+            .end_command_buffer(self.command_buffer_acquire)?;
+
+        let command_buffers = [self.command_buffer_acquire];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+        self.device
+            .queue_submit(self.queue, &[*submit_info], self.fence_acquire)?;
+
+        // The buffer is freed right after this.
+        {
+            let _span = info_span!("wait for fence_acquire").entered();
+
+            self.device
+                .wait_for_fences(&[self.fence_acquire], true, u64::MAX)?;
+        }
+
+        Ok(())
+    }
+
     #[instrument(skip(self, muxer))]
     pub unsafe fn convert_colors_and_mux(
         &self,
-        muxer: &mut Muxer,
+        muxer: &mut dyn FrameSink,
         frames: usize,
     ) -> eyre::Result<()> {
         let begin_info = vk::CommandBufferBeginInfo::builder()
@@ -741,8 +902,48 @@ impl Vulkan {
     }
 }
 
+/// Returns the device extensions needed for sharing the frames with OpenGL and for the color
+/// conversion.
+fn required_extensions() -> [&'static CStr; 3] {
+    [
+        #[cfg(unix)]
+        ash::extensions::khr::ExternalMemoryFd::name(),
+        #[cfg(windows)]
+        vk::KhrExternalMemoryWin32Fn::name(),
+        #[cfg(unix)]
+        ash::extensions::khr::ExternalSemaphoreFd::name(),
+        #[cfg(windows)]
+        vk::KhrExternalSemaphoreWin32Fn::name(),
+        vk::Khr8bitStorageFn::name(),
+    ]
+}
+
+/// Returns the required extensions which are not in `available`.
+fn missing_extensions(available: &[vk::ExtensionProperties]) -> Vec<&'static CStr> {
+    required_extensions()
+        .into_iter()
+        .filter(|&required| {
+            !available.iter().any(|extension| {
+                let name = unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) };
+                name == required
+            })
+        })
+        .collect()
+}
+
+/// Initializes Vulkan on the physical device used by the OpenGL context.
+///
+/// If `device_selector` is set, the device it matches is used instead, which must support the
+/// extensions needed for capturing and be used by the OpenGL context. If no device matches, the
+/// device is chosen automatically.
 #[instrument(name = "vulkan::init", skip(uuids))]
-pub fn init(width: u32, height: u32, uuids: &Uuids, is_sampling: bool) -> eyre::Result<Vulkan> {
+pub fn init(
+    width: u32,
+    height: u32,
+    uuids: &Uuids,
+    is_sampling: bool,
+    device_selector: Option<&DeviceSelector>,
+) -> eyre::Result<Vulkan> {
     // TODO: handle weird resolutions.
     ensure!(
         width % 2 == 0 && height % 2 == 0,
@@ -755,7 +956,8 @@ pub fn init(width: u32, height: u32, uuids: &Uuids, is_sampling: bool) -> eyre::
 
     // Physical device.
     let physical_devices = unsafe { instance.enumerate_physical_devices()? };
-    let mut physical_device_index = None;
+    let mut device_names = Vec::with_capacity(physical_devices.len());
+    let mut is_compatible = Vec::with_capacity(physical_devices.len());
     debug!("physical devices:");
     for (i, &device) in physical_devices.iter().enumerate() {
         let mut id_properties = vk::PhysicalDeviceIDProperties::default();
@@ -766,29 +968,79 @@ pub fn init(width: u32, height: u32, uuids: &Uuids, is_sampling: bool) -> eyre::
         unsafe { instance.get_physical_device_properties2(device, &mut properties2) };
 
         let properties = &properties2.properties;
+        let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
         debug!(
             "\t{}: [{:?}, Vulkan {}.{}] {}",
             i,
             properties.device_type,
             vk::api_version_major(properties.api_version),
             vk::api_version_minor(properties.api_version),
-            unsafe {
-                str::from_utf8_unchecked(CStr::from_ptr(properties.device_name.as_ptr()).to_bytes())
-            }
+            name,
         );
 
-        // Choose the device used for the OpenGL context.
-        if id_properties.driver_uuid == uuids.driver_uuid
-            && uuids.device_uuids.contains(&id_properties.device_uuid)
-            && properties.api_version >= vk::make_api_version(0, 1, 1, 0)
-        {
-            physical_device_index = Some(i);
-        }
+        // The device must be the one used for the OpenGL context.
+        is_compatible.push(
+            id_properties.driver_uuid == uuids.driver_uuid
+                && uuids.device_uuids.contains(&id_properties.device_uuid)
+                && properties.api_version >= vk::make_api_version(0, 1, 1, 0),
+        );
+        device_names.push(name);
     }
 
-    let physical_device_index =
-        physical_device_index.ok_or_else(|| eyre!("couldn't find a compatible physical device"))?;
-    debug!("choosing physical device {}", physical_device_index);
+    let selected_index = match device_selector {
+        Some(selector) => {
+            let index = device_names
+                .iter()
+                .enumerate()
+                .position(|(i, name)| selector.matches(i, name));
+            if index.is_none() {
+                warn!(
+                    "no Vulkan device matches {}, choosing one automatically",
+                    selector
+                );
+            }
+            index
+        }
+        None => None,
+    };
+
+    let physical_device_index = match selected_index {
+        Some(i) => {
+            let available =
+                unsafe { instance.enumerate_device_extension_properties(physical_devices[i])? };
+            let missing = missing_extensions(&available);
+            ensure!(
+                missing.is_empty(),
+                "Vulkan device {} ({}) doesn't support the extensions needed for capturing: {}",
+                i,
+                device_names[i],
+                missing
+                    .iter()
+                    .map(|extension| extension.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+            ensure!(
+                is_compatible[i],
+                "Vulkan device {} ({}) is not the one the game renders on, so it can't access \
+                the game's frames",
+                i,
+                device_names[i],
+            );
+            i
+        }
+        None => is_compatible
+            .iter()
+            .rposition(|&compatible| compatible)
+            .ok_or_else(|| eyre!("couldn't find a compatible physical device"))?,
+    };
+    let device_name = device_names.swap_remove(physical_device_index);
+    debug!(
+        "choosing physical device {} ({})",
+        physical_device_index, device_name
+    );
     let physical_device = physical_devices[physical_device_index];
 
     // Memory properties.
@@ -813,17 +1065,7 @@ pub fn init(width: u32, height: u32, uuids: &Uuids, is_sampling: bool) -> eyre::
         p_queue_priorities: &1.,
         ..Default::default()
     }];
-    let extension_names = [
-        #[cfg(unix)]
-        ash::extensions::khr::ExternalMemoryFd::name().as_ptr(),
-        #[cfg(windows)]
-        vk::KhrExternalMemoryWin32Fn::name().as_ptr(),
-        #[cfg(unix)]
-        ash::extensions::khr::ExternalSemaphoreFd::name().as_ptr(),
-        #[cfg(windows)]
-        vk::KhrExternalSemaphoreWin32Fn::name().as_ptr(),
-        vk::Khr8bitStorageFn::name().as_ptr(),
-    ];
+    let extension_names = required_extensions().map(CStr::as_ptr);
     let mut physical_device_8_bit_storage_features =
         vk::PhysicalDevice8BitStorageFeatures::builder()
             .storage_buffer8_bit_access(true)
@@ -1420,10 +1662,12 @@ pub fn init(width: u32, height: u32, uuids: &Uuids, is_sampling: bool) -> eyre::
     let fence_accumulate = unsafe { device.create_fence(&create_info, None)? };
 
     Ok(Vulkan {
+        device_name,
         width,
         height,
         is_sampling,
         queue_family_index,
+        memory_properties,
         device,
         command_pool,
         command_buffer_acquire,
@@ -1505,3 +1749,45 @@ fn find_memorytype_index_f<F: Fn(vk::MemoryPropertyFlags, vk::MemoryPropertyFlag
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_selector_by_index_or_name() {
+        let index = DeviceSelector::parse(" 1 ");
+        assert_eq!(index, DeviceSelector::Index(1));
+        assert!(index.matches(1, "Intel(R) UHD Graphics"));
+        assert!(!index.matches(0, "Intel(R) UHD Graphics"));
+        assert_eq!(index.to_string(), "#1");
+
+        let name = DeviceSelector::parse("rtx");
+        assert_eq!(name, DeviceSelector::Name("rtx".to_owned()));
+        assert!(name.matches(0, "NVIDIA GeForce RTX 3060 Laptop GPU"));
+        assert!(!name.matches(1, "Intel(R) UHD Graphics"));
+        assert_eq!(name.to_string(), "\"rtx\"");
+    }
+
+    #[test]
+    fn missing_extensions_are_listed() {
+        let extension = |name: &CStr| {
+            let mut properties = vk::ExtensionProperties::default();
+            for (dst, &src) in properties
+                .extension_name
+                .iter_mut()
+                .zip(name.to_bytes_with_nul())
+            {
+                *dst = src as _;
+            }
+            properties
+        };
+
+        let [memory, semaphore, storage] = required_extensions();
+        assert_eq!(
+            missing_extensions(&[extension(memory), extension(storage)]),
+            [semaphore]
+        );
+        assert!(missing_extensions(&[memory, semaphore, storage].map(extension)).is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+use std::cmp::Ordering;
+
+use hltas::types::Line;
+use hltas::HLTAS;
+
+/// [`HLTAS`] with cached frame indices and start times of its lines.
+///
+/// The caches are computed lazily and on every edit only the part after the edited line is
+/// invalidated, so querying after small edits is cheap.
+#[derive(Debug, Clone)]
+pub struct EditorFrameCache {
+    hltas: HLTAS,
+    /// Index of the first frame of every line, and the full frame count as the last item.
+    ///
+    /// As in [`line_first_frame_idx`](super::utils::line_first_frame_idx), the index starts at
+    /// `1`.
+    first_frame_idx: Vec<usize>,
+    /// Start time of every line in seconds, and the full duration as the last item.
+    start_time: Vec<f64>,
+    /// Number of items at the start of the caches which are up to date.
+    valid: usize,
+    /// Number of cache items computed so far.
+    computed: usize,
+}
+
+impl EditorFrameCache {
+    pub fn new(hltas: HLTAS) -> Self {
+        let len = hltas.lines.len() + 1;
+
+        let mut first_frame_idx = vec![0; len];
+        first_frame_idx[0] = 1;
+
+        Self {
+            hltas,
+            first_frame_idx,
+            start_time: vec![0.; len],
+            valid: 1,
+            computed: 0,
+        }
+    }
+
+    pub fn hltas(&self) -> &HLTAS {
+        &self.hltas
+    }
+
+    pub fn into_hltas(self) -> HLTAS {
+        self.hltas
+    }
+
+    /// Edits the line at `line_idx`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line_idx` is out of bounds.
+    pub fn edit_line(&mut self, line_idx: usize, f: impl FnOnce(&mut Line)) {
+        f(&mut self.hltas.lines[line_idx]);
+        self.invalidate_after(line_idx);
+    }
+
+    /// Inserts `line` at `line_idx`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line_idx` is greater than the number of lines.
+    pub fn insert_line(&mut self, line_idx: usize, line: Line) {
+        self.hltas.lines.insert(line_idx, line);
+        self.first_frame_idx.push(0);
+        self.start_time.push(0.);
+        self.invalidate_after(line_idx);
+    }
+
+    /// Removes and returns the line at `line_idx`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line_idx` is out of bounds.
+    pub fn remove_line(&mut self, line_idx: usize) -> Line {
+        let line = self.hltas.lines.remove(line_idx);
+        self.first_frame_idx.pop();
+        self.start_time.pop();
+        self.invalidate_after(line_idx);
+        line
+    }
+
+    /// Returns index of the first frame affected by the line at `line_idx`.
+    ///
+    /// `line_idx` can be one past the last line, in which case the full frame count is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line_idx` is greater than the number of lines.
+    pub fn line_first_frame_idx(&mut self, line_idx: usize) -> usize {
+        self.compute_up_to(line_idx);
+        self.first_frame_idx[line_idx]
+    }
+
+    /// Returns the time, in seconds, at which the frames of the line at `line_idx` begin.
+    ///
+    /// `line_idx` can be one past the last line, in which case the full duration is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line_idx` is greater than the number of lines.
+    pub fn line_start_time(&mut self, line_idx: usize) -> f64 {
+        self.compute_up_to(line_idx);
+        self.start_time[line_idx]
+    }
+
+    /// Returns the line index and the repeat for the frame at `frame_idx`.
+    ///
+    /// As in [`line_idx_and_repeat_at_frame`](super::utils::line_idx_and_repeat_at_frame), the
+    /// index starts at `0` at the first frame simulated by a frame bulk, and one past the last
+    /// frame gives the number of lines and a repeat of `0`. Returns [`None`] if `frame_idx` is
+    /// further past the end of the script.
+    pub fn line_idx_and_repeat_at_frame(&mut self, frame_idx: usize) -> Option<(usize, u32)> {
+        let line_count = self.hltas.lines.len();
+        self.compute_up_to(line_count);
+
+        // The cached indices count the initial frame.
+        let frame_idx = frame_idx + 1;
+        match frame_idx.cmp(&self.first_frame_idx[line_count]) {
+            Ordering::Less => (),
+            Ordering::Equal => return Some((line_count, 0)),
+            Ordering::Greater => return None,
+        }
+
+        let next_line_idx =
+            self.first_frame_idx[..line_count].partition_point(|&idx| idx <= frame_idx);
+        let line_idx = next_line_idx - 1;
+        let repeat = frame_idx - self.first_frame_idx[line_idx];
+        Some((line_idx, repeat as u32))
+    }
+
+    /// Returns index of the frame which is simulated at `time` seconds from the start.
+    ///
+    /// Returns [`None`] if `time` is negative or past the end of the script.
+    pub fn frame_at_time(&mut self, time: f64) -> Option<usize> {
+        let line_count = self.hltas.lines.len();
+        self.compute_up_to(line_count);
+
+        if time < 0. || time >= self.start_time[line_count] {
+            return None;
+        }
+
+        // This is the last line starting at or before `time`, and since the next line starts after
+        // `time`, this line has a non-zero duration, so it must be a frame bulk.
+        let line_idx = self.start_time[..line_count].partition_point(|&start| start <= time) - 1;
+        let bulk = self.hltas.lines[line_idx].frame_bulk().unwrap();
+        let frame_time = frame_time(bulk.frame_time.as_str());
+
+        let repeat = ((time - self.start_time[line_idx]) / frame_time) as usize;
+        let repeat = repeat.min(bulk.frame_count.get() as usize - 1);
+        Some(self.first_frame_idx[line_idx] + repeat)
+    }
+
+    fn invalidate_after(&mut self, line_idx: usize) {
+        self.valid = self.valid.min(line_idx + 1);
+    }
+
+    fn compute_up_to(&mut self, idx: usize) {
+        while self.valid <= idx {
+            let prev = self.valid - 1;
+            let (frames, duration) = match self.hltas.lines[prev].frame_bulk() {
+                Some(bulk) => {
+                    let frame_count = bulk.frame_count.get() as usize;
+                    let duration = frame_time(&bulk.frame_time) * frame_count as f64;
+                    (frame_count, duration)
+                }
+                None => (0, 0.),
+            };
+
+            self.first_frame_idx[self.valid] = self.first_frame_idx[prev] + frames;
+            self.start_time[self.valid] = self.start_time[prev] + duration;
+            self.valid += 1;
+            self.computed += 1;
+        }
+    }
+}
+
+fn frame_time(frame_time: &str) -> f64 {
+    frame_time.parse().unwrap_or(0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::*;
+    use crate::modules::tas_studio::editor::utils::{
+        line_first_frame_idx_and_frame_count, line_idx_and_repeat_at_frame,
+    };
+
+    #[track_caller]
+    fn hltas(bulks: &str) -> HLTAS {
+        HLTAS::from_str(&format!("version 1\nframes\n{bulks}")).unwrap()
+    }
+
+    #[test]
+    fn edit_recomputes_only_suffix() {
+        let hltas = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             ----------|------|------|0.004|-|-|5\n\
+             ----------|------|------|0.004|-|-|20\n\
+             ----------|------|------|0.004|-|-|3",
+        );
+        let mut cache = EditorFrameCache::new(hltas);
+
+        assert_eq!(cache.line_first_frame_idx(4), 39);
+        assert_eq!(cache.computed, 4);
+
+        cache.edit_line(2, |line| {
+            line.frame_bulk_mut().unwrap().frame_count = NonZeroU32::new(30).unwrap();
+        });
+
+        // Lines before and including the edited one keep their indices.
+        assert_eq!(cache.line_first_frame_idx(2), 16);
+        assert_eq!(cache.computed, 4);
+
+        // Only the indices after the edited line are recomputed.
+        assert_eq!(cache.line_first_frame_idx(4), 49);
+        assert_eq!(cache.computed, 6);
+
+        let expected = line_first_frame_idx_and_frame_count(cache.hltas()).collect::<Vec<_>>();
+        let actual = (0..=4)
+            .map(|line_idx| cache.line_first_frame_idx(line_idx))
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+
+        assert_eq!(cache.line_idx_and_repeat_at_frame(15), Some((2, 0)));
+        assert_eq!(cache.line_idx_and_repeat_at_frame(16), Some((2, 1)));
+        assert_eq!(cache.line_idx_and_repeat_at_frame(44), Some((2, 29)));
+        assert_eq!(cache.line_idx_and_repeat_at_frame(48), Some((4, 0)));
+        assert_eq!(cache.line_idx_and_repeat_at_frame(49), None);
+
+        for frame_idx in 0..50 {
+            assert_eq!(
+                cache.line_idx_and_repeat_at_frame(frame_idx),
+                line_idx_and_repeat_at_frame(&cache.hltas().lines, frame_idx),
+                "frame {frame_idx}"
+            );
+        }
+
+        assert!((cache.line_start_time(3) - 45. * 0.004).abs() < 1e-9);
+        assert_eq!(cache.frame_at_time(0.), Some(1));
+        assert_eq!(cache.frame_at_time(15.5 * 0.004), Some(16));
+        assert_eq!(cache.frame_at_time(1.), None);
+    }
+
+    #[test]
+    fn insert_and_remove_lines() {
+        let mut cache = EditorFrameCache::new(hltas(
+            "----------|------|------|0.004|-|-|10
+             ----------|------|------|0.004|-|-|5",
+        ));
+        assert_eq!(cache.line_first_frame_idx(2), 16);
+
+        let line = cache.remove_line(0);
+        assert_eq!(cache.line_first_frame_idx(1), 6);
+
+        cache.insert_line(1, line);
+        cache.insert_line(1, Line::Comment(" comment".to_owned()));
+        assert_eq!(cache.line_first_frame_idx(2), 6);
+        assert_eq!(cache.line_first_frame_idx(3), 16);
+
+        let hltas = cache.into_hltas();
+        let expected = line_first_frame_idx_and_frame_count(&hltas).collect::<Vec<_>>();
+        assert_eq!(expected, [1, 6, 6, 16]);
+    }
+}
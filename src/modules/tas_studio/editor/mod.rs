@@ -23,8 +23,8 @@ use self::operation::{Key, Operation};
 use self::toggle_auto_action::ToggleAutoActionTarget;
 use self::utils::{
     bulk_and_first_frame_idx, bulk_and_first_frame_idx_mut, bulk_idx_and_is_last,
-    bulk_idx_and_repeat_at_frame, join_lines, line_first_frame_idx, line_idx_and_repeat_at_frame,
-    FrameBulkExt, MaxAccelOffsetValuesMut,
+    bulk_idx_and_repeat_at_frame, debug_assert_frame_invariants, join_lines, line_first_frame_idx,
+    line_idx_and_repeat_at_frame, FrameBulkExt, MaxAccelOffsetValuesMut,
 };
 use super::remote::{AccurateFrame, PlayRequest};
 use crate::hooks::sdl::MouseState;
@@ -34,6 +34,7 @@ use crate::modules::triangle_drawing::triangle_api::{Primitive, RenderMode};
 use crate::modules::triangle_drawing::TriangleApi;
 
 mod db;
+pub mod frame_cache;
 pub mod operation;
 pub mod toggle_auto_action;
 pub mod utils;
@@ -1559,6 +1560,7 @@ impl Editor {
             self.invalidate(first_frame_idx + min(frame_count, new_frame_count) as usize);
         }
 
+        debug_assert_frame_invariants(&self.branch().branch.script);
         Ok(())
     }
 
@@ -1715,6 +1717,7 @@ impl Editor {
             self.invalidate(first_frame_idx + min(frame_count, new_frame_count) as usize);
         }
 
+        debug_assert_frame_invariants(&self.branch().branch.script);
         Ok(())
     }
 
@@ -2201,7 +2204,7 @@ impl Editor {
                 let to = String::from_utf8(buffer)
                     .expect("Line serialization should never produce invalid UTF-8");
 
-                join_lines(&mut prev_line, next_line);
+                join_lines(&mut prev_line, next_line)?;
                 let mut buffer = Vec::new();
                 hltas::write::gen_lines(&mut buffer, [&prev_line])
                     .expect("writing to an in-memory buffer should never fail");
@@ -2281,18 +2284,20 @@ impl Editor {
             return Ok(());
         }
 
-        let mut line = branch.branch.script.lines.remove(*camera_line_idx);
         let lines = &mut branch.branch.script.lines;
 
-        // Try to re-join frame bulks we might have split before.
+        // Try to re-join frame bulks we might have split before. This is done before removing the
+        // line so that nothing has changed yet if joining fails.
         if *did_split {
-            let next_line = lines[*camera_line_idx].clone();
+            let next_line = lines[*camera_line_idx + 1].clone();
             let prev_line = &mut lines[*camera_line_idx - 1];
 
-            join_lines(prev_line, &next_line);
-            lines.remove(*camera_line_idx);
+            join_lines(prev_line, &next_line)?;
+            lines.remove(*camera_line_idx + 1);
         }
 
+        let mut line = lines.remove(*camera_line_idx);
+
         let (line_idx, repeat) = line_idx_and_repeat_at_frame(&*lines, new_frame_idx).unwrap();
 
         if let Line::Change(Change { over, .. }) = &mut line {
@@ -2327,6 +2332,7 @@ impl Editor {
 
         self.invalidate(new_frame_idx);
 
+        debug_assert_frame_invariants(&self.branch().branch.script);
         Ok(())
     }
 
@@ -2693,20 +2699,28 @@ impl Editor {
                 .unwrap()
                 - 1;
 
-            branch.branch.script.lines.remove(camera_line_idx);
+            let lines = &mut branch.branch.script.lines;
 
             if did_split {
-                let lines = &mut branch.branch.script.lines;
-                let next_line = lines[camera_line_idx].clone();
+                let next_line = lines[camera_line_idx + 1].clone();
                 let prev_line = &mut lines[camera_line_idx - 1];
 
-                join_lines(prev_line, &next_line);
-                lines.remove(camera_line_idx);
+                // Leaving the frame bulks split doesn't change any frames.
+                match join_lines(prev_line, &next_line) {
+                    Ok(()) => {
+                        lines.remove(camera_line_idx + 1);
+                    }
+                    Err(err) => warn!("could not re-join the split frame bulk: {err:#}"),
+                }
             }
 
+            lines.remove(camera_line_idx);
+
             self.invalidate(curr_frame_idx);
         }
 
+        debug_assert_frame_invariants(&self.branch().branch.script);
+
         // Camera adjustment only takes effects after the key is released.
         // So there is no need to add this.
         // if let Some(CameraViewAdjustment { mode, starting_frame_idx, camera_line_idx }) =
@@ -7,8 +7,8 @@ use serde::{Deserialize, Serialize};
 
 use super::utils::{line_first_frame_idx, line_first_frame_idx_and_frame_count};
 use crate::modules::tas_studio::editor::utils::{
-    bulk_and_first_frame_idx_mut, line_idx_and_repeat_at_frame, FrameBulkExt,
-    MaxAccelOffsetValuesMut,
+    bulk_and_first_frame_idx_mut, debug_assert_frame_invariants, join_lines,
+    line_idx_and_repeat_at_frame, FrameBulkExt, MaxAccelOffsetValuesMut,
 };
 
 // This enum is stored in a SQLite DB as bincode bytes. All changes MUST BE BACKWARDS COMPATIBLE to
@@ -150,6 +150,21 @@ impl Operation {
     ///
     /// Returns `None` if all frames remain valid.
     pub fn apply(&self, hltas: &mut HLTAS) -> Option<usize> {
+        let first_frame_idx = self.apply_inner(hltas);
+        debug_assert_frame_invariants(hltas);
+        first_frame_idx
+    }
+
+    /// Undoes operation on HLTAS and returns index of first affected frame.
+    ///
+    /// Returns `None` if all frames remain valid.
+    pub fn undo(&self, hltas: &mut HLTAS) -> Option<usize> {
+        let first_frame_idx = self.undo_inner(hltas);
+        debug_assert_frame_invariants(hltas);
+        first_frame_idx
+    }
+
+    fn apply_inner(&self, hltas: &mut HLTAS) -> Option<usize> {
         match *self {
             Operation::SetFrameCount { bulk_idx, from, to } => {
                 let (bulk, first_frame_idx) = bulk_and_first_frame_idx_mut(hltas)
@@ -496,10 +511,7 @@ impl Operation {
         None
     }
 
-    /// Undoes operation on HLTAS and returns index of first affected frame.
-    ///
-    /// Returns `None` if all frames remain valid.
-    pub fn undo(&self, hltas: &mut HLTAS) -> Option<usize> {
+    fn undo_inner(&self, hltas: &mut HLTAS) -> Option<usize> {
         match *self {
             Operation::SetFrameCount { bulk_idx, from, to } => {
                 let (bulk, first_frame_idx) = bulk_and_first_frame_idx_mut(hltas)
@@ -546,17 +558,18 @@ impl Operation {
                 assert_eq!(repeat, 0, "current repeat should be 0");
                 assert!(line_idx > 0, "line index should be above 0");
 
-                let prev_bulk = match hltas.lines.remove(line_idx - 1) {
-                    Line::FrameBulk(prev_bulk) => prev_bulk,
-                    _ => panic!("previous line should be frame bulk"),
-                };
-                let bulk = hltas.lines[line_idx - 1].frame_bulk_mut().unwrap();
-                bulk.frame_count = bulk
-                    .frame_count
-                    .checked_add(prev_bulk.frame_count.get())
-                    .expect("combined frame count should fit");
-
-                // Merging equal frame bulks (undoing a split) does not invalidate any frames.
+                let next_line = hltas.lines[line_idx].clone();
+                let prev_line = &mut hltas.lines[line_idx - 1];
+                assert!(
+                    prev_line.frame_bulk().is_some(),
+                    "previous line should be frame bulk"
+                );
+
+                // Merging equal frame bulks (undoing a split) does not invalidate any frames. If
+                // they are too long to merge, leaving them split doesn't either.
+                if join_lines(prev_line, &next_line).is_ok() {
+                    hltas.lines.remove(line_idx);
+                }
             }
             Operation::Replace {
                 line_idx, ref from, ..
@@ -1,9 +1,16 @@
+use std::cmp::max;
+use std::collections::BTreeMap;
 use std::iter;
 use std::num::NonZeroU32;
+use std::ops::Range;
 
-use hltas::types::{AutoMovement, FrameBulk, Line, StrafeDir, StrafeSettings, StrafeType};
+use color_eyre::eyre::{self, ensure, eyre};
+use hltas::types::{
+    ActionKeys, AutoMovement, Change, ChangeTarget, FrameBulk, LeaveGroundAction,
+    LeaveGroundActionType, Line, MovementKeys, StrafeDir, StrafeSettings, StrafeType,
+};
 use hltas::HLTAS;
-use itertools::Itertools;
+use itertools::{EitherOrBoth, Itertools};
 
 /// Helper methods for `FrameBulk`.
 pub trait FrameBulkExt {
@@ -13,6 +20,26 @@ pub trait FrameBulkExt {
     /// Returns a mutable reference to the yaw stored in the frame bulk, if any.
     fn yaw_mut(&mut self) -> Option<&mut f32>;
 
+    /// Returns a reference to the pitch stored in the frame bulk, if any.
+    fn pitch(&self) -> Option<&f32>;
+
+    /// Returns a mutable reference to the pitch stored in the frame bulk, if any.
+    fn pitch_mut(&mut self) -> Option<&mut f32>;
+
+    /// Returns the strafe type of the frame bulk, if it strafes.
+    fn strafe_type(&self) -> Option<StrafeType>;
+
+    /// Returns a mutable reference to the strafe type of the frame bulk, if it strafes.
+    ///
+    /// Changing the type keeps the strafe direction, including its yaw.
+    fn strafe_type_mut(&mut self) -> Option<&mut StrafeType>;
+
+    /// Returns the strafe direction of the frame bulk, if it strafes.
+    fn strafe_dir(&self) -> Option<StrafeDir>;
+
+    /// Returns a mutable reference to the strafe direction of the frame bulk, if it strafes.
+    fn strafe_dir_mut(&mut self) -> Option<&mut StrafeDir>;
+
     /// Returns a reference to the left-right count stored in the frame bulk, if any.
     fn left_right_count(&self) -> Option<&NonZeroU32>;
 
@@ -75,6 +102,42 @@ impl FrameBulkExt for FrameBulk {
         }
     }
 
+    fn pitch(&self) -> Option<&f32> {
+        self.pitch.as_ref()
+    }
+
+    fn pitch_mut(&mut self) -> Option<&mut f32> {
+        self.pitch.as_mut()
+    }
+
+    fn strafe_type(&self) -> Option<StrafeType> {
+        match &self.auto_actions.movement {
+            Some(AutoMovement::Strafe(StrafeSettings { type_, .. })) => Some(*type_),
+            _ => None,
+        }
+    }
+
+    fn strafe_type_mut(&mut self) -> Option<&mut StrafeType> {
+        match &mut self.auto_actions.movement {
+            Some(AutoMovement::Strafe(StrafeSettings { type_, .. })) => Some(type_),
+            _ => None,
+        }
+    }
+
+    fn strafe_dir(&self) -> Option<StrafeDir> {
+        match &self.auto_actions.movement {
+            Some(AutoMovement::Strafe(StrafeSettings { dir, .. })) => Some(*dir),
+            _ => None,
+        }
+    }
+
+    fn strafe_dir_mut(&mut self) -> Option<&mut StrafeDir> {
+        match &mut self.auto_actions.movement {
+            Some(AutoMovement::Strafe(StrafeSettings { dir, .. })) => Some(dir),
+            _ => None,
+        }
+    }
+
     fn left_right_count(&self) -> Option<&NonZeroU32> {
         match &self.auto_actions.movement {
             Some(AutoMovement::Strafe(StrafeSettings {
@@ -290,8 +353,16 @@ pub fn bulk_idx_and_repeat_at_frame(hltas: &HLTAS, frame_idx: usize) -> Option<(
         .nth(frame_idx)
 }
 
+/// Adds the frames of the `next` frame bulk to the `prev` frame bulk.
+///
+/// Returns an error, leaving `prev` unchanged, if the combined frame count doesn't fit into one
+/// frame bulk.
+///
+/// # Panics
+///
+/// Panics if the lines aren't frame bulks or if they differ in anything but the frame count.
 #[track_caller]
-pub fn join_lines(prev: &mut Line, next: &Line) {
+pub fn join_lines(prev: &mut Line, next: &Line) -> eyre::Result<()> {
     let next_bulk = next.frame_bulk().unwrap();
     let prev_bulk = prev.frame_bulk_mut().unwrap();
 
@@ -302,5 +373,2520 @@ pub fn join_lines(prev: &mut Line, next: &Line) {
     prev_bulk.frame_count = temp;
     assert!(equal, "frame bulks are not equal");
 
-    prev_bulk.frame_count = NonZeroU32::new(temp.get() + next_bulk.frame_count.get()).unwrap();
+    let next_count = next_bulk.frame_count.get();
+    let Some(frame_count) = checked_frame_count_sum(temp.get(), next_count) else {
+        return Err(eyre!(
+            "frame bulks with {} and {next_count} frames are too long to join",
+            temp.get()
+        ));
+    };
+    prev_bulk.frame_count = frame_count;
+    Ok(())
+}
+
+/// Returns the sum of two frame counts, or [`None`] if it doesn't fit into a frame bulk.
+///
+/// The sum doesn't fit if it overflows [`u32`] or is zero.
+pub fn checked_frame_count_sum(a: u32, b: u32) -> Option<NonZeroU32> {
+    NonZeroU32::new(a.checked_add(b)?)
+}
+
+/// Moves the frames of `next` into `prev`, which must be otherwise identical.
+///
+/// If the combined frame count doesn't fit into one frame bulk, `prev` is filled up to the maximum
+/// frame count and `next` keeps the rest. Returns `true` if all frames were moved.
+fn move_frames_into(prev: &mut FrameBulk, next: &mut FrameBulk) -> bool {
+    let prev_count = prev.frame_count.get();
+    let next_count = next.frame_count.get();
+
+    if let Some(frame_count) = checked_frame_count_sum(prev_count, next_count) {
+        prev.frame_count = frame_count;
+        return true;
+    }
+
+    prev.frame_count = NonZeroU32::new(u32::MAX).unwrap();
+    next.frame_count = NonZeroU32::new(next_count - (u32::MAX - prev_count)).unwrap();
+    false
+}
+
+/// Returns line indices of frame bulks whose movement settings are internally inconsistent.
+///
+/// Only combinations that the HLTAS types can represent are checked: opposing movement keys held
+/// at the same time, constant yawspeed strafing in a direction other than left or right, and
+/// non-finite angles or strafe parameters.
+pub fn find_conflicting_movement(hltas: &HLTAS) -> Vec<usize> {
+    hltas
+        .lines
+        .iter()
+        .enumerate()
+        .filter_map(|(line_idx, line)| line.frame_bulk().map(|bulk| (line_idx, bulk)))
+        .filter(|(_, bulk)| has_conflicting_movement(bulk))
+        .map(|(line_idx, _)| line_idx)
+        .collect()
+}
+
+fn has_conflicting_movement(bulk: &FrameBulk) -> bool {
+    let keys = &bulk.movement_keys;
+    if (keys.forward && keys.back) || (keys.left && keys.right) || (keys.up && keys.down) {
+        return true;
+    }
+
+    match &bulk.auto_actions.movement {
+        Some(AutoMovement::SetYaw(yaw)) => !yaw.is_finite(),
+        Some(AutoMovement::Strafe(StrafeSettings { type_, dir })) => {
+            let type_is_valid = match *type_ {
+                StrafeType::ConstYawspeed(yawspeed) => {
+                    yawspeed.is_finite() && matches!(dir, StrafeDir::Left | StrafeDir::Right)
+                }
+                StrafeType::MaxAccelYawOffset {
+                    start,
+                    target,
+                    accel,
+                } => start.is_finite() && target.is_finite() && accel.is_finite(),
+                _ => true,
+            };
+
+            let dir_is_valid = match *dir {
+                StrafeDir::Yaw(yaw) | StrafeDir::Line { yaw } => yaw.is_finite(),
+                StrafeDir::Point { x, y } => x.is_finite() && y.is_finite(),
+                _ => true,
+            };
+
+            !type_is_valid || !dir_is_valid
+        }
+        None => false,
+    }
+}
+
+/// A single step of a frame bulk-level diff between two scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditOp {
+    /// The frame bulk at `b_line_idx` in the new script was inserted.
+    Insert { b_line_idx: usize },
+    /// The frame bulk at `a_line_idx` in the old script was deleted.
+    Delete { a_line_idx: usize },
+    /// The frame bulk at `a_line_idx` in the old script was changed into the frame bulk at
+    /// `b_line_idx` in the new script.
+    Modify {
+        a_line_idx: usize,
+        b_line_idx: usize,
+    },
+}
+
+/// Returns the operations turning frame bulks of `a` into frame bulks of `b`.
+///
+/// The diff is based on the longest common subsequence of the frame bulks, so unchanged frame
+/// bulks never show up in the result. Non-frame-bulk lines are ignored.
+pub fn bulk_edit_script(a: &HLTAS, b: &HLTAS) -> Vec<EditOp> {
+    fn bulks(hltas: &HLTAS) -> Vec<(usize, &FrameBulk)> {
+        hltas
+            .lines
+            .iter()
+            .enumerate()
+            .filter_map(|(line_idx, line)| line.frame_bulk().map(|bulk| (line_idx, bulk)))
+            .collect()
+    }
+
+    let a = bulks(a);
+    let b = bulks(b);
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i].1 == b[j].1 {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].1 == b[j].1 {
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j + 1] == lcs[i][j] {
+            // Replacing one bulk with the other doesn't make the common subsequence shorter.
+            ops.push(EditOp::Modify {
+                a_line_idx: a[i].0,
+                b_line_idx: b[j].0,
+            });
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(EditOp::Delete { a_line_idx: a[i].0 });
+            i += 1;
+        } else {
+            ops.push(EditOp::Insert { b_line_idx: b[j].0 });
+            j += 1;
+        }
+    }
+
+    ops.extend(
+        a[i..]
+            .iter()
+            .map(|&(a_line_idx, _)| EditOp::Delete { a_line_idx }),
+    );
+    ops.extend(
+        b[j..]
+            .iter()
+            .map(|&(b_line_idx, _)| EditOp::Insert { b_line_idx }),
+    );
+
+    ops
+}
+
+/// Returns the number of frames simulated by all frame bulks.
+///
+/// This doesn't include the initial frame.
+pub fn total_frame_count(hltas: &HLTAS) -> usize {
+    hltas
+        .frame_bulks()
+        .map(|bulk| bulk.frame_count.get() as usize)
+        .sum()
+}
+
+/// An automatic action that a frame bulk can enable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoAction {
+    Strafe,
+    AutoJump,
+    DuckTap,
+    JumpBug,
+    DuckBeforeCollision,
+    DuckBeforeGround,
+    DuckWhenJump,
+}
+
+impl AutoAction {
+    /// Returns whether the action is enabled in `bulk`.
+    pub fn is_enabled(self, bulk: &FrameBulk) -> bool {
+        let aa = &bulk.auto_actions;
+        match self {
+            AutoAction::Strafe => matches!(aa.movement, Some(AutoMovement::Strafe(_))),
+            AutoAction::AutoJump => matches!(
+                aa.leave_ground_action,
+                Some(LeaveGroundAction {
+                    type_: LeaveGroundActionType::Jump,
+                    ..
+                })
+            ),
+            AutoAction::DuckTap => matches!(
+                aa.leave_ground_action,
+                Some(LeaveGroundAction {
+                    type_: LeaveGroundActionType::DuckTap { .. },
+                    ..
+                })
+            ),
+            AutoAction::JumpBug => aa.jump_bug.is_some(),
+            AutoAction::DuckBeforeCollision => aa.duck_before_collision.is_some(),
+            AutoAction::DuckBeforeGround => aa.duck_before_ground.is_some(),
+            AutoAction::DuckWhenJump => aa.duck_when_jump.is_some(),
+        }
+    }
+}
+
+/// Returns, for every simulated frame, whether `action` is enabled on that frame.
+///
+/// The length of the result is equal to [`total_frame_count`].
+pub fn action_timeline(hltas: &HLTAS, action: AutoAction) -> Vec<bool> {
+    bulk_idx_and_is_last(&hltas.lines)
+        .map(|(_, bulk, _)| action.is_enabled(bulk))
+        .collect()
+}
+
+/// Returns `true` if the frame bulk doesn't move the player, press any keys or run any commands.
+fn is_idle(bulk: &FrameBulk) -> bool {
+    let aa = &bulk.auto_actions;
+    aa.movement.is_none()
+        && aa.leave_ground_action.is_none()
+        && aa.jump_bug.is_none()
+        && aa.duck_before_collision.is_none()
+        && aa.duck_before_ground.is_none()
+        && aa.duck_when_jump.is_none()
+        && bulk.movement_keys == MovementKeys::default()
+        && bulk.action_keys == ActionKeys::default()
+        && bulk.console_command.is_none()
+}
+
+/// Removes idle frame bulks from the end of the script and returns the number of removed frames.
+///
+/// Stops at the last frame bulk that does something. Other lines, such as comments, are kept.
+pub fn trim_trailing_idle(hltas: &mut HLTAS) -> usize {
+    let mut removed_frames = 0;
+
+    let mut line_idx = hltas.lines.len();
+    while line_idx > 0 {
+        line_idx -= 1;
+
+        let Some(bulk) = hltas.lines[line_idx].frame_bulk() else {
+            continue;
+        };
+
+        if !is_idle(bulk) {
+            break;
+        }
+
+        removed_frames += bulk.frame_count.get() as usize;
+        hltas.lines.remove(line_idx);
+    }
+
+    debug_assert_frame_invariants(hltas);
+    removed_frames
+}
+
+/// Returns how many frame bulks there are of every frame count.
+///
+/// Lots of single-frame bulks mean that the script is heavily fragmented.
+pub fn frame_count_histogram(hltas: &HLTAS) -> BTreeMap<u32, usize> {
+    let mut histogram = BTreeMap::new();
+    for bulk in hltas.frame_bulks() {
+        *histogram.entry(bulk.frame_count.get()).or_insert(0) += 1;
+    }
+    histogram
+}
+
+/// Limits the yaw change between consecutive frames in `start..end` to `max_deg_per_frame`.
+///
+/// Frame indices start at `1` because the very first frame is always the initial frame, which is
+/// not simulated by any frame bulk.
+///
+/// Only frames which have a yaw set take part; the first and the last of them keep their yaw so
+/// that the turn still starts and ends in the same place. Frame bulks without a yaw are left as is.
+/// Frame bulks are split as needed so that every changed frame can carry its own yaw; the console
+/// command, if any, stays on the first piece.
+///
+/// If the turn cannot fit into the range at the given rate, it is instead distributed evenly
+/// across the range, exceeding the rate.
+///
+/// Returns the number of frames whose yaw was changed.
+pub fn clamp_turn_rate(
+    hltas: &mut HLTAS,
+    max_deg_per_frame: f32,
+    start: usize,
+    end: usize,
+) -> usize {
+    let frames = bulk_and_first_frame_idx(hltas)
+        .flat_map(|(bulk, first_frame_idx)| {
+            let yaw = bulk.yaw().copied();
+            (first_frame_idx..first_frame_idx + bulk.frame_count.get() as usize)
+                .filter_map(move |frame_idx| yaw.map(|yaw| (frame_idx, yaw)))
+        })
+        .filter(|(frame_idx, _)| (start..end).contains(frame_idx))
+        .collect::<Vec<_>>();
+
+    if frames.len() < 3 {
+        return 0;
+    }
+
+    // Unwrap the yaws so that turning across 0 doesn't count as a full turn the other way.
+    let mut yaws = Vec::with_capacity(frames.len());
+    yaws.push(frames[0].1);
+    for ((_, prev), (_, yaw)) in frames.iter().tuple_windows() {
+        let delta = (yaw - prev + 180.).rem_euclid(360.) - 180.;
+        yaws.push(yaws.last().unwrap() + delta);
+    }
+
+    let steps = yaws.len() - 1;
+    let first = yaws[0];
+    let last = yaws[steps];
+    let max = max_deg_per_frame.abs();
+
+    let mut new_yaws = Vec::with_capacity(yaws.len());
+    if (last - first).abs() > max * steps as f32 {
+        warn!(
+            "the turn needs {} more degrees than fit at {} degrees per frame",
+            (last - first).abs() - max * steps as f32,
+            max
+        );
+
+        for i in 0..=steps {
+            new_yaws.push(first + (last - first) * i as f32 / steps as f32);
+        }
+    } else {
+        new_yaws.push(first);
+        for (i, &yaw) in yaws.iter().enumerate().skip(1) {
+            let prev = *new_yaws.last().unwrap();
+
+            // Stay within the rate from the previous frame while still being able to reach both
+            // endpoints.
+            let lo = (prev - max)
+                .max(first - max * i as f32)
+                .max(last - max * (steps - i) as f32);
+            let hi = (prev + max)
+                .min(first + max * i as f32)
+                .min(last + max * (steps - i) as f32);
+            new_yaws.push(yaw.max(lo).min(hi));
+        }
+    }
+
+    let changed = frames
+        .iter()
+        .zip(yaws)
+        .zip(new_yaws)
+        .filter(|((_, yaw), new_yaw)| (yaw - new_yaw).abs() > 0.0001)
+        .map(|(((frame_idx, _), _), new_yaw)| (*frame_idx, new_yaw))
+        .collect::<BTreeMap<_, _>>();
+
+    if changed.is_empty() {
+        return 0;
+    }
+
+    let mut first_frame_idx = 1;
+    let mut lines = Vec::with_capacity(hltas.lines.len());
+    for line in hltas.lines.drain(..) {
+        let bulk = match line {
+            Line::FrameBulk(bulk) => bulk,
+            line => {
+                lines.push(line);
+                continue;
+            }
+        };
+
+        let frame_count = bulk.frame_count.get() as usize;
+        let frame_range = first_frame_idx..first_frame_idx + frame_count;
+        first_frame_idx += frame_count;
+
+        let Some(&bulk_yaw) = bulk.yaw() else {
+            lines.push(Line::FrameBulk(bulk));
+            continue;
+        };
+
+        if changed.range(frame_range.clone()).next().is_none() {
+            lines.push(Line::FrameBulk(bulk));
+            continue;
+        }
+
+        let mut pieces: Vec<FrameBulk> = Vec::new();
+        for frame_idx in frame_range {
+            let yaw = changed.get(&frame_idx).copied().unwrap_or(bulk_yaw);
+
+            match pieces.last_mut() {
+                // Pieces only differ in yaw, so merge the ones that ended up with the same yaw.
+                // They never have more frames than the frame bulk they came from.
+                Some(last) if last.yaw() == Some(&yaw) => {
+                    last.frame_count = last.frame_count.saturating_add(1);
+                }
+                _ => {
+                    let mut piece = bulk.clone();
+                    piece.frame_count = NonZeroU32::new(1).unwrap();
+                    if !pieces.is_empty() {
+                        piece.console_command = None;
+                    }
+                    if let Some(piece_yaw) = piece.yaw_mut() {
+                        *piece_yaw = yaw;
+                    }
+                    pieces.push(piece);
+                }
+            }
+        }
+
+        lines.extend(pieces.into_iter().map(Line::FrameBulk));
+    }
+    hltas.lines = lines;
+
+    debug_assert_frame_invariants(hltas);
+    changed.len()
+}
+
+/// Returns the time, in seconds, at which the frames of the line at `line_idx` begin.
+///
+/// This is the sum of the durations of all frame bulks before the line. `line_idx` can be one
+/// past the last line, in which case the total duration of the script is returned.
+pub fn line_start_time(hltas: &HLTAS, line_idx: usize) -> eyre::Result<f64> {
+    ensure!(
+        line_idx <= hltas.lines.len(),
+        "line index {line_idx} is out of bounds ({} lines)",
+        hltas.lines.len()
+    );
+
+    let mut time = 0.;
+    for (idx, line) in hltas.lines[..line_idx].iter().enumerate() {
+        let Some(bulk) = line.frame_bulk() else {
+            continue;
+        };
+
+        let frame_time = bulk
+            .frame_time
+            .parse::<f64>()
+            .map_err(|err| eyre!("invalid frame time on line {idx}: {err}"))?;
+        ensure!(
+            frame_time > 0.,
+            "frame time on line {idx} must be positive, but it is {frame_time}"
+        );
+
+        time += frame_time * bulk.frame_count.get() as f64;
+    }
+
+    Ok(time)
+}
+
+/// Returns indices of lines with frame bulks whose strafe direction contradicts their movement
+/// keys.
+///
+/// For example, strafing to the left while also holding the right movement key. Unlike
+/// [`find_conflicting_movement`], this only looks at the strafe direction.
+pub fn find_contradictory_strafe(hltas: &HLTAS) -> Vec<usize> {
+    hltas
+        .lines
+        .iter()
+        .enumerate()
+        .filter_map(|(line_idx, line)| line.frame_bulk().map(|bulk| (line_idx, bulk)))
+        .filter(|(_, bulk)| has_contradictory_strafe(bulk))
+        .map(|(line_idx, _)| line_idx)
+        .collect()
+}
+
+fn has_contradictory_strafe(bulk: &FrameBulk) -> bool {
+    let keys = &bulk.movement_keys;
+    match &bulk.auto_actions.movement {
+        Some(AutoMovement::Strafe(StrafeSettings { dir, .. })) => match dir {
+            StrafeDir::Left => keys.right,
+            StrafeDir::Right => keys.left,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns the number of simulated frames whose frame bulk matches `predicate`.
+pub fn count_frames_where(hltas: &HLTAS, predicate: impl Fn(&FrameBulk) -> bool) -> usize {
+    hltas
+        .frame_bulks()
+        .filter(|bulk| predicate(bulk))
+        .map(|bulk| bulk.frame_count.get() as usize)
+        .sum()
+}
+
+/// Returns the fraction of simulated frames that use strafing.
+///
+/// Returns `0` for a script without frames.
+pub fn strafe_fraction(hltas: &HLTAS) -> f64 {
+    let total = total_frame_count(hltas);
+    if total == 0 {
+        return 0.;
+    }
+
+    let strafing = count_frames_where(hltas, |bulk| AutoAction::Strafe.is_enabled(bulk));
+    strafing as f64 / total as f64
+}
+
+/// Replaces strafing towards a yaw with setting that yaw.
+///
+/// Only strafe directions that carry a yaw ([`StrafeDir::Yaw`] and [`StrafeDir::Line`]) are
+/// converted; other directions can't be represented with a single yaw and are left as is.
+///
+/// Returns the number of converted frame bulks.
+pub fn strafe_to_set_yaw(hltas: &mut HLTAS) -> usize {
+    let mut converted = 0;
+
+    for bulk in hltas.frame_bulks_mut() {
+        if let Some(AutoMovement::Strafe(StrafeSettings {
+            dir: StrafeDir::Yaw(yaw) | StrafeDir::Line { yaw },
+            ..
+        })) = bulk.auto_actions.movement
+        {
+            bulk.auto_actions.movement = Some(AutoMovement::SetYaw(yaw));
+            converted += 1;
+        }
+    }
+
+    converted
+}
+
+/// Splits the script right after the first frame bulk with a console command containing
+/// `pattern`.
+///
+/// The first script contains everything up to and including the matching frame bulk, the second
+/// script contains the rest. Both keep the properties of the original script.
+///
+/// Returns [`None`] if no console command contains `pattern`.
+pub fn split_at_command_matching(hltas: &HLTAS, pattern: &str) -> Option<(HLTAS, HLTAS)> {
+    let line_idx = hltas.lines.iter().position(|line| {
+        line.frame_bulk()
+            .and_then(|bulk| bulk.console_command.as_deref())
+            .map_or(false, |command| command.contains(pattern))
+    })?;
+
+    let (before, after) = hltas.lines.split_at(line_idx + 1);
+    let before = HLTAS {
+        properties: hltas.properties.clone(),
+        lines: before.to_vec(),
+    };
+    let after = HLTAS {
+        properties: hltas.properties.clone(),
+        lines: after.to_vec(),
+    };
+
+    Some((before, after))
+}
+
+/// Kind of the movement of a frame bulk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementKind {
+    /// No automatic movement.
+    Manual,
+    /// Setting the yaw.
+    SetYaw,
+    /// Strafing.
+    Strafe,
+}
+
+fn movement_kind(bulk: &FrameBulk) -> MovementKind {
+    match bulk.auto_actions.movement {
+        None => MovementKind::Manual,
+        Some(AutoMovement::SetYaw(_)) => MovementKind::SetYaw,
+        Some(AutoMovement::Strafe(_)) => MovementKind::Strafe,
+    }
+}
+
+/// Summary of a frame bulk for displaying in a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkSummary {
+    /// Index of the line with the frame bulk.
+    pub line_idx: usize,
+    /// Index of the first frame affected by the frame bulk.
+    ///
+    /// As in [`line_first_frame_idx`], the index starts at `1`.
+    pub first_frame: usize,
+    pub frame_count: u32,
+    pub frame_time: String,
+    pub movement_kind: MovementKind,
+    pub yaw: Option<f32>,
+    pub pitch: Option<f32>,
+}
+
+/// Returns a summary of every frame bulk.
+pub fn bulk_summaries(hltas: &HLTAS) -> Vec<BulkSummary> {
+    hltas
+        .lines
+        .iter()
+        .zip(line_first_frame_idx(hltas))
+        .enumerate()
+        .filter_map(|(line_idx, (line, first_frame))| {
+            let bulk = line.frame_bulk()?;
+
+            Some(BulkSummary {
+                line_idx,
+                first_frame,
+                frame_count: bulk.frame_count.get(),
+                frame_time: bulk.frame_time.clone(),
+                movement_kind: movement_kind(bulk),
+                yaw: bulk.yaw().copied(),
+                pitch: bulk.pitch,
+            })
+        })
+        .collect()
+}
+
+/// Returns, for every simulated frame, the time at the end of that frame and the yaw set on it.
+///
+/// Times are in seconds from the start of the script. Unparsable frame times count as zero.
+pub fn yaw_time_series(hltas: &HLTAS) -> Vec<(f64, Option<f32>)> {
+    let mut time = 0.;
+    let mut series = Vec::with_capacity(total_frame_count(hltas));
+
+    for bulk in hltas.frame_bulks() {
+        let frame_time = bulk.frame_time.parse::<f64>().unwrap_or(0.);
+        let yaw = bulk.yaw().copied();
+
+        for _ in 0..bulk.frame_count.get() {
+            time += frame_time;
+            series.push((time, yaw));
+        }
+    }
+
+    series
+}
+
+/// Rounds the yaw of every frame bulk to the nearest multiple of `step_deg`.
+///
+/// Returns the number of frame bulks whose yaw was changed, or an error, leaving the script
+/// unchanged, if `step_deg` is not a positive finite number.
+pub fn quantize_yaws(hltas: &mut HLTAS, step_deg: f32) -> eyre::Result<usize> {
+    ensure!(
+        step_deg > 0. && step_deg.is_finite(),
+        "step must be positive, but it is {step_deg}"
+    );
+
+    let mut changed = 0;
+    for bulk in hltas.frame_bulks_mut() {
+        if let Some(yaw) = bulk.yaw_mut() {
+            let quantized = (*yaw / step_deg).round() * step_deg;
+            if quantized != *yaw {
+                *yaw = quantized;
+                changed += 1;
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Returns a line of HUD text for every simulated frame.
+///
+/// Each line shows the frame index (starting at `1` as in [`line_first_frame_idx`]), the time at
+/// the start of the frame and the strafe type used on the frame.
+pub fn frame_hud_lines(hltas: &HLTAS) -> Vec<String> {
+    let mut time = 0.;
+    let mut lines = Vec::with_capacity(total_frame_count(hltas));
+
+    for bulk in hltas.frame_bulks() {
+        let frame_time = bulk.frame_time.parse::<f64>().unwrap_or(0.);
+        let strafe = match &bulk.auto_actions.movement {
+            Some(AutoMovement::Strafe(StrafeSettings { type_, .. })) => match type_ {
+                StrafeType::MaxAccel => "speed increasing",
+                StrafeType::MaxAngle => "quick turn",
+                StrafeType::MaxDeccel => "slow down",
+                StrafeType::ConstSpeed => "constant speed",
+                StrafeType::ConstYawspeed(_) => "constant turn rate",
+                StrafeType::MaxAccelYawOffset { .. } => "yaw offset",
+            },
+            _ => "disabled",
+        };
+
+        for _ in 0..bulk.frame_count.get() {
+            lines.push(format!(
+                "Frame: {}  Time: {time:.3}  Strafe: {strafe}",
+                lines.len() + 1
+            ));
+            time += frame_time;
+        }
+    }
+
+    lines
+}
+
+/// Joins single-frame bulks into the preceding frame bulk if they are otherwise identical.
+///
+/// Comment lines between the two don't prevent joining; they are moved above the result, after
+/// the comments of the preceding frame bulk.
+///
+/// Frame bulks with console commands are left alone so that the commands run as many times as
+/// before.
+///
+/// Returns the number of removed lines.
+pub fn coalesce_single_frame_bulks(hltas: &mut HLTAS) -> usize {
+    let old_len = hltas.lines.len();
+
+    let mut lines: Vec<Line> = Vec::with_capacity(old_len);
+    for line in hltas.lines.drain(..) {
+        let comments = comments_around(&lines, lines.len());
+        if let (Some(prev_idx), Some(bulk)) = (comments.start.checked_sub(1), line.frame_bulk()) {
+            let prev = &mut lines[prev_idx];
+            let can_join = bulk.frame_count.get() == 1
+                && bulk.console_command.is_none()
+                && prev.frame_bulk().map_or(false, |prev_bulk| {
+                    let mut prev_bulk = prev_bulk.clone();
+                    prev_bulk.frame_count = bulk.frame_count;
+                    prev_bulk == *bulk
+                });
+
+            if can_join {
+                let mut line = line;
+                if move_frames_into(
+                    prev.frame_bulk_mut().unwrap(),
+                    line.frame_bulk_mut().unwrap(),
+                ) {
+                    // Keep the comments of the joined frame bulk above the result.
+                    lines[prev_idx..].rotate_left(1);
+                } else {
+                    lines.push(line);
+                }
+                continue;
+            }
+        }
+
+        lines.push(line);
+    }
+    hltas.lines = lines;
+
+    debug_assert_frame_invariants(hltas);
+    old_len - hltas.lines.len()
+}
+
+/// Returns the range of comment lines directly above the line at `idx`.
+///
+/// Comments describe the line below them, so these are the comments that belong to it. Utilities
+/// which merge frame bulks keep the comments of both above the result, and ones which split a
+/// frame bulk keep its comments with the first half.
+pub fn comments_around(lines: &[Line], idx: usize) -> Range<usize> {
+    let start = lines[..idx]
+        .iter()
+        .rposition(|line| !matches!(line, Line::Comment(_)))
+        .map_or(0, |prev_idx| prev_idx + 1);
+    start..idx
+}
+
+/// Parses `raw` and coalesces the single-frame bulks in it.
+///
+/// Some tools write out every frame as a separate frame bulk; this cleans them up on import.
+pub fn import_and_coalesce(raw: &str) -> eyre::Result<HLTAS> {
+    let mut hltas =
+        HLTAS::from_str(raw).map_err(|err| eyre!("invalid script, cannot parse: {err:?}"))?;
+    coalesce_single_frame_bulks(&mut hltas);
+    Ok(hltas)
+}
+
+/// Returns the distinct frame times used by the frame bulks, sorted in ascending order.
+///
+/// Frame times that can't be parsed are skipped.
+pub fn distinct_frame_times(hltas: &HLTAS) -> Vec<f64> {
+    let mut frame_times = hltas
+        .frame_bulks()
+        .filter_map(|bulk| bulk.frame_time.parse::<f64>().ok())
+        .collect::<Vec<_>>();
+    frame_times.sort_by(f64::total_cmp);
+    frame_times.dedup();
+    frame_times
+}
+
+/// Shifts the phase of the left-right or right-left strafe frame bulk at `line_idx` by `by`
+/// frames.
+///
+/// The first strafe direction is held for `by` frames less than the count: these frames are split
+/// off into a leading frame bulk strafing in that direction only, and the rest of the frame bulk
+/// continues the pattern starting with the complementary direction. The console command, if any,
+/// stays on the leading frame bulk.
+///
+/// Returns `false` and leaves the script unchanged if the line is not a left-right or right-left
+/// strafe frame bulk, or if `by` is not less than its count.
+pub fn shift_left_right_phase(hltas: &mut HLTAS, line_idx: usize, by: u32) -> bool {
+    let Some(bulk) = hltas.lines.get_mut(line_idx).and_then(Line::frame_bulk_mut) else {
+        return false;
+    };
+    let Some(AutoMovement::Strafe(settings)) = &mut bulk.auto_actions.movement else {
+        return false;
+    };
+
+    let (count, first_dir, rest_dir) = match settings.dir {
+        StrafeDir::LeftRight(count) => (count, StrafeDir::Left, StrafeDir::RightLeft(count)),
+        StrafeDir::RightLeft(count) => (count, StrafeDir::Right, StrafeDir::LeftRight(count)),
+        _ => return false,
+    };
+    if by >= count.get() {
+        return false;
+    }
+
+    let lead_count = count.get() - by;
+    if bulk.frame_count.get() <= lead_count {
+        // The whole frame bulk ends before the first direction switch.
+        settings.dir = first_dir;
+        return true;
+    }
+
+    settings.dir = rest_dir;
+    let mut lead = bulk.clone();
+    lead.frame_count = NonZeroU32::new(lead_count).unwrap();
+    if let Some(AutoMovement::Strafe(settings)) = &mut lead.auto_actions.movement {
+        settings.dir = first_dir;
+    }
+
+    bulk.frame_count = NonZeroU32::new(bulk.frame_count.get() - lead_count).unwrap();
+    bulk.console_command = None;
+
+    hltas.lines.insert(line_idx, Line::FrameBulk(lead));
+
+    debug_assert_frame_invariants(hltas);
+    true
+}
+
+/// Normalizes the script so that cosmetic differences don't show up in diffs.
+///
+/// Yaws are brought into the `[0; 360)` range, frame times are written in the shortest form which
+/// parses back to the same value, and adjacent frame bulks which differ only in the frame count are
+/// merged. Frame bulks are not merged when this could change the behavior: if the second one has a
+/// console command, or if they use left-right or yaw offset strafing, whose patterns restart with
+/// every frame bulk. If the merged frame count wouldn't fit into one frame bulk, the frames are
+/// split between two. The comments of both merged frame bulks end up above the result.
+pub fn canonicalize(hltas: &mut HLTAS) {
+    for bulk in hltas.frame_bulks_mut() {
+        match &mut bulk.auto_actions.movement {
+            Some(AutoMovement::SetYaw(yaw))
+            | Some(AutoMovement::Strafe(StrafeSettings {
+                dir: StrafeDir::Yaw(yaw) | StrafeDir::Line { yaw },
+                ..
+            })) => *yaw = yaw.rem_euclid(360.),
+            _ => (),
+        }
+
+        if let Ok(frame_time) = bulk.frame_time.parse::<f64>() {
+            bulk.frame_time = frame_time.to_string();
+        }
+    }
+
+    let mut lines: Vec<Line> = Vec::with_capacity(hltas.lines.len());
+    for line in hltas.lines.drain(..) {
+        let comments = comments_around(&lines, lines.len());
+        if let (Some(prev_idx), Some(bulk)) = (comments.start.checked_sub(1), line.frame_bulk()) {
+            let prev = &mut lines[prev_idx];
+            let restarts_pattern = matches!(
+                bulk.auto_actions.movement,
+                Some(AutoMovement::Strafe(StrafeSettings {
+                    type_: StrafeType::MaxAccelYawOffset { .. },
+                    ..
+                })) | Some(AutoMovement::Strafe(StrafeSettings {
+                    dir: StrafeDir::LeftRight(_) | StrafeDir::RightLeft(_),
+                    ..
+                }))
+            );
+
+            let can_join = bulk.console_command.is_none()
+                && !restarts_pattern
+                && prev.frame_bulk().map_or(false, |prev_bulk| {
+                    let mut prev_bulk = prev_bulk.clone();
+                    prev_bulk.frame_count = bulk.frame_count;
+                    prev_bulk.console_command = None;
+                    prev_bulk == *bulk
+                });
+
+            if can_join {
+                let mut line = line;
+                if move_frames_into(
+                    prev.frame_bulk_mut().unwrap(),
+                    line.frame_bulk_mut().unwrap(),
+                ) {
+                    // Keep the comments of the merged frame bulk above the result.
+                    lines[prev_idx..].rotate_left(1);
+                } else {
+                    // The previous frame bulk is full, so the rest stays in this one.
+                    lines.push(line);
+                }
+                continue;
+            }
+        }
+
+        lines.push(line);
+    }
+    hltas.lines = lines;
+
+    debug_assert_frame_invariants(hltas);
+}
+
+/// Returns index of the first frame from which the frame indices differ between the two scripts.
+///
+/// This is the first frame of the first frame bulk whose frame count changed, or which was inserted
+/// or deleted. Returns [`None`] if all frame bulks have the same frame counts.
+///
+/// The index starts at `1` because the very first frame is always the initial frame, which is not
+/// simulated by any frame bulk.
+pub fn frames_shifted_by_edit(before: &HLTAS, after: &HLTAS) -> Option<usize> {
+    bulk_and_first_frame_idx(before)
+        .zip_longest(bulk_and_first_frame_idx(after))
+        .find_map(|pair| match pair {
+            EitherOrBoth::Both((a, first_frame_idx), (b, _)) => {
+                (a.frame_count != b.frame_count).then_some(first_frame_idx)
+            }
+            EitherOrBoth::Left((_, first_frame_idx))
+            | EitherOrBoth::Right((_, first_frame_idx)) => Some(first_frame_idx),
+        })
+}
+
+/// Returns the first and the last frame index of the longest run of consecutive frames with the
+/// same movement kind and yaw.
+///
+/// Runs can span multiple frame bulks. If several runs are equally long, the first one is
+/// returned. As in [`line_first_frame_idx`], the index starts at `1`. Returns [`None`] if the
+/// script has no frames.
+pub fn longest_constant_movement_run(hltas: &HLTAS) -> Option<(usize, usize)> {
+    let runs = bulk_idx_and_is_last(&hltas.lines)
+        .map(|(_, bulk, _)| (movement_kind(bulk), bulk.yaw().copied()))
+        .dedup_with_count();
+
+    let mut longest: Option<(usize, usize)> = None;
+    let mut start = 1;
+    for (count, _) in runs {
+        let end = start + count - 1;
+        if longest.map_or(true, |(first, last)| count > last - first + 1) {
+            longest = Some((start, end));
+        }
+        start = end + 1;
+    }
+
+    longest
+}
+
+/// Checks that the frame indexing functions agree with each other on `hltas`.
+///
+/// Editing functions call this at the end to catch bugs early. The checks only run in debug
+/// builds.
+///
+/// # Panics
+///
+/// Panics if the frame indices are inconsistent.
+#[track_caller]
+pub fn debug_assert_frame_invariants(hltas: &HLTAS) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let total = total_frame_count(hltas);
+
+    let line_frame_idx = line_first_frame_idx_and_frame_count(hltas).collect::<Vec<_>>();
+    assert_eq!(
+        line_frame_idx.last(),
+        Some(&(total + 1)),
+        "line frame indices don't add up to the total frame count"
+    );
+
+    let mut expected_frame_idx = 1;
+    let bulk_lines = hltas
+        .lines
+        .iter()
+        .zip(&line_frame_idx)
+        .filter(|(line, _)| line.frame_bulk().is_some());
+    for ((bulk, first_frame_idx), (_, &line_first_frame_idx)) in
+        bulk_and_first_frame_idx(hltas).zip(bulk_lines)
+    {
+        assert!(bulk.frame_count.get() >= 1, "frame bulk has no frames");
+        assert_eq!(
+            first_frame_idx, expected_frame_idx,
+            "frame bulk indices don't accumulate the frame counts"
+        );
+        assert_eq!(
+            first_frame_idx, line_first_frame_idx,
+            "frame bulk and line indices disagree"
+        );
+        expected_frame_idx += bulk.frame_count.get() as usize;
+    }
+    assert_eq!(
+        expected_frame_idx,
+        total + 1,
+        "frame bulk indices don't add up to the total frame count"
+    );
+}
+
+/// Inserts `transition_frames` frames before the frame at `at_frame` which smoothly turn from the
+/// yaw of the previous frame to the yaw of the frame at `at_frame`.
+///
+/// Every inserted frame is a single-frame bulk setting the yaw, linearly interpolated the short
+/// way around, and otherwise copying the frame bulk of the previous frame. The frame bulk
+/// containing `at_frame` is split if needed with [`split_bulk_at_frame`], which restarts the
+/// pattern of left-right and yaw offset strafing. As in [`line_first_frame_idx`], the index starts
+/// at `1`.
+///
+/// There's nothing to interpolate between unless both frames exist and have a yaw, so unlike most
+/// edits this one can fail on a valid script. Returns an error, leaving the script unchanged, if
+/// `transition_frames` is zero, if `at_frame` is the first frame or past the last one, or if the
+/// frame at `at_frame` or the one before it has no yaw.
+pub fn bridge_yaw(hltas: &mut HLTAS, at_frame: usize, transition_frames: u32) -> eyre::Result<()> {
+    ensure!(
+        transition_frames > 0,
+        "transition must have at least one frame"
+    );
+    ensure!(
+        at_frame >= 2 && at_frame <= total_frame_count(hltas),
+        "frame {at_frame} has no frames on both sides"
+    );
+
+    // Returns the line index, the first frame index and the frame bulk containing the frame.
+    let bulk_at = |frame_idx: usize| {
+        hltas
+            .lines
+            .iter()
+            .zip(line_first_frame_idx(hltas))
+            .enumerate()
+            .filter_map(|(line_idx, (line, first_frame_idx))| {
+                line.frame_bulk()
+                    .map(|bulk| (line_idx, first_frame_idx, bulk))
+            })
+            .find(|(_, first_frame_idx, bulk)| {
+                frame_idx < first_frame_idx + bulk.frame_count.get() as usize
+            })
+            .unwrap()
+    };
+
+    let (_, _, prev_bulk) = bulk_at(at_frame - 1);
+    let (line_idx, _, next_bulk) = bulk_at(at_frame);
+    let Some(&prev_yaw) = prev_bulk.yaw() else {
+        return Err(eyre!("frame {} has no yaw", at_frame - 1));
+    };
+    let Some(&next_yaw) = next_bulk.yaw() else {
+        return Err(eyre!("frame {at_frame} has no yaw"));
+    };
+
+    let mut template = prev_bulk.clone();
+    template.frame_count = NonZeroU32::new(1).unwrap();
+    template.console_command = None;
+
+    let insert_idx = split_bulk_at_frame(hltas, at_frame).unwrap_or(line_idx);
+
+    let delta = (next_yaw - prev_yaw + 180.).rem_euclid(360.) - 180.;
+    let transition = (1..=transition_frames).map(|i| {
+        let t = i as f32 / (transition_frames + 1) as f32;
+        let mut bulk = template.clone();
+        bulk.auto_actions.movement = Some(AutoMovement::SetYaw(
+            (prev_yaw + delta * t).rem_euclid(360.),
+        ));
+        Line::FrameBulk(bulk)
+    });
+    hltas.lines.splice(insert_idx..insert_idx, transition);
+
+    debug_assert_frame_invariants(hltas);
+    Ok(())
+}
+
+/// Splits the script into frame ranges at the points where the turning direction reverses.
+///
+/// The turning direction of a frame is the sign of the yaw change from the previous frame, taken
+/// the short way around. Frames which don't turn, or which have no yaw, continue the current
+/// range. The ranges are inclusive and cover all frames; as in [`line_first_frame_idx`], the index
+/// starts at `1`.
+pub fn split_at_turn_reversals(hltas: &HLTAS) -> Vec<(usize, usize)> {
+    let yaws = bulk_idx_and_is_last(&hltas.lines).map(|(_, bulk, _)| bulk.yaw().copied());
+
+    let mut ranges = Vec::new();
+    let mut start = 1;
+    let mut direction = 0.;
+    let mut prev_yaw: Option<f32> = None;
+    let mut frame_count = 0;
+    for (frame_idx, yaw) in (1..).zip(yaws) {
+        frame_count = frame_idx;
+
+        if let (Some(prev), Some(yaw)) = (prev_yaw, yaw) {
+            let delta: f32 = (yaw - prev + 180.).rem_euclid(360.) - 180.;
+            if delta != 0. {
+                let new_direction = delta.signum();
+                if direction != 0. && new_direction != direction {
+                    ranges.push((start, frame_idx - 1));
+                    start = frame_idx;
+                }
+                direction = new_direction;
+            }
+        }
+
+        prev_yaw = yaw;
+    }
+
+    if frame_count > 0 {
+        ranges.push((start, frame_count));
+    }
+    ranges
+}
+
+/// Returns the line index of every frame bulk paired with its frame count.
+///
+/// This is a run-length encoding of the line index of the frame bulk simulating every frame, which
+/// is a compact alternative to expanding it per frame.
+pub fn bulk_rle(lines: &[Line]) -> Vec<(usize, usize)> {
+    let bulk_line_idx = lines
+        .iter()
+        .positions(|line| line.frame_bulk().is_some())
+        .collect::<Vec<_>>();
+
+    let mut rle = Vec::with_capacity(bulk_line_idx.len());
+    let mut run_length = 0;
+    for (bulk_idx, _, is_last) in bulk_idx_and_is_last(lines) {
+        run_length += 1;
+
+        if is_last {
+            rle.push((bulk_line_idx[bulk_idx], run_length));
+            run_length = 0;
+        }
+    }
+
+    rle
+}
+
+/// Returns line indices of strafing frame bulks whose frame time differs from the previous
+/// strafing frame bulk in the same strafing run.
+///
+/// A strafing run is a sequence of strafing frame bulks without any non-strafing frame bulk in
+/// between. Changing the frame time in the middle of a run can subtly desync the simulation.
+pub fn find_frametime_changes_within_strafe(hltas: &HLTAS) -> Vec<usize> {
+    let mut line_indices = Vec::new();
+    let mut prev_frame_time = None;
+    for (line_idx, line) in hltas.lines.iter().enumerate() {
+        let Some(bulk) = line.frame_bulk() else {
+            continue;
+        };
+
+        if !matches!(bulk.auto_actions.movement, Some(AutoMovement::Strafe(_))) {
+            prev_frame_time = None;
+            continue;
+        }
+
+        let frame_time = bulk.frame_time.parse::<f64>().ok();
+        if matches!(prev_frame_time, Some(prev) if prev != frame_time) {
+            line_indices.push(line_idx);
+        }
+        prev_frame_time = Some(frame_time);
+    }
+    line_indices
+}
+
+/// Returns the frame count changes which make the frame bulks of `from` match those of `to`.
+///
+/// Every item is the index of a frame bulk among all frame bulks, and the frame count from `to`,
+/// for every frame bulk whose frame count differs. Returns [`None`] if the scripts have a different
+/// number of frame bulks.
+pub fn frame_count_alignment(from: &HLTAS, to: &HLTAS) -> Option<Vec<(usize, u32)>> {
+    let from_bulks = from.lines.iter().filter_map(Line::frame_bulk);
+    let to_bulks = to.lines.iter().filter_map(Line::frame_bulk);
+
+    let mut changes = Vec::new();
+    for (bulk_idx, pair) in from_bulks.zip_longest(to_bulks).enumerate() {
+        let EitherOrBoth::Both(from_bulk, to_bulk) = pair else {
+            return None;
+        };
+
+        if from_bulk.frame_count != to_bulk.frame_count {
+            changes.push((bulk_idx, to_bulk.frame_count.get()));
+        }
+    }
+    Some(changes)
+}
+
+/// Merges the console commands of `commands` into `movement` at the same frames.
+///
+/// Every console command in `commands` runs on the first frame of its frame bulk. The frame bulk
+/// of `movement` containing that frame is split with [`split_bulk_at_frame`] so that a frame bulk
+/// starts there, and the command is appended to its console command. The total frame count of
+/// `movement` doesn't change.
+///
+/// Returns an error, leaving `movement` unchanged, if a command is past the end of `movement`.
+pub fn merge_commands_by_frame(movement: &mut HLTAS, commands: &HLTAS) -> eyre::Result<()> {
+    let total = total_frame_count(movement);
+    let to_merge = bulk_and_first_frame_idx(commands)
+        .filter_map(|(bulk, frame_idx)| {
+            bulk.console_command
+                .as_deref()
+                .map(|command| (frame_idx, command))
+        })
+        .collect::<Vec<_>>();
+
+    if let Some((frame_idx, command)) = to_merge.iter().find(|(idx, _)| *idx > total) {
+        return Err(eyre!(
+            "command `{command}` at frame {frame_idx} is past the end of the script"
+        ));
+    }
+
+    for (frame_idx, command) in to_merge {
+        let line_idx = match split_bulk_at_frame(movement, frame_idx) {
+            Some(line_idx) => line_idx,
+            // The frame already starts a frame bulk. Unlike the frame indices here, the ones in
+            // line_idx_and_repeat_at_frame() don't count the initial frame.
+            None => {
+                line_idx_and_repeat_at_frame(&movement.lines, frame_idx - 1)
+                    .unwrap()
+                    .0
+            }
+        };
+
+        let bulk = movement.lines[line_idx].frame_bulk_mut().unwrap();
+        bulk.console_command = Some(match bulk.console_command.take() {
+            Some(existing) => format!("{existing};{command}"),
+            None => command.to_owned(),
+        });
+    }
+
+    debug_assert_frame_invariants(movement);
+    Ok(())
+}
+
+/// Appends an idle frame bulk with `frame_time` so that the script has `target` frames in total.
+///
+/// Returns the number of added frames, which is zero if the script already has `target` frames.
+/// Returns an error if the script has more than `target` frames.
+pub fn pad_to_frame_count(hltas: &mut HLTAS, target: usize, frame_time: &str) -> eyre::Result<u32> {
+    let total = total_frame_count(hltas);
+    ensure!(
+        total <= target,
+        "script already has {total} frames, more than {target}"
+    );
+
+    let Some(frame_count) = NonZeroU32::new(u32::try_from(target - total)?) else {
+        return Ok(0);
+    };
+
+    let mut bulk = FrameBulk::with_frame_time(frame_time.to_owned());
+    bulk.frame_count = frame_count;
+    debug_assert!(is_idle(&bulk));
+    hltas.lines.push(Line::FrameBulk(bulk));
+
+    debug_assert_frame_invariants(hltas);
+    Ok(frame_count.get())
+}
+
+/// Returns indices of lines after the last frame bulk.
+///
+/// These lines only take effect after the last frame of the script has been simulated, if at
+/// all. Without any frame bulks, every line is returned.
+pub fn lines_after_last_frame(hltas: &HLTAS) -> Vec<usize> {
+    let first = hltas
+        .lines
+        .iter()
+        .rposition(|line| matches!(line, Line::FrameBulk(_)))
+        .map_or(0, |line_idx| line_idx + 1);
+    (first..hltas.lines.len()).collect()
+}
+
+/// Splits the frame bulk which simulates the frame at `frame_idx` so that a new frame bulk starts
+/// at that frame.
+///
+/// Both frame bulks keep the same actions, and the console command and the comments, if any, stay
+/// with the first one. The simulation doesn't change, except for left-right and yaw offset
+/// strafing, whose patterns restart with every frame bulk. Returns the line index of the new frame
+/// bulk, or [`None`] if `frame_idx` is already the first frame of a frame bulk or is past the end
+/// of the script.
+pub fn split_bulk_at_frame(hltas: &mut HLTAS, frame_idx: usize) -> Option<usize> {
+    let (bulk_idx, first_frame_idx) = bulk_and_first_frame_idx(hltas)
+        .enumerate()
+        .find(|(_, (bulk, first_frame_idx))| {
+            frame_idx < first_frame_idx + bulk.frame_count.get() as usize
+        })
+        .map(|(bulk_idx, (_, first_frame_idx))| (bulk_idx, first_frame_idx))?;
+
+    if frame_idx <= first_frame_idx {
+        return None;
+    }
+
+    let line_idx = hltas
+        .lines
+        .iter()
+        .positions(|line| matches!(line, Line::FrameBulk(_)))
+        .nth(bulk_idx)
+        .unwrap();
+
+    let before_count = (frame_idx - first_frame_idx) as u32;
+    let bulk = hltas.lines[line_idx].frame_bulk_mut().unwrap();
+    let mut after = bulk.clone();
+    after.frame_count = NonZeroU32::new(bulk.frame_count.get() - before_count).unwrap();
+    after.console_command = None;
+    bulk.frame_count = NonZeroU32::new(before_count).unwrap();
+
+    hltas.lines.insert(line_idx + 1, Line::FrameBulk(after));
+
+    debug_assert_frame_invariants(hltas);
+    Some(line_idx + 1)
+}
+
+/// Returns the line index of every frame bulk and the fraction of the total duration of the
+/// script that it takes up.
+///
+/// Frame bulks with an invalid frame time take up no time. If the whole script takes no time,
+/// every fraction is `0`.
+pub fn bulk_time_fractions(hltas: &HLTAS) -> Vec<(usize, f64)> {
+    let durations = hltas
+        .lines
+        .iter()
+        .enumerate()
+        .filter_map(|(line_idx, line)| line.frame_bulk().map(|bulk| (line_idx, bulk)))
+        .map(|(line_idx, bulk)| {
+            let frame_time = bulk.frame_time.parse::<f64>().unwrap_or(0.).max(0.);
+            (line_idx, frame_time * bulk.frame_count.get() as f64)
+        })
+        .collect::<Vec<_>>();
+
+    let total = durations.iter().map(|(_, duration)| duration).sum::<f64>();
+    durations
+        .into_iter()
+        .map(|(line_idx, duration)| {
+            let fraction = if total > 0. { duration / total } else { 0. };
+            (line_idx, fraction)
+        })
+        .collect()
+}
+
+/// Returns the line index and the yaw of the frame bulk whose yaw is the farthest from `0`.
+///
+/// The distance wraps around, so `350` is closer to `0` than `-170`. On ties the first frame bulk
+/// is returned. Returns [`None`] if no frame bulk has a yaw.
+pub fn extreme_yaw_bulk(hltas: &HLTAS) -> Option<(usize, f32)> {
+    let distance_from_zero = |yaw: f32| ((yaw + 180.).rem_euclid(360.) - 180.).abs();
+
+    hltas
+        .lines
+        .iter()
+        .enumerate()
+        .filter_map(|(line_idx, line)| Some((line_idx, *line.frame_bulk()?.yaw()?)))
+        .reduce(|extreme, candidate| {
+            if distance_from_zero(candidate.1) > distance_from_zero(extreme.1) {
+                candidate
+            } else {
+                extreme
+            }
+        })
+}
+
+/// Removes the simulated frames in `[start_frame, end_frame)` and returns how many were removed.
+///
+/// As in [`bulk_and_first_frame_idx`], the first simulated frame has the index `1`. Frame bulks
+/// that overlap the range partially are shrunk, and the ones that end up with no frames are
+/// removed. Lines other than frame bulks are kept.
+pub fn delete_frame_range(hltas: &mut HLTAS, start_frame: usize, end_frame: usize) -> usize {
+    let mut removed = 0;
+    let mut frame_idx = 1;
+
+    hltas.lines.retain_mut(|line| {
+        let Some(bulk) = line.frame_bulk_mut() else {
+            return true;
+        };
+
+        let frame_count = bulk.frame_count.get() as usize;
+        let first_frame_idx = frame_idx;
+        frame_idx += frame_count;
+
+        let overlap = frame_idx
+            .min(end_frame)
+            .saturating_sub(first_frame_idx.max(start_frame));
+        removed += overlap;
+
+        match NonZeroU32::new((frame_count - overlap) as u32) {
+            Some(frame_count) => {
+                bulk.frame_count = frame_count;
+                true
+            }
+            None => false,
+        }
+    });
+
+    debug_assert_frame_invariants(hltas);
+    removed
+}
+
+/// Returns a value for every simulated frame, carrying it over from the previous lines.
+///
+/// `update` returns `Some` with the new value for lines that change it, and `None` for lines that
+/// leave it as it was.
+fn carried_over_per_frame<'a>(
+    lines: &'a [Line],
+    update: impl Fn(&Line) -> Option<Option<f32>> + 'a,
+) -> impl Iterator<Item = Option<f32>> + 'a {
+    lines
+        .iter()
+        .scan(None, move |value, line| {
+            if let Some(new_value) = update(line) {
+                *value = new_value;
+            }
+
+            let frame_count = line.frame_bulk().map_or(0, |bulk| bulk.frame_count.get());
+            Some(iter::repeat(*value).take(frame_count as usize))
+        })
+        .flatten()
+}
+
+/// Returns the yaw for every simulated frame.
+///
+/// Like in the game, frame bulks that don't set the yaw keep the one from before. Strafing frame
+/// bulks use the yaw of their strafe direction, and the ones whose direction has no single yaw,
+/// such as left-right strafing, give [`None`], as do the frames before the yaw is first set. Lines
+/// that turn the view on their own, such as changes and target yaw overrides, also give [`None`]
+/// until the next frame bulk which sets the yaw.
+pub fn resolved_yaw_per_frame(lines: &[Line]) -> impl Iterator<Item = Option<f32>> + '_ {
+    carried_over_per_frame(lines, |line| match line {
+        Line::FrameBulk(bulk) => bulk
+            .auto_actions
+            .movement
+            .as_ref()
+            .map(|_| bulk.yaw().copied()),
+        Line::Change(Change { target, .. }) if !matches!(target, ChangeTarget::Pitch) => Some(None),
+        Line::TargetYawOverride(_) => Some(None),
+        _ => None,
+    })
+}
+
+/// Returns the pitch for every simulated frame.
+///
+/// Like in the game, frame bulks that don't set the pitch keep the one from before. The frames
+/// before the pitch is first set give [`None`], as do the frames after a pitch change line until
+/// the next frame bulk which sets the pitch.
+pub fn resolved_pitch_per_frame(lines: &[Line]) -> impl Iterator<Item = Option<f32>> + '_ {
+    carried_over_per_frame(lines, |line| match line {
+        Line::FrameBulk(bulk) => bulk.pitch.map(Some),
+        Line::Change(Change {
+            target: ChangeTarget::Pitch,
+            ..
+        }) => Some(None),
+        _ => None,
+    })
+}
+
+/// Returns the first frame at which the view has turned by at least `target_deg` since `start`.
+///
+/// As in [`bulk_and_first_frame_idx`], the first simulated frame has the index `1`. The turn adds
+/// up the absolute yaw changes between consecutive frames from [`resolved_yaw_per_frame`], taking
+/// the shorter way around, so turning left and then right back counts both ways. Returns [`None`]
+/// if the script ends or a frame with no known yaw comes up before the turn reaches `target_deg`.
+pub fn frame_after_turning(hltas: &HLTAS, start: usize, target_deg: f32) -> Option<usize> {
+    let mut yaws = resolved_yaw_per_frame(&hltas.lines)
+        .zip(1..)
+        .skip_while(|&(_, frame_idx)| frame_idx < start);
+
+    let (mut prev, frame_idx) = yaws.next()?;
+    if frame_idx != start {
+        return None;
+    }
+
+    if target_deg <= 0. {
+        return Some(start);
+    }
+
+    let mut turned = 0.;
+
+    for (yaw, frame_idx) in yaws {
+        let (prev_yaw, yaw_now) = (prev?, yaw?);
+        turned += ((yaw_now - prev_yaw + 180.).rem_euclid(360.) - 180.).abs();
+        if turned >= target_deg {
+            return Some(frame_idx);
+        }
+        prev = yaw;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn hltas(bulks: &str) -> HLTAS {
+        HLTAS::from_str(&format!("version 1\nframes\n{bulks}")).unwrap()
+    }
+
+    #[test]
+    fn pitch_accessors() {
+        let mut script = hltas(
+            "----------|------|------|0.004|10|5|1\n\
+             s03-------|------|------|0.004|10|-|1",
+        );
+
+        let bulk = script.lines[0].frame_bulk_mut().unwrap();
+        assert_eq!(bulk.pitch(), Some(&5.));
+        *bulk.pitch_mut().unwrap() = -20.;
+        assert_eq!(bulk.pitch, Some(-20.));
+
+        let bulk = script.lines[1].frame_bulk_mut().unwrap();
+        assert_eq!(bulk.pitch(), None);
+        assert_eq!(bulk.pitch_mut(), None);
+    }
+
+    #[test]
+    fn strafe_accessors() {
+        let mut script = hltas(
+            "s03-------|------|------|0.004|10|-|1\n\
+             s15-------|------|------|0.004|20|-|1\n\
+             s06-------|------|------|0.004|3|-|1\n\
+             s07-------|------|------|0.004|4|-|1\n\
+             s20-------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|30|-|1",
+        );
+
+        let dirs = script
+            .frame_bulks()
+            .map(|bulk| bulk.strafe_dir())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dirs,
+            [
+                Some(StrafeDir::Yaw(10.)),
+                Some(StrafeDir::Line { yaw: 20. }),
+                Some(StrafeDir::LeftRight(NonZeroU32::new(3).unwrap())),
+                Some(StrafeDir::RightLeft(NonZeroU32::new(4).unwrap())),
+                Some(StrafeDir::Left),
+                None,
+            ]
+        );
+
+        let types = script
+            .frame_bulks()
+            .map(|bulk| bulk.strafe_type())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                Some(StrafeType::MaxAccel),
+                Some(StrafeType::MaxAngle),
+                Some(StrafeType::MaxAccel),
+                Some(StrafeType::MaxAccel),
+                Some(StrafeType::MaxDeccel),
+                None,
+            ]
+        );
+
+        // Changing the type keeps the yaw.
+        let bulk = script.lines[0].frame_bulk_mut().unwrap();
+        *bulk.strafe_type_mut().unwrap() = StrafeType::MaxAngle;
+        assert_eq!(bulk.strafe_dir(), Some(StrafeDir::Yaw(10.)));
+        assert_eq!(bulk.yaw(), Some(&10.));
+
+        *bulk.strafe_dir_mut().unwrap() = StrafeDir::Point { x: 1., y: 2. };
+        assert_eq!(bulk.strafe_dir(), Some(StrafeDir::Point { x: 1., y: 2. }));
+        assert_eq!(bulk.strafe_type(), Some(StrafeType::MaxAngle));
+
+        let bulk = script.lines[5].frame_bulk_mut().unwrap();
+        assert_eq!(bulk.strafe_type_mut(), None);
+        assert_eq!(bulk.strafe_dir_mut(), None);
+    }
+
+    #[test]
+    fn conflicting_movement() {
+        let mut script = hltas(
+            "s03-------|------|------|0.004|10|-|6\n\
+            ----------|f--b--|------|0.004|-|-|6\n\
+            s06-------|------|------|0.004|10|-|6\n\
+            ----------|------|------|0.004|-|-|6",
+        );
+
+        // Constant yawspeed strafing towards the best direction can't be written in the HLTAS
+        // format, so construct it by hand.
+        script.lines[3]
+            .frame_bulk_mut()
+            .unwrap()
+            .auto_actions
+            .movement = Some(AutoMovement::Strafe(StrafeSettings {
+            type_: StrafeType::ConstYawspeed(10.),
+            dir: StrafeDir::Best,
+        }));
+
+        assert_eq!(find_conflicting_movement(&script), [1, 3]);
+    }
+
+    #[test]
+    fn bulk_edit_script_single_insert() {
+        let a = hltas(
+            "----------|------|------|0.004|10|-|6\n\
+            ----------|------|------|0.004|20|-|6",
+        );
+        let b = hltas(
+            "----------|------|------|0.004|10|-|6\n\
+            ----------|------|------|0.004|15|-|3\n\
+            ----------|------|------|0.004|20|-|6",
+        );
+
+        assert_eq!(bulk_edit_script(&a, &b), [EditOp::Insert { b_line_idx: 1 }]);
+        assert_eq!(bulk_edit_script(&b, &a), [EditOp::Delete { a_line_idx: 1 }]);
+        assert_eq!(bulk_edit_script(&a, &a), []);
+    }
+
+    #[test]
+    fn action_timeline_auto_jump() {
+        let script = hltas(
+            "----------|------|------|0.004|10|-|2\n\
+            ----j-----|------|------|0.004|10|-|3\n\
+            -----d----|------|------|0.004|10|-|1",
+        );
+
+        let timeline = action_timeline(&script, AutoAction::AutoJump);
+        assert_eq!(timeline.len(), total_frame_count(&script));
+        assert_eq!(timeline, [false, false, true, true, true, false]);
+
+        let timeline = action_timeline(&script, AutoAction::DuckTap);
+        assert_eq!(timeline, [false, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn trim_trailing_idle_bulks() {
+        let mut script = hltas(
+            "----------|------|------|0.004|-|-|5\n\
+            s03-------|------|------|0.004|10|-|6\n\
+            ----------|------|------|0.004|-|-|2\n\
+            ----------|f-----|------|0.004|-|-|3\n\
+            ----------|------|------|0.004|-|-|4\n\
+            // comment\n\
+            ----------|------|------|0.004|-|-|7",
+        );
+
+        assert_eq!(trim_trailing_idle(&mut script), 11);
+
+        let expected = hltas(
+            "----------|------|------|0.004|-|-|5\n\
+            s03-------|------|------|0.004|10|-|6\n\
+            ----------|------|------|0.004|-|-|2\n\
+            ----------|f-----|------|0.004|-|-|3\n\
+            // comment",
+        );
+        assert_eq!(script, expected);
+    }
+
+    #[test]
+    fn frame_count_histogram_counts_bulks() {
+        let hltas = hltas(
+            "----------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|-|-|5\n\
+             ----------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|-|-|5\n\
+             ----------|------|------|0.004|-|-|20",
+        );
+
+        let histogram = frame_count_histogram(&hltas);
+        assert_eq!(histogram, BTreeMap::from([(1, 3), (5, 2), (20, 1)]));
+    }
+
+    #[test]
+    fn clamp_turn_rate_smooths_sharp_turn() {
+        let mut hltas = hltas(
+            "----------|------|------|0.004|0|-|5\n\
+             ----------|------|------|0.004|90|-|5",
+        );
+
+        let modified = clamp_turn_rate(&mut hltas, 20., 0, 10);
+        assert!(modified > 0);
+
+        let yaws = hltas
+            .frame_bulks()
+            .flat_map(|bulk| {
+                iter::repeat(*bulk.yaw().unwrap()).take(bulk.frame_count.get() as usize)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(yaws.len(), 10);
+        assert_eq!(yaws[0], 0.);
+        assert_eq!(yaws[9], 90.);
+        for (prev, yaw) in yaws.iter().tuple_windows() {
+            assert!((yaw - prev).abs() <= 20.0001, "{prev} -> {yaw}");
+        }
+    }
+
+    #[test]
+    fn line_start_time_matches_first_frame() {
+        let script = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             ----------|------|------|0.004|-|-|5\n\
+             ----------|------|------|0.004|-|-|20",
+        );
+
+        for (line_idx, first_frame_idx) in line_first_frame_idx(&script).enumerate() {
+            let time = line_start_time(&script, line_idx).unwrap();
+            // Frame indices start at 1 due to the initial frame.
+            let expected = (first_frame_idx - 1) as f64 * 0.004;
+            assert!((time - expected).abs() < 1e-9, "{time} != {expected}");
+        }
+
+        assert!((line_start_time(&script, 3).unwrap() - 35. * 0.004).abs() < 1e-9);
+        assert!(line_start_time(&script, 4).is_err());
+
+        let zero = hltas("----------|------|------|0|-|-|10\n----------|------|------|0.004|-|-|5");
+        assert!(line_start_time(&zero, 1).is_err());
+    }
+
+    #[test]
+    fn contradictory_strafe() {
+        let hltas = hltas(
+            "s00-------|------|------|0.004|-|-|1\n\
+             s00-------|--r---|------|0.004|-|-|1\n\
+             s01-------|-l----|------|0.004|-|-|1\n\
+             s01-------|--r---|------|0.004|-|-|1\n\
+             ----------|--r---|------|0.004|-|-|1",
+        );
+
+        assert_eq!(find_contradictory_strafe(&hltas), vec![1, 2]);
+    }
+
+    #[test]
+    fn strafe_fraction_half() {
+        let script = hltas(
+            "s03-------|------|------|0.004|10|-|6\n\
+             ----------|------|------|0.004|-|-|4\n\
+             s06-------|------|------|0.004|10|-|4\n\
+             ----------|------|------|0.004|-|-|6",
+        );
+
+        assert_eq!(strafe_fraction(&script), 0.5);
+        assert_eq!(strafe_fraction(&hltas("")), 0.);
+    }
+
+    #[test]
+    fn strafe_to_set_yaw_line() {
+        let mut hltas = hltas(
+            "s05-------|------|------|0.004|45|-|3\n\
+             s06-------|------|------|0.004|10|-|4",
+        );
+
+        assert_eq!(strafe_to_set_yaw(&mut hltas), 1);
+
+        let bulks = hltas.frame_bulks().collect::<Vec<_>>();
+        assert_eq!(
+            bulks[0].auto_actions.movement,
+            Some(AutoMovement::SetYaw(45.))
+        );
+        assert_eq!(bulks[0].frame_count.get(), 3);
+        assert!(matches!(
+            bulks[1].auto_actions.movement,
+            Some(AutoMovement::Strafe(StrafeSettings {
+                dir: StrafeDir::LeftRight(_),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn split_at_map_command() {
+        let hltas = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             ----------|------|------|0.004|-|-|1|map c1a0\n\
+             ----------|------|------|0.004|-|-|5\n\
+             ----------|------|------|0.004|-|-|3",
+        );
+
+        let (before, after) = split_at_command_matching(&hltas, "map").unwrap();
+        assert_eq!(before.properties, hltas.properties);
+        assert_eq!(after.properties, hltas.properties);
+        assert_eq!(before.lines, hltas.lines[..2]);
+        assert_eq!(after.lines, hltas.lines[2..]);
+
+        assert!(split_at_command_matching(&hltas, "changelevel").is_none());
+    }
+
+    #[test]
+    fn bulk_summaries_mixed() {
+        let hltas = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             seed 1234\n\
+             s03-------|------|------|0.001|90|5|6\n\
+             ----------|------|------|0.004|45|-|4",
+        );
+
+        let summaries = bulk_summaries(&hltas);
+        assert_eq!(
+            summaries,
+            vec![
+                BulkSummary {
+                    line_idx: 0,
+                    first_frame: 1,
+                    frame_count: 10,
+                    frame_time: "0.004".to_owned(),
+                    movement_kind: MovementKind::Manual,
+                    yaw: None,
+                    pitch: None,
+                },
+                BulkSummary {
+                    line_idx: 2,
+                    first_frame: 11,
+                    frame_count: 6,
+                    frame_time: "0.001".to_owned(),
+                    movement_kind: MovementKind::Strafe,
+                    yaw: Some(90.),
+                    pitch: Some(5.),
+                },
+                BulkSummary {
+                    line_idx: 3,
+                    first_frame: 17,
+                    frame_count: 4,
+                    frame_time: "0.004".to_owned(),
+                    movement_kind: MovementKind::SetYaw,
+                    yaw: Some(45.),
+                    pitch: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn yaw_time_series_per_frame() {
+        let hltas = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             s03-------|------|------|0.001|90|-|6\n\
+             ----------|------|------|0.010|45|-|4",
+        );
+
+        let series = yaw_time_series(&hltas);
+        assert_eq!(series.len(), total_frame_count(&hltas));
+        assert!(series.iter().tuple_windows().all(|(a, b)| a.0 < b.0));
+
+        assert_eq!(series[0].1, None);
+        assert_eq!(series[10].1, Some(90.));
+        assert_eq!(series[19].1, Some(45.));
+        assert!((series[19].0 - 0.086).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quantize_yaws_to_degrees() {
+        let mut hltas = hltas(
+            "s03-------|------|------|0.004|10.4|-|6\n\
+             ----------|------|------|0.004|-|-|4\n\
+             ----------|------|------|0.004|45|-|4\n\
+             s05-------|------|------|0.004|-89.6|-|4",
+        );
+
+        assert!(quantize_yaws(&mut hltas, 0.).is_err());
+        assert!(quantize_yaws(&mut hltas, f32::NAN).is_err());
+        assert_eq!(quantize_yaws(&mut hltas, 1.).unwrap(), 2);
+
+        let yaws = hltas
+            .frame_bulks()
+            .map(|bulk| bulk.yaw().copied())
+            .collect::<Vec<_>>();
+        assert_eq!(yaws, [Some(10.), None, Some(45.), Some(-90.)]);
+    }
+
+    #[test]
+    fn frame_hud_lines_per_frame() {
+        let hltas = hltas(
+            "----------|------|------|0.01|-|-|2\n\
+             s03-------|------|------|0.004|10|-|2",
+        );
+
+        assert_eq!(
+            frame_hud_lines(&hltas),
+            [
+                "Frame: 1  Time: 0.000  Strafe: disabled",
+                "Frame: 2  Time: 0.010  Strafe: disabled",
+                "Frame: 3  Time: 0.020  Strafe: speed increasing",
+                "Frame: 4  Time: 0.024  Strafe: speed increasing",
+            ]
+        );
+    }
+
+    #[test]
+    fn import_and_coalesce_fragmented() {
+        let raw = "version 1\nframes\n\
+                   ----------|------|------|0.004|-|-|1\n\
+                   ----------|------|------|0.004|-|-|1\n\
+                   ----------|------|------|0.004|-|-|1\n\
+                   s03-------|------|------|0.004|10|-|1\n\
+                   s03-------|------|------|0.004|10|-|1\n\
+                   s03-------|------|------|0.004|10|-|1|+attack\n\
+                   s03-------|------|------|0.004|10|-|1";
+
+        let original = HLTAS::from_str(raw).unwrap();
+        let imported = import_and_coalesce(raw).unwrap();
+        assert_eq!(imported.frame_bulks().count(), 4);
+
+        // Every frame should still do the same thing.
+        let frames = |hltas: &HLTAS| {
+            hltas
+                .frame_bulks()
+                .flat_map(|bulk| {
+                    let mut bulk = bulk.clone();
+                    let count = bulk.frame_count.get() as usize;
+                    bulk.frame_count = NonZeroU32::new(1).unwrap();
+                    iter::repeat(bulk).take(count)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(frames(&imported), frames(&original));
+
+        assert!(import_and_coalesce("not a script").is_err());
+    }
+
+    #[test]
+    fn distinct_frame_times_mixed() {
+        let hltas = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             ----------|------|------|0.001|-|-|5\n\
+             ----------|------|------|0.0040|-|-|5\n\
+             ----------|------|------|0.010000001|-|-|3\n\
+             ----------|------|------|0.001|-|-|1",
+        );
+
+        assert_eq!(distinct_frame_times(&hltas), [0.001, 0.004, 0.010000001]);
+    }
+
+    #[test]
+    fn shift_right_left_phase() {
+        let mut script = hltas(
+            "----------|------|------|0.004|-|-|5\n\
+             s07-------|------|------|0.004|10|-|40|echo start",
+        );
+
+        assert!(!shift_left_right_phase(&mut script, 0, 3));
+        assert!(!shift_left_right_phase(&mut script, 1, 10));
+        assert!(shift_left_right_phase(&mut script, 1, 3));
+
+        let expected = hltas(
+            "----------|------|------|0.004|-|-|5\n\
+             s01-------|------|------|0.004|-|-|7|echo start\n\
+             s06-------|------|------|0.004|10|-|33",
+        );
+        assert_eq!(script.lines, expected.lines);
+    }
+
+    #[test]
+    fn canonicalize_cosmetic_differences() {
+        let mut a = hltas(
+            "s03-------|------|------|0.0040|370|-|5|echo hi\n\
+             s03-------|------|------|0.004|10|-|5\n\
+             ----------|------|------|1e-2|-|-|3\n\
+             s06-------|------|------|0.004|10|-|4\n\
+             s06-------|------|------|0.004|10|-|4",
+        );
+        let mut b = hltas(
+            "s03-------|------|------|0.004|-350|-|10|echo hi\n\
+             ----------|------|------|0.01|-|-|1\n\
+             ----------|------|------|0.010|-|-|2\n\
+             s06-------|------|------|0.004|10|-|4\n\
+             s06-------|------|------|0.004|10|-|4",
+        );
+
+        canonicalize(&mut a);
+        canonicalize(&mut b);
+
+        let mut a_bytes = Vec::new();
+        a.to_writer(&mut a_bytes).unwrap();
+        let mut b_bytes = Vec::new();
+        b.to_writer(&mut b_bytes).unwrap();
+        assert_eq!(a_bytes, b_bytes);
+
+        let expected = hltas(
+            "s03-------|------|------|0.004|10|-|10|echo hi\n\
+             ----------|------|------|0.01|-|-|3\n\
+             s06-------|------|------|0.004|10|-|4\n\
+             s06-------|------|------|0.004|10|-|4",
+        );
+        assert_eq!(a.lines, expected.lines);
+    }
+
+    #[test]
+    fn frames_shifted_by_count_change() {
+        let before = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             ----------|------|------|0.004|-|-|5\n\
+             ----------|------|------|0.004|-|-|20",
+        );
+
+        let mut after = before.clone();
+        after.lines[0].frame_bulk_mut().unwrap().pitch = Some(10.);
+        assert_eq!(frames_shifted_by_edit(&before, &after), None);
+
+        after.lines[1].frame_bulk_mut().unwrap().frame_count = NonZeroU32::new(8).unwrap();
+        assert_eq!(frames_shifted_by_edit(&before, &after), Some(11));
+
+        let mut after = before.clone();
+        after.lines.pop();
+        assert_eq!(frames_shifted_by_edit(&before, &after), Some(16));
+        assert_eq!(frames_shifted_by_edit(&after, &before), Some(16));
+    }
+
+    #[test]
+    fn longest_constant_movement_run_set_yaw() {
+        let script = hltas(
+            "s03-------|------|------|0.004|10|-|5\n\
+             ----------|------|------|0.004|90|-|10\n\
+             ----------|------|------|0.004|90|-|15\n\
+             ----------|------|------|0.004|45|-|20\n\
+             s03-------|------|------|0.004|10|-|8\n\
+             s03-------|------|------|0.004|10|-|8",
+        );
+        assert_eq!(longest_constant_movement_run(&script), Some((6, 30)));
+
+        assert_eq!(longest_constant_movement_run(&hltas("")), None);
+    }
+
+    #[test]
+    fn frame_invariants_hold_after_edits() {
+        let mut script = hltas(
+            "s07-------|------|------|0.004|10|-|40\n\
+             seed 1234\n\
+             ----------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|-|-|1",
+        );
+        debug_assert_frame_invariants(&script);
+
+        // A frame bulk with zero frames can't be constructed, so only check that the invariants
+        // hold after edits which change the frame bulks.
+        assert!(shift_left_right_phase(&mut script, 0, 3));
+        coalesce_single_frame_bulks(&mut script);
+        canonicalize(&mut script);
+        trim_trailing_idle(&mut script);
+        debug_assert_frame_invariants(&script);
+    }
+
+    #[test]
+    fn bridge_yaw_ramps_between_headings() {
+        let mut script = hltas(
+            "----------|------|------|0.004|350|-|10\n\
+             ----------|------|------|0.004|20|-|10",
+        );
+
+        bridge_yaw(&mut script, 11, 5).unwrap();
+        assert_eq!(total_frame_count(&script), 25);
+
+        let yaws = script
+            .frame_bulks()
+            .map(|bulk| *bulk.yaw().unwrap())
+            .collect::<Vec<_>>();
+        let expected = [350., 355., 0., 5., 10., 15., 20.];
+        assert_eq!(yaws.len(), expected.len());
+        for (yaw, expected) in yaws.into_iter().zip(expected) {
+            assert!((yaw - expected).abs() < 1e-3, "{yaw} != {expected}");
+        }
+
+        // Splits the frame bulk when bridging in the middle of it.
+        bridge_yaw(&mut script, 5, 1).unwrap();
+        let frame_counts = script
+            .frame_bulks()
+            .map(|bulk| bulk.frame_count.get())
+            .collect::<Vec<_>>();
+        assert_eq!(frame_counts, [4, 1, 6, 1, 1, 1, 1, 1, 10]);
+
+        assert!(bridge_yaw(&mut script, 1, 1).is_err());
+        assert!(bridge_yaw(&mut hltas("----------|------|------|0.004|-|-|10"), 5, 1).is_err());
+    }
+
+    #[test]
+    fn split_at_turn_reversal() {
+        let script = hltas(
+            "----------|------|------|0.004|30|-|1\n\
+             ----------|------|------|0.004|20|-|1\n\
+             ----------|------|------|0.004|10|-|1\n\
+             ----------|------|------|0.004|0|-|2\n\
+             ----------|------|------|0.004|10|-|1\n\
+             ----------|------|------|0.004|20|-|1",
+        );
+        assert_eq!(split_at_turn_reversals(&script), [(1, 5), (6, 7)]);
+
+        // Turning through 0 is not a reversal.
+        let script = hltas(
+            "----------|------|------|0.004|20|-|1\n\
+             ----------|------|------|0.004|350|-|1\n\
+             ----------|------|------|0.004|340|-|1",
+        );
+        assert_eq!(split_at_turn_reversals(&script), [(1, 3)]);
+    }
+
+    #[test]
+    fn bulk_rle_matches_expansion() {
+        let hltas = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             seed 1234\n\
+             ----------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|-|-|5",
+        );
+
+        let rle = bulk_rle(&hltas.lines);
+        assert_eq!(rle, [(0, 10), (2, 1), (3, 5)]);
+        assert_eq!(
+            rle.iter().map(|&(_, run_length)| run_length).sum::<usize>(),
+            total_frame_count(&hltas)
+        );
+
+        let expanded = rle
+            .iter()
+            .flat_map(|&(line_idx, run_length)| iter::repeat(line_idx).take(run_length))
+            .collect::<Vec<_>>();
+        let expected = (0..total_frame_count(&hltas))
+            .map(|frame_idx| {
+                line_idx_and_repeat_at_frame(&hltas.lines, frame_idx)
+                    .unwrap()
+                    .0
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(expanded, expected);
+    }
+
+    #[test]
+    fn find_frametime_changes_within_strafe_run() {
+        let hltas = hltas(
+            "s03-------|------|------|0.004|10|-|10\n\
+             s03-------|------|------|0.0040|10|-|5\n\
+             // comment\n\
+             s03-------|------|------|0.001|10|-|5\n\
+             s03-------|------|------|0.001|10|-|5\n\
+             ----------|------|------|0.004|-|-|5\n\
+             s03-------|------|------|0.010|10|-|5",
+        );
+        assert_eq!(find_frametime_changes_within_strafe(&hltas), [3]);
+    }
+
+    #[test]
+    fn frame_count_alignment_same_structure() {
+        let from = hltas(
+            "s03-------|------|------|0.004|10|-|10\n\
+             // comment\n\
+             ----------|------|------|0.004|-|-|5\n\
+             s06-------|------|------|0.004|2|-|20",
+        );
+        let to = hltas(
+            "----------|------|------|0.004|-|-|12\n\
+             ----------|------|------|0.004|-|-|5\n\
+             ----------|------|------|0.004|-|-|17",
+        );
+        assert_eq!(
+            frame_count_alignment(&from, &to),
+            Some(vec![(0, 12), (2, 17)])
+        );
+
+        let shorter = hltas("----------|------|------|0.004|-|-|12");
+        assert_eq!(frame_count_alignment(&from, &shorter), None);
+    }
+
+    #[test]
+    fn merge_commands_into_movement() {
+        let mut movement = hltas(
+            "s03-------|------|------|0.004|10|-|10\n\
+             ----------|------|------|0.004|90|-|10|echo hi",
+        );
+        let commands = hltas(
+            "----------|------|------|0.004|-|-|4\n\
+             ----------|------|------|0.004|-|-|7|weapon_crowbar\n\
+             // comment\n\
+             ----------|------|------|0.004|-|-|3|+attack",
+        );
+
+        merge_commands_by_frame(&mut movement, &commands).unwrap();
+        assert_eq!(total_frame_count(&movement), 20);
+
+        let bulks = movement
+            .frame_bulks()
+            .map(|bulk| (bulk.frame_count.get(), bulk.console_command.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bulks,
+            [
+                (4, None),
+                (6, Some("weapon_crowbar")),
+                (1, Some("echo hi")),
+                (9, Some("+attack"))
+            ]
+        );
+
+        let past_end = hltas(
+            "----------|------|------|0.004|-|-|20\n\
+             ----------|------|------|0.004|-|-|1|+use",
+        );
+        let before = movement.clone();
+        assert!(merge_commands_by_frame(&mut movement, &past_end).is_err());
+        assert_eq!(movement, before);
+    }
+
+    #[test]
+    fn pad_to_target_frame_count() {
+        let mut script = hltas(
+            "s03-------|------|------|0.004|10|-|60\n\
+             ----------|------|------|0.004|90|-|40",
+        );
+
+        assert_eq!(pad_to_frame_count(&mut script, 150, "0.010").unwrap(), 50);
+        assert_eq!(total_frame_count(&script), 150);
+
+        let padding = script.lines.last().unwrap().frame_bulk().unwrap();
+        assert_eq!(padding.frame_count.get(), 50);
+        assert_eq!(padding.frame_time, "0.010");
+        assert!(is_idle(padding));
+
+        assert_eq!(pad_to_frame_count(&mut script, 150, "0.010").unwrap(), 0);
+        assert_eq!(script.lines.len(), 3);
+        assert!(pad_to_frame_count(&mut script, 149, "0.010").is_err());
+    }
+
+    #[test]
+    fn lines_after_last_frame_bulk() {
+        let script = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             // comment\n\
+             ----------|------|------|0.004|-|-|10\n\
+             seed 1234\n\
+             // trailing comment",
+        );
+        assert_eq!(lines_after_last_frame(&script), [3, 4]);
+
+        let script = hltas(
+            "seed 1234\n\
+             // comment\n\
+             ----------|------|------|0.004|-|-|10",
+        );
+        assert!(lines_after_last_frame(&script).is_empty());
+    }
+
+    #[test]
+    fn split_bulk_at_given_frame() {
+        let mut script = hltas(
+            "s03-------|------|------|0.004|90|-|10|echo hi\n\
+             // comment\n\
+             ----------|------|------|0.004|-|-|5",
+        );
+
+        assert_eq!(split_bulk_at_frame(&mut script, 1), None);
+        assert_eq!(split_bulk_at_frame(&mut script, 11), None);
+        assert_eq!(split_bulk_at_frame(&mut script, 16), None);
+
+        let before = script.clone();
+        assert_eq!(split_bulk_at_frame(&mut script, 4), Some(1));
+        assert_eq!(total_frame_count(&script), total_frame_count(&before));
+
+        let first = script.lines[0].frame_bulk().unwrap();
+        let second = script.lines[1].frame_bulk().unwrap();
+        assert_eq!(first.frame_count.get(), 3);
+        assert_eq!(second.frame_count.get(), 7);
+        assert_eq!(first.auto_actions, second.auto_actions);
+        assert_eq!(first.yaw(), second.yaw());
+        assert_eq!(first.console_command.as_deref(), Some("echo hi"));
+        assert_eq!(second.console_command, None);
+
+        assert_eq!(split_bulk_at_frame(&mut script, 14), Some(4));
+        let counts = script
+            .frame_bulks()
+            .map(|bulk| bulk.frame_count.get())
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [3, 7, 3, 2]);
+    }
+
+    #[test]
+    fn bulk_time_fractions_sum_to_one() {
+        let script = hltas(
+            "----------|------|------|0.001|-|-|100\n\
+             // comment\n\
+             ----------|------|------|0.010|-|-|30",
+        );
+
+        let fractions = bulk_time_fractions(&script);
+        assert_eq!(fractions.len(), 2);
+        assert_eq!(fractions[0].0, 0);
+        assert_eq!(fractions[1].0, 2);
+        assert!((fractions[0].1 - 0.25).abs() < 1e-9);
+        assert!((fractions[1].1 - 0.75).abs() < 1e-9);
+
+        let sum = fractions.iter().map(|(_, fraction)| fraction).sum::<f64>();
+        assert!((sum - 1.).abs() < 1e-9);
+
+        assert_eq!(bulk_time_fractions(&hltas("")), []);
+    }
+
+    #[test]
+    fn extreme_yaw_bulk_wraps_around() {
+        let script = hltas(
+            "----------|------|------|0.004|90|-|10\n\
+             ----------|------|------|0.004|-|-|10\n\
+             ----------|------|------|0.004|-170|-|10\n\
+             ----------|------|------|0.004|350|-|10\n\
+             s03-------|------|------|0.004|185|-|10",
+        );
+
+        // 185 is 175 away from 0, farther than -170, while 350 is only 10 away.
+        assert_eq!(extreme_yaw_bulk(&script), Some((4, 185.)));
+
+        let script = hltas(
+            "----------|------|------|0.004|-|-|10\n\
+             ----------|------|------|0.004|-|-|10",
+        );
+        assert_eq!(extreme_yaw_bulk(&script), None);
+    }
+
+    #[test]
+    fn delete_frame_range_shrinks_and_removes_bulks() {
+        let mut script = hltas(
+            "----------|------|------|0.004|-|-|5\n\
+             // comment\n\
+             ----------|------|------|0.004|-|-|3\n\
+             ----------|------|------|0.004|-|-|4",
+        );
+
+        // Frames 4 and 5 of the first bulk, all of the second one and frame 9 of the third one.
+        assert_eq!(delete_frame_range(&mut script, 4, 10), 6);
+        assert_eq!(script.lines.len(), 3);
+        assert!(matches!(script.lines[1], Line::Comment(_)));
+        let counts = script
+            .frame_bulks()
+            .map(|bulk| bulk.frame_count.get())
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [3, 3]);
+
+        // A range in the middle of a bulk shrinks it.
+        assert_eq!(delete_frame_range(&mut script, 5, 6), 1);
+        assert_eq!(total_frame_count(&script), 5);
+
+        // An empty range or one past the end removes nothing.
+        assert_eq!(delete_frame_range(&mut script, 3, 3), 0);
+        assert_eq!(delete_frame_range(&mut script, 100, 200), 0);
+        assert_eq!(total_frame_count(&script), 5);
+    }
+
+    #[test]
+    fn merging_huge_bulks_does_not_overflow() {
+        assert_eq!(checked_frame_count_sum(2, 3), NonZeroU32::new(5));
+        assert_eq!(checked_frame_count_sum(u32::MAX, 1), None);
+        assert_eq!(checked_frame_count_sum(0, 0), None);
+
+        let count = u32::MAX - 5;
+        let mut script = hltas(&format!(
+            "----------|------|------|0.004|-|-|{count}\n\
+             ----------|------|------|0.004|-|-|{count}"
+        ));
+        let total = total_frame_count(&script);
+
+        canonicalize(&mut script);
+        let counts = script
+            .frame_bulks()
+            .map(|bulk| bulk.frame_count.get())
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [u32::MAX, count - 5]);
+        assert_eq!(total_frame_count(&script), total);
+
+        let mut script = hltas(&format!(
+            "----------|------|------|0.004|-|-|{}\n\
+             ----------|------|------|0.004|-|-|1",
+            u32::MAX
+        ));
+        assert_eq!(coalesce_single_frame_bulks(&mut script), 0);
+        assert_eq!(script.lines.len(), 2);
+
+        let next = script.lines[1].clone();
+        assert!(join_lines(&mut script.lines[0], &next).is_err());
+        assert_eq!(
+            script.lines[0].frame_bulk().unwrap().frame_count.get(),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn resolved_yaw_and_pitch_carry_over() {
+        let mut script = hltas(
+            "----------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|90|10|2\n\
+             ----------|------|------|0.004|-|-|1\n\
+             s03-------|------|------|0.004|45|-|1\n\
+             s06-------|------|------|0.004|4|-|2\n\
+             ----------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|30|-20|1\n\
+             ----------|------|------|0.004|-|-|1",
+        );
+        script.lines.insert(
+            7,
+            Line::Change(Change {
+                target: ChangeTarget::Yaw,
+                final_value: 0.,
+                over: 0.1,
+            }),
+        );
+
+        assert_eq!(
+            resolved_yaw_per_frame(&script.lines).collect::<Vec<_>>(),
+            [
+                None,
+                Some(90.),
+                Some(90.),
+                Some(90.),
+                Some(45.),
+                None,
+                None,
+                None,
+                Some(30.),
+                None,
+            ]
+        );
+        assert_eq!(
+            resolved_pitch_per_frame(&script.lines).collect::<Vec<_>>(),
+            [
+                None,
+                Some(10.),
+                Some(10.),
+                Some(10.),
+                Some(10.),
+                Some(10.),
+                Some(10.),
+                Some(10.),
+                Some(-20.),
+                Some(-20.),
+            ]
+        );
+    }
+
+    #[test]
+    fn frame_after_turning_steady_turn() {
+        let bulks = (0..20)
+            .map(|i| format!("----------|------|------|0.004|{}|-|1", i * 10))
+            .join("\n");
+        let script = hltas(&bulks);
+
+        // Frame 1 has the yaw 0, so 90 degrees is reached on frame 10.
+        assert_eq!(frame_after_turning(&script, 1, 90.), Some(10));
+        assert_eq!(frame_after_turning(&script, 3, 90.), Some(12));
+        assert_eq!(frame_after_turning(&script, 5, 0.), Some(5));
+        assert_eq!(frame_after_turning(&script, 1, 1000.), None);
+        assert_eq!(frame_after_turning(&script, 0, 90.), None);
+
+        // Turning across 0 takes the short way around.
+        let script = hltas(
+            "----------|------|------|0.004|350|-|1\n\
+             ----------|------|------|0.004|10|-|1\n\
+             ----------|------|------|0.004|-10|-|1",
+        );
+        assert_eq!(frame_after_turning(&script, 1, 40.), Some(3));
+    }
+
+    #[test]
+    fn merges_keep_comments() {
+        let mut script = hltas(
+            "// first\n\
+             ----------|------|------|0.004|-|-|5\n\
+             // second\n\
+             // third\n\
+             ----------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|-|-|2",
+        );
+
+        assert_eq!(comments_around(&script.lines, 1), 0..1);
+        assert_eq!(comments_around(&script.lines, 4), 2..4);
+        assert_eq!(comments_around(&script.lines, 5), 5..5);
+
+        assert_eq!(coalesce_single_frame_bulks(&mut script), 1);
+        assert_eq!(comments_around(&script.lines, 3), 0..3);
+        assert!(matches!(&script.lines[1], Line::Comment(c) if c.contains("second")));
+
+        canonicalize(&mut script);
+        assert_eq!(script.lines.len(), 4);
+        assert_eq!(script.frame_bulks().next().unwrap().frame_count.get(), 8);
+
+        // Splitting keeps the comments with the first half.
+        assert_eq!(split_bulk_at_frame(&mut script, 3), Some(4));
+        assert_eq!(comments_around(&script.lines, 3), 0..3);
+        assert_eq!(comments_around(&script.lines, 4), 4..4);
+    }
+
+    #[test]
+    fn clamp_turn_rate_skips_bulks_without_yaw() {
+        let mut hltas = hltas(
+            "----------|------|------|0.004|0|-|5\n\
+             ----------|------|------|0.004|-|-|1\n\
+             ----------|------|------|0.004|90|-|5",
+        );
+
+        assert!(clamp_turn_rate(&mut hltas, 10., 0, 100) > 0);
+        assert_eq!(total_frame_count(&hltas), 11);
+
+        let yaws = hltas
+            .frame_bulks()
+            .flat_map(|bulk| {
+                iter::repeat(bulk.yaw().copied()).take(bulk.frame_count.get() as usize)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(yaws[5], None);
+        let yaws = yaws.into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(yaws.len(), 10);
+        assert_eq!(yaws[0], 0.);
+        assert_eq!(yaws[9], 90.);
+        for (prev, yaw) in yaws.iter().tuple_windows() {
+            assert!((yaw - prev).abs() <= 10.0001, "{prev} -> {yaw}");
+        }
+    }
+
+    #[test]
+    fn clamp_turn_rate_range_in_the_middle() {
+        let mut hltas = hltas(
+            "----------|------|------|0.004|0|-|5|echo first\n\
+             ----------|------|------|0.004|0|-|5|echo second\n\
+             ----------|------|------|0.004|90|-|5",
+        );
+
+        // Frames 6 to 15, the last two frame bulks.
+        assert!(clamp_turn_rate(&mut hltas, 20., 6, 16) > 0);
+        assert_eq!(total_frame_count(&hltas), 15);
+
+        let first = hltas.lines[0].frame_bulk().unwrap();
+        assert_eq!(first.frame_count.get(), 5);
+        assert_eq!(first.console_command.as_deref(), Some("echo first"));
+
+        let commands = hltas
+            .frame_bulks()
+            .filter_map(|bulk| bulk.console_command.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(commands, ["echo first", "echo second"]);
+
+        let yaws = hltas
+            .frame_bulks()
+            .flat_map(|bulk| {
+                iter::repeat(*bulk.yaw().unwrap()).take(bulk.frame_count.get() as usize)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(yaws[..6], [0.; 6]);
+        assert_eq!(yaws[14], 90.);
+        for (prev, yaw) in yaws.iter().tuple_windows() {
+            assert!((yaw - prev).abs() <= 20.0001, "{prev} -> {yaw}");
+        }
+    }
 }
@@ -132,7 +132,7 @@ impl Module for TasStudio {
     }
 }
 
-mod editor;
+pub mod editor;
 use editor::Editor;
 
 mod remote;